@@ -0,0 +1,230 @@
+//! `--best-n`: each player's plays worth the most rks, the "Best N" the game's
+//! rating is built from.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+use xlsxwriter::Format;
+
+use crate::config::Config;
+use crate::filter::RecordFilter;
+use crate::input::SaveFile;
+use crate::output::{create_workbook, write_atomically, OutputFormat};
+use crate::run_summary::RunSummary;
+use crate::{claim_output_file, player_file_stem, records_by_player, ProcessedRecord, ProcessedSave};
+
+pub const BEST_DIR: &str = "best";
+
+/// Plays counted towards a player's rks besides the phi slots.
+pub const BEST_SLOTS: usize = 27;
+
+/// All Perfect plays counted on top of the best ones.
+pub const PHI_SLOTS: usize = 3;
+
+#[derive(Serialize)]
+struct BestPlay<'a> {
+    rank: usize,
+    song: &'a str,
+    difficulty: &'a str,
+    acc: f64,
+    score: i32,
+    constant: f64,
+    play_rks: f64,
+}
+
+impl BestPlay<'_> {
+    const HEADER: [&'static str; 7] = ["rank", "song", "difficulty", "acc", "score", "constant", "play_rks"];
+}
+
+/// Each chart's best play, from the most rks to the least. Ties go to the
+/// higher acc, then to the song name.
+pub fn ranked_plays(records: Vec<ProcessedRecord>) -> Vec<ProcessedRecord> {
+    let mut by_chart: HashMap<(String, String), ProcessedRecord> = HashMap::new();
+    for record in records {
+        let Some(play_rks) = record.play_rks else {
+            continue;
+        };
+        let key = (record.song_id.clone(), record.difficulty.clone());
+        match by_chart.get(&key) {
+            Some(best) if (best.play_rks, best.acc) >= (Some(play_rks), record.acc) => {}
+            _ => {
+                by_chart.insert(key, record);
+            }
+        }
+    }
+    let mut plays: Vec<_> = by_chart.into_values().collect();
+    plays.sort_by(|a, b| {
+        b.play_rks
+            .unwrap_or_default()
+            .total_cmp(&a.play_rks.unwrap_or_default())
+            .then_with(|| b.acc.total_cmp(&a.acc))
+            .then_with(|| a.song_name.cmp(&b.song_name))
+            .then_with(|| a.difficulty.cmp(&b.difficulty))
+    });
+    plays
+}
+
+fn best_plays(records: Vec<ProcessedRecord>, n: usize) -> Vec<ProcessedRecord> {
+    let mut plays = ranked_plays(records);
+    plays.truncate(n);
+    plays
+}
+
+/// A player's rks the way the game computes it: the best 27 plays and the
+/// 3 All Perfect (100% acc) plays on the highest constants, which may repeat
+/// charts from the best 27, averaged over those 30 slots. Empty slots count
+/// as 0.
+pub fn computed_rks(records: Vec<ProcessedRecord>) -> f64 {
+    let plays = ranked_plays(records);
+    let best: f64 = plays.iter().take(BEST_SLOTS).filter_map(|play| play.play_rks).sum();
+    let mut phi: Vec<_> = plays
+        .iter()
+        .filter(|play| play.acc == 100.0)
+        .filter_map(|play| play.chart_constant)
+        .collect();
+    phi.sort_by(|a, b| b.total_cmp(a));
+    let phi: f64 = phi.iter().take(PHI_SLOTS).sum();
+    (best + phi) / (BEST_SLOTS + PHI_SLOTS) as f64
+}
+
+fn rows(plays: &[ProcessedRecord]) -> impl Iterator<Item = BestPlay<'_>> {
+    plays.iter().enumerate().map(|(index, play)| BestPlay {
+        rank: index + 1,
+        song: &play.song_name,
+        difficulty: &play.difficulty,
+        acc: play.acc,
+        score: play.score,
+        constant: play.chart_constant.unwrap_or_default(),
+        play_rks: play.play_rks.unwrap_or_default(),
+    })
+}
+
+fn write_csv(plays: &[ProcessedRecord], path: &Path) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows(plays) {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_xlsx(plays: &[ProcessedRecord], path: &Path) -> Result<()> {
+    let workbook = create_workbook(path)?;
+    let mut sheet = workbook.add_worksheet(Some("Best"))?;
+    let mut header = Format::new();
+    header.set_bold();
+    let mut acc = Format::new();
+    acc.set_num_format("0.00");
+    let mut rks = Format::new();
+    rks.set_num_format("0.0000");
+    for (col, name) in BestPlay::HEADER.into_iter().enumerate() {
+        sheet.write_string(0, col as u16, name, Some(&header))?;
+    }
+    for (row, play) in rows(plays).enumerate() {
+        let row = row as u32 + 1;
+        sheet.write_number(row, 0, play.rank as f64, None)?;
+        sheet.write_string(row, 1, play.song, None)?;
+        sheet.write_string(row, 2, play.difficulty, None)?;
+        sheet.write_number(row, 3, play.acc, Some(&acc))?;
+        sheet.write_number(row, 4, f64::from(play.score), None)?;
+        sheet.write_number(row, 5, play.constant, None)?;
+        sheet.write_number(row, 6, play.play_rks, Some(&rks))?;
+    }
+    sheet.set_column(1, 1, 40.0, None)?;
+    sheet.freeze_panes(1, 0);
+    workbook.close()?;
+    Ok(())
+}
+
+/// Writes `best/{player}.csv`, and `.xlsx` too when xlsx is among the
+/// formats, for every player with at least one play of a chart with a known
+/// constant. Returns the files written, or that would be with `dry_run`.
+pub fn write_best_files(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+    config: &Config,
+    n: usize,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let best_dir = config.output_dir().join(BEST_DIR);
+    let mut written = Vec::new();
+    for (player, records) in records_by_player(saves, filter, aliases) {
+        let plays = best_plays(records, n);
+        if plays.is_empty() {
+            continue;
+        }
+        let stem = player_file_stem(&player);
+        let mut paths = vec![best_dir.join(format!("{}.csv", stem))];
+        if config.formats.contains(&OutputFormat::Xlsx) {
+            paths.push(best_dir.join(format!("{}.xlsx", stem)));
+        }
+        for path in paths {
+            if !claim_output_file(&path, config.existing, summary)? {
+                continue;
+            }
+            if !dry_run {
+                fs::create_dir_all(&best_dir)?;
+                if path.extension().is_some_and(|extension| extension == "xlsx") {
+                    write_atomically(&path, |temp| write_xlsx(&plays, temp))?;
+                } else {
+                    write_atomically(&path, |temp| write_csv(&plays, temp))?;
+                }
+            }
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(song_id: &str, acc: f64, constant: f64, play_rks: f64) -> ProcessedRecord {
+        ProcessedRecord {
+            song_id: song_id.to_string(),
+            song_name: song_id.to_string(),
+            difficulty: "IN".to_string(),
+            acc,
+            chart_constant: Some(constant),
+            play_rks: Some(play_rks),
+            ..ProcessedRecord::default()
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn empty_slots_count_as_zero() {
+        let records = vec![play("a", 98.0, 14.0, 12.5), play("b", 96.0, 13.0, 10.0)];
+        assert_close(computed_rks(records), (12.5 + 10.0) / 30.0);
+    }
+
+    #[test]
+    fn ap_chart_counts_in_the_best_27_and_the_ap_slots() {
+        let records = vec![play("a", 100.0, 15.0, 15.0), play("b", 98.0, 14.0, 12.5)];
+        assert_close(computed_rks(records), (15.0 + 12.5 + 15.0) / 30.0);
+    }
+
+    #[test]
+    fn only_the_best_27_and_the_3_highest_aps_count() {
+        let mut records: Vec<_> = (0..28).map(|index| play(&format!("song{}", index), 97.0, 12.0, 10.0)).collect();
+        for constant in [9.0, 8.0, 7.0, 6.0] {
+            records.push(play(&format!("ap{}", constant), 100.0, constant, constant));
+        }
+        assert_close(computed_rks(records), (27.0 * 10.0 + 9.0 + 8.0 + 7.0) / 30.0);
+    }
+
+    #[test]
+    fn only_the_best_play_of_a_chart_counts() {
+        let records = vec![play("a", 100.0, 15.0, 15.0), play("a", 99.0, 15.0, 14.0)];
+        assert_close(computed_rks(records), (15.0 + 15.0) / 30.0);
+    }
+}
@@ -0,0 +1,234 @@
+//! `.phisavesong_cache.json` in the output directory: the records each save
+//! produced last time, so saves that haven't changed since aren't parsed
+//! again, and what each per-song file was written from, so songs whose
+//! records are the same aren't rewritten.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::constants::play_rks;
+use crate::input::SaveFile;
+use crate::lenient::Lenient;
+use crate::output::write_atomically;
+use crate::profiles::{Settings, User};
+use crate::progress::GameProgress;
+use crate::{ProcessedRecord, ProcessedSave, SaveInfo};
+
+pub const CACHE_FILE: &str = ".phisavesong_cache.json";
+
+/// The summary fields `ProcessedSave::missing_summary` can name.
+const SUMMARY_FIELDS: [&str; 2] = ["rankingScore", "gameVersion"];
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheFile {
+    /// The settings the saves were read with; the saves are read again when
+    /// they differ.
+    settings: String,
+    saves: HashMap<String, CachedSave>,
+    /// Hash of the records and configuration each per-song file was last
+    /// written from, by file stem.
+    songs: HashMap<String, u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedSave {
+    size: u64,
+    modified: SystemTime,
+    /// With their song ids, which the records themselves don't serialize.
+    records: Vec<(String, ProcessedRecord)>,
+    malformed: Vec<String>,
+    invalid: Vec<String>,
+    missing_summary: Vec<String>,
+    #[serde(default)]
+    lenient_fixes: Vec<String>,
+    /// Missing from caches written before the rest of the save was kept;
+    /// those saves are read again. Sections that could not be read are
+    /// cached as missing.
+    #[serde(default)]
+    save_info: Option<SaveInfo>,
+    #[serde(default)]
+    game_progress: Option<Lenient<GameProgress>>,
+    #[serde(default)]
+    user: Option<Lenient<User>>,
+    #[serde(default)]
+    settings: Option<Lenient<Settings>>,
+}
+
+pub struct Cache {
+    /// Unset with --no-cache, and when writing to stdout.
+    path: Option<PathBuf>,
+    settings: String,
+    previous: CacheFile,
+    /// The saves read in this run; saves that are gone are dropped. Filled in
+    /// from the threads reading the saves.
+    saves: Mutex<HashMap<String, CachedSave>>,
+}
+
+/// What reading a save depends on besides the file itself. Chart constants
+/// are left out, as they are looked up again for cached records.
+fn settings(config: &Config) -> String {
+    format!(
+        "{} {:?} keep_invalid={} lenient_json={}",
+        env!("CARGO_PKG_VERSION"),
+        config.revision_policy,
+        config.keep_invalid,
+        config.lenient_json
+    )
+}
+
+/// Identifies all records of a per-song file together with the configuration
+/// they are written with.
+pub fn song_hash(records: &[ProcessedRecord], config_toml: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    config_toml.hash(&mut hasher);
+    serde_json::to_vec(records).ok().hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Cache {
+    /// A cache that never has anything and is never written.
+    pub fn disabled() -> Self {
+        Cache { path: None, settings: String::new(), previous: CacheFile::default(), saves: Mutex::default() }
+    }
+
+    /// Reads the cache in `output_dir`, starting over with `rebuild` or when
+    /// it is missing, unreadable or was written with other settings.
+    pub fn load(output_dir: &Path, config: &Config, rebuild: bool) -> Self {
+        let path = output_dir.join(CACHE_FILE);
+        let settings = settings(config);
+        let previous = if rebuild { None } else { read(&path) }
+            .filter(|previous| {
+                let same = previous.settings == settings;
+                if !same {
+                    debug!("{} was written with other settings, reading every save", path.display());
+                }
+                same
+            })
+            .unwrap_or_default();
+        Cache { path: Some(path), settings, previous, saves: Mutex::default() }
+    }
+
+    /// The save as it was read last time, if it hasn't changed since.
+    pub fn get(&self, save_file: &SaveFile, config: &Config) -> Option<ProcessedSave> {
+        let cached = self.previous.saves.get(&save_file.location.to_string())?;
+        let (size, modified) = save_file.stamp()?;
+        if cached.size != size || cached.modified != modified {
+            return None;
+        }
+        // Left for a fresh read to fail with --require-summary.
+        if config.require_summary && !cached.missing_summary.is_empty() {
+            return None;
+        }
+        let save_info = cached.save_info.clone()?;
+        let records = cached
+            .records
+            .iter()
+            .map(|(song_id, record)| {
+                let chart_constant = config.chart_constants.get(song_id, &record.difficulty);
+                ProcessedRecord {
+                    song_id: song_id.clone(),
+                    chart_constant,
+                    play_rks: chart_constant.map(|constant| play_rks(constant, record.acc)),
+                    ..record.clone()
+                }
+            })
+            .collect();
+        Some(ProcessedSave {
+            records,
+            malformed: cached.malformed.clone(),
+            invalid: cached.invalid.clone(),
+            missing_summary: cached
+                .missing_summary
+                .iter()
+                .filter_map(|field| SUMMARY_FIELDS.into_iter().find(|known| known == field))
+                .collect(),
+            lenient_fixes: cached.lenient_fixes.clone(),
+            save_info,
+            game_progress: cached.game_progress.clone(),
+            user: cached.user.clone(),
+            settings: cached.settings.clone(),
+        })
+    }
+
+    /// Keeps what `save_file` was read into for the next run.
+    pub fn insert(&self, save_file: &SaveFile, save: &ProcessedSave) {
+        if self.path.is_none() {
+            return;
+        }
+        let Some((size, modified)) = save_file.stamp() else {
+            return;
+        };
+        let cached = CachedSave {
+            size,
+            modified,
+            records: save.records.iter().map(|record| (record.song_id.clone(), record.clone())).collect(),
+            malformed: save.malformed.clone(),
+            invalid: save.invalid.clone(),
+            missing_summary: save.missing_summary.iter().map(|field| field.to_string()).collect(),
+            lenient_fixes: save.lenient_fixes.clone(),
+            save_info: Some(save.save_info.clone()),
+            game_progress: save.game_progress.clone(),
+            user: save.user.clone(),
+            settings: save.settings.clone(),
+        };
+        self.saves.lock().unwrap_or_else(PoisonError::into_inner).insert(save_file.location.to_string(), cached);
+    }
+
+    /// Whether the per-song file `stem` was last written from the same records.
+    pub fn song_unchanged(&self, stem: &str, hash: u64) -> bool {
+        self.path.is_some() && self.previous.songs.get(stem) == Some(&hash)
+    }
+
+    pub fn song_written(&mut self, stem: &str, hash: u64) {
+        self.previous.songs.insert(stem.to_string(), hash);
+    }
+
+    /// Writes the cache for the next run. Failing to do so only loses the
+    /// speed-up, so it is a warning.
+    pub fn write(self) {
+        let Some(path) = self.path else {
+            return;
+        };
+        let saves = self.saves.into_inner().unwrap_or_else(PoisonError::into_inner);
+        let file = CacheFile { settings: self.settings, saves, songs: self.previous.songs };
+        let written = write_atomically(&path, |temp| {
+            let mut writer = BufWriter::new(File::create(temp)?);
+            serde_json::to_writer(&mut writer, &file)?;
+            writer.flush()?;
+            Ok(())
+        });
+        if let Err(err) = written {
+            warn!("Failed to write {}: {:#}", path.display(), err);
+        }
+    }
+}
+
+/// The cache at `path`, or `None` when there is none or it can't be used.
+fn read(path: &Path) -> Option<CacheFile> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return None,
+        Err(err) => {
+            warn!("Failed to read {}, reading every save: {}", path.display(), err);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&content).with_context(|| format!("{} is corrupt", path.display())) {
+        Ok(cache) => Some(cache),
+        Err(err) => {
+            warn!("{:#}, reading every save", err);
+            None
+        }
+    }
+}
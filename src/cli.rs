@@ -0,0 +1,478 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::failures::ErrorPolicy;
+use crate::output::{AccFormat, Column, Compression, Delimiter, GroupBy, Layout, OutputFormat, SplitBy};
+use crate::sort::SortKey;
+use crate::{Difficulty, RevisionPolicy};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Export per-song score tables from phi-plugin saveData",
+    after_help = crate::exit::EXIT_STATUS_HELP
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Config file [default: phisavesong.toml if present]
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Increase log verbosity (-v for per-song details, -vv for every record)
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only log errors
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Run the whole pipeline but only print what would be written
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Save data directories, each containing one sub-directory per player,
+    /// or zip archives of them [env: PHISAVESONG_INPUT] [default: saveData]
+    #[arg(short, long, value_name = "DIR", num_args = 1..)]
+    pub input: Vec<PathBuf>,
+
+    /// Find save.json at any depth below the input directories; the player id
+    /// becomes the relative directory, e.g. `player/date`
+    #[arg(short, long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub recursive: Option<bool>,
+
+    /// Glob for save file names in each player directory; every match is read
+    /// as its own snapshot [default: save.json]
+    #[arg(long, value_name = "GLOB")]
+    pub save_pattern: Option<String>,
+
+    /// Fetch this account's latest cloud save into the first input directory
+    /// before processing (repeatable; needs the `cloud` feature)
+    #[arg(long, value_name = "TOKEN")]
+    pub session_token: Vec<String>,
+
+    /// File with session tokens to fetch, one per line
+    #[arg(long, value_name = "FILE")]
+    pub tokens_file: Option<PathBuf>,
+
+    /// Stop after fetching cloud saves
+    #[arg(long)]
+    pub fetch_only: bool,
+
+    /// Directory the per-song files are written to, or `-` for stdout
+    /// [env: PHISAVESONG_OUTPUT] [default: rks_data_output]
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+
+    /// Write to the output directory even if it is an input directory or
+    /// inside one; the saves are still looked for outside of it
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub allow_nested_output: Option<bool>,
+
+    /// Stream all selected records as one CSV to stdout (same as `-o -`)
+    #[arg(long, conflicts_with = "output")]
+    pub stdout: bool,
+
+    /// Comma-separated list of output formats to write [default: csv,xlsx]
+    #[arg(short, long, value_name = "FORMATS", value_delimiter = ',')]
+    pub format: Vec<OutputFormat>,
+
+    /// Only keep records of these difficulties, e.g. `IN,AT`
+    #[arg(short, long, value_name = "DIFFS", value_delimiter = ',', ignore_case = true)]
+    pub difficulty: Vec<Difficulty>,
+
+    /// Only process songs whose name matches this glob or exact name (repeatable)
+    #[arg(short, long, value_name = "PATTERN")]
+    pub song: Vec<String>,
+
+    /// Match --song patterns case-sensitively
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub case_sensitive: Option<bool>,
+
+    /// Drop records with acc below this value (inclusive threshold)
+    #[arg(long, value_name = "ACC")]
+    pub min_acc: Option<f64>,
+
+    /// Drop records with score below this value (inclusive threshold)
+    #[arg(long, value_name = "SCORE")]
+    pub min_score: Option<i32>,
+
+    /// Only keep full-combo records
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub fc_only: Option<bool>,
+
+    /// Only keep all-perfect records
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub ap_only: Option<bool>,
+
+    /// Only process these player directories (comma-separated, or @file)
+    #[arg(long, value_name = "IDS", value_delimiter = ',')]
+    pub players: Vec<String>,
+
+    /// Skip these player directories (comma-separated, or @file)
+    #[arg(long, value_name = "IDS", value_delimiter = ',')]
+    pub exclude_players: Vec<String>,
+
+    /// File with song names or raw song ids to drop, one per line
+    #[arg(long, value_name = "FILE")]
+    pub exclude_songs: Option<PathBuf>,
+
+    /// Row order, e.g. `acc:desc,score:desc` [default: song_name,difficulty,score:desc]
+    #[arg(long, value_name = "KEYS", value_delimiter = ',')]
+    pub sort_by: Vec<SortKey>,
+
+    /// Comma-separated columns to export, in order [default: all]
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+    pub columns: Vec<Column>,
+
+    /// Overwrite output files that already exist
+    #[arg(long, conflicts_with = "skip_existing")]
+    pub force: bool,
+
+    /// Keep output files that already exist and only write new ones
+    #[arg(long)]
+    pub skip_existing: bool,
+
+    /// Field separator for csv output: `,`, `;`, `\t` or any single character
+    /// (a tab writes `.tsv` files) [default: ,]
+    #[arg(long, value_name = "CHAR")]
+    pub delimiter: Option<Delimiter>,
+
+    /// Write JSON output on a single line instead of pretty-printed
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub json_compact: Option<bool>,
+
+    /// Write jsonl output for all songs into a single all_records.jsonl
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub jsonl_single: Option<bool>,
+
+    /// With parquet output, also write all songs into all_records.parquet
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub parquet_combined: Option<bool>,
+
+    /// Only put the N highest-scoring rows into markdown tables
+    #[arg(long, value_name = "N")]
+    pub top: Option<usize>,
+
+    /// Write xlsx output as one all_songs.xlsx with a worksheet per song
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub xlsx_single_workbook: Option<bool>,
+
+    /// How acc is displayed in xlsx output [default: number]
+    #[arg(long, value_name = "FORMAT")]
+    pub xlsx_acc_format: Option<AccFormat>,
+
+    /// Don't highlight full-combo and all-perfect rows in xlsx output
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub no_xlsx_colors: Option<bool>,
+
+    /// Compress csv output, including csv streamed to stdout [default: none]
+    #[arg(long, value_name = "METHOD")]
+    pub compress: Option<Compression>,
+
+    /// Compression level from 0 (fastest) to 9 (smallest) [default: 6]
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u32).range(0..=9))]
+    pub compress_level: Option<u32>,
+
+    /// Write one file per song and difficulty, e.g. `{song}_IN.csv`
+    #[arg(long, value_name = "KEY")]
+    pub split_by: Option<SplitBy>,
+
+    /// Keep the difficulty column in files written with --split-by difficulty
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub keep_difficulty_column: Option<bool>,
+
+    /// Table shape of csv and xlsx files: `long` (one row per record) or
+    /// `wide` (one row per player, score and acc per difficulty) [default: long]
+    #[arg(long, value_name = "LAYOUT")]
+    pub layout: Option<Layout>,
+
+    /// Write one file per `song` or per `player` directory [default: song]
+    #[arg(long, value_name = "KEY")]
+    pub group_by: Option<GroupBy>,
+
+    /// Also write every record into all_records.csv / .xlsx / .msgpack
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub combined: Option<bool>,
+
+    /// Like --combined, but skip the per-song files
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub combined_only: Option<bool>,
+
+    /// Merge new rows into existing per-song csv files instead of rewriting them
+    #[arg(
+        long, conflicts_with_all = ["force", "skip_existing"],
+        num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL"
+    )]
+    pub append: Option<bool>,
+
+    /// Columns that identify a row when merging with --append; the best score
+    /// is kept for each [default: all exported columns]
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+    pub dedupe_on: Vec<Column>,
+
+    /// Read saves and write per-song files on this many threads; 1 does
+    /// everything in order on one thread [default: number of CPU cores]
+    #[arg(long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Append each save's records to the per-song csv files as it is read,
+    /// instead of holding every record in memory. Only plain csv per-song
+    /// files are written, and rows are sorted within each save only, so a
+    /// song's rows come in the order of the saves
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub low_memory: Option<bool>,
+
+    /// Don't read or write the cache of unchanged saves and songs in the
+    /// output directory
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub no_cache: Option<bool>,
+
+    /// Read every save again and rewrite every song, then start a new cache
+    #[arg(long, conflicts_with = "no_cache")]
+    pub rebuild_cache: bool,
+
+    /// Also write player_summary.csv with one row per save, taken from the
+    /// counts the game reports in the save summary
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub player_summary: Option<bool>,
+
+    /// Also write player_totals.csv with one row per save totalling its
+    /// exported records: counts per difficulty, FC / AP counts and the mean
+    /// acc on IN and AT; with --format xlsx also a sheet in summary.xlsx
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub player_summary_out: Option<bool>,
+
+    /// Keep only each player's most recently captured save in
+    /// player_totals.csv instead of a row per snapshot, and rank it in the
+    /// global leaderboard instead of the save with the highest rks
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub latest_snapshot: Option<bool>,
+
+    /// Also write fc_ap_summary.csv with each player's FC and AP counts per
+    /// difficulty over their best plays, flagging counts that differ from
+    /// the save's own summary
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub fc_ap_summary: Option<bool>,
+
+    /// CSV (or .tsv) of `song_id,difficulty,constant` rows; adds
+    /// chart_constant and play_rks columns to every record
+    #[arg(long, value_name = "FILE")]
+    pub constants: Option<PathBuf>,
+
+    /// CSV of `song_id,difficulty` rows; also write completion.csv with how
+    /// many of those charts each player has played, FC'd and AP'd, and
+    /// unplayed_charts.csv with the ones nobody has played
+    #[arg(long, value_name = "FILE")]
+    pub songlist: Option<PathBuf>,
+
+    /// Also write leaderboards/{song}_{difficulty}.csv ranking each player's
+    /// best record by score, then acc
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub leaderboards: Option<bool>,
+
+    /// Keep only the first N rows of each leaderboard
+    #[arg(long, value_name = "N")]
+    pub leaderboard_top: Option<usize>,
+
+    /// Also write leaderboard_global.csv (and .xlsx with --format xlsx)
+    /// ranking every player by rankingScore, or by the computed rks with
+    /// --constants
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub global_leaderboard: Option<bool>,
+
+    /// Also write histograms/{song}_{difficulty}.csv counting each player's
+    /// best acc in bins from 70 to 100, plus one below 70 and one for 100
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub histograms: Option<bool>,
+
+    /// Width of the histogram bins [default: 1.0]
+    #[arg(long, value_name = "ACC")]
+    pub histogram_bin_width: Option<f64>,
+
+    /// Also draw plots/{song}_{difficulty}.png with the acc histogram of
+    /// each chart; needs building with `--features plots`
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub plots: Option<bool>,
+
+    /// Skip plotting charts played by fewer players [default: 10]
+    #[arg(long, value_name = "N")]
+    pub plot_min_players: Option<usize>,
+
+    /// Width of the plots in pixels [default: 800]
+    #[arg(long, value_name = "PIXELS")]
+    pub plot_width: Option<u32>,
+
+    /// Height of the plots in pixels [default: 600]
+    #[arg(long, value_name = "PIXELS")]
+    pub plot_height: Option<u32>,
+
+    /// Also write timeline/{player}.csv with the rankingScore of each of a
+    /// player's snapshots, and a line chart of it with the plots feature
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub rks_timeline: Option<bool>,
+
+    /// Also write history/{player}.csv with the records that changed in score,
+    /// acc or fc from each of a player's snapshots to the next
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub history: Option<bool>,
+
+    /// Also write stats.csv with the record and player counts, mean acc,
+    /// score range and FC / AP counts of every song and difficulty
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub stats: Option<bool>,
+
+    /// Print where the run spent its time at the end, and write it to
+    /// timings.json
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub timings: Option<bool>,
+
+    /// Also write errors.json with every save that could not be read
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub errors_json: Option<bool>,
+
+    /// Exit successfully even if some saves could not be read
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub allow_errors: Option<bool>,
+
+    /// Whether to `continue` past saves that can't be read, malformed or
+    /// invalid records and files that can't be written, counting them in
+    /// the run summary, or stop at the first with `fail-fast`
+    /// [default: continue]
+    #[arg(long, value_name = "POLICY")]
+    pub error_policy: Option<ErrorPolicy>,
+
+    /// Also write best/{player}.csv (and .xlsx with --format xlsx) with each
+    /// player's N plays worth the most rks, e.g. 27 for B27; needs --constants
+    #[arg(long, value_name = "N")]
+    pub best_n: Option<usize>,
+
+    /// Also write push_acc/{player}.csv with the acc each played chart needs
+    /// to raise the player's rks by 0.01; needs --constants
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub push_acc: Option<bool>,
+
+    /// Also list in push_acc/ the charts a player hasn't played yet whose
+    /// constant is at least CONSTANT
+    #[arg(long, value_name = "CONSTANT")]
+    pub push_acc_unplayed: Option<f64>,
+
+    /// Also write suggestions/{player}.md with the 20 charts where a little
+    /// more acc would raise the player's rks the most; needs --constants
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub suggestions: Option<bool>,
+
+    /// Acc added to each chart's current acc for --suggestions, capped at
+    /// 100% [default: 1.0]
+    #[arg(long, value_name = "ACC")]
+    pub suggestion_step: Option<f64>,
+
+    /// Also write rks_drift.csv comparing each save's rankingScore with the
+    /// rks computed from its records; needs --constants
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub rks_drift: Option<bool>,
+
+    /// Difference in rks above which rks_drift.csv flags a save and lists
+    /// the plays contributing most to it [default: 0.01]
+    #[arg(long, value_name = "RKS")]
+    pub drift_threshold: Option<f64>,
+
+    /// Also write progress.csv with one row per save from its gameProgress
+    /// section (chapter unlocks, data, ...)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub progress_out: Option<bool>,
+
+    /// Also write one row per save combining its summary with the user and
+    /// settings sections; json if the path ends in .json, csv otherwise
+    #[arg(long, value_name = "PATH")]
+    pub profiles_out: Option<PathBuf>,
+
+    /// Add an avatar column with the save's avatar id to every record
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub with_avatar: Option<bool>,
+
+    /// Add a captured_at column with when the save was captured (RFC 3339, UTC)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub with_timestamp: Option<bool>,
+
+    /// Add an acc_percentile column with the percentage of players on the
+    /// same chart whose best acc is below the record's (ties count half),
+    /// left empty for charts with fewer than 5 players
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub with_percentiles: Option<bool>,
+
+    /// Add a chart_suffix column with the numeric revision suffix of the song
+    /// id (e.g. `0` in `SongName.ArtistName.0`)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub with_chart_id: Option<bool>,
+
+    /// How to export song ids that differ only in their chart suffix, e.g.
+    /// `Song.Artist.0` and `Song.Artist.1`: `merge` them into one song, keep
+    /// only the `latest` revision in each save, or export them as `separate`
+    /// songs named `Song.1` [default: merge]
+    #[arg(long, value_name = "POLICY")]
+    pub revision_policy: Option<RevisionPolicy>,
+
+    /// Put songs whose names differ only in case into one file, named with
+    /// the most common casing
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub merge_case_insensitive: Option<bool>,
+
+    /// List the song ids that Unicode (NFC) normalization changed and merged
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub report_normalization: Option<bool>,
+
+    /// Keep records whose score or acc is out of range instead of dropping
+    /// them, and mark them in an `invalid` column
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub keep_invalid: Option<bool>,
+
+    /// Treat saves without rankingScore or gameVersion in their summary as
+    /// failed instead of exporting placeholders
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub require_summary: Option<bool>,
+
+    /// Accept saves with comments, trailing commas or bare NaN / Infinity,
+    /// as hand-edited ones tend to have, and report which saves needed it
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub lenient_json: Option<bool>,
+
+    /// Leave the player_id column out of the default columns
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub no_player_id: Option<bool>,
+
+    /// Fail when a save has score entries beyond the known difficulties or
+    /// records with out-of-range score or acc, instead of warning
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    pub strict: Option<bool>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Compare two saves of one player and report what changed between them
+    Diff {
+        /// The older save, or a directory holding its save.json
+        old: PathBuf,
+        /// The newer save, or a directory holding its save.json
+        new: PathBuf,
+        /// Also write the changes as csv to FILE, e.g. diff.csv
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+    },
+    /// Estimate constants for the charts missing from --constants from the
+    /// players' accs and rankingScores, written to estimated_constants.csv
+    EstimateConstants {
+        /// Where to write the estimates [default: <output>/estimated_constants.csv]
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Players a chart needs to get an estimate
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        min_samples: usize,
+    },
+}
@@ -0,0 +1,117 @@
+//! Downloads players' latest cloud saves from the TapTap/LeanCloud save API
+//! the game uses, given their session tokens.
+//!
+//! Session tokens grant full access to an account, so they are never logged;
+//! messages refer to a token by its position instead.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::decode;
+use crate::output::write_atomically;
+
+const API: &str = "https://rak3ffdi.cloud.tds1.tapapis.cn/1.1";
+const APP_ID: &str = "rAK3FfdieFob2Nn8Am";
+const APP_KEY: &str = "Qr9AEqtuoSVS3zeD6iVbM4ZC0AtkJcQ89tywVyi0";
+const USER_AGENT: &str = "LeanCloud-CSharp-SDK/1.0.3";
+
+/// Pause between accounts so a long token list doesn't trip rate limiting.
+const DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+struct User {
+    #[serde(rename = "objectId")]
+    object_id: String,
+    nickname: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GameSaves {
+    results: Vec<GameSave>,
+}
+
+#[derive(Deserialize)]
+struct GameSave {
+    #[serde(rename = "gameFile")]
+    game_file: GameFile,
+    /// Base64 of the binary summary.
+    summary: String,
+    #[serde(rename = "updatedAt")]
+    updated_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GameFile {
+    url: String,
+}
+
+fn get(url: &str, token: &str) -> Result<ureq::Response> {
+    Ok(ureq::get(url)
+        .set("X-LC-Id", APP_ID)
+        .set("X-LC-Key", APP_KEY)
+        .set("X-LC-Session", token)
+        .set("User-Agent", USER_AGENT)
+        .set("Accept", "application/json")
+        .call()?)
+}
+
+/// Fetches one account's latest save and writes it as
+/// `<save_data_dir>/<player>/save.json`, returning the player directory name.
+fn fetch_one(token: &str, save_data_dir: &Path) -> Result<String> {
+    let user: User = get(&format!("{}/users/me", API), token)?.into_json().context("Unexpected user response")?;
+    // An account can have more than one save; the newest is the one in use.
+    let saves: GameSaves = get(&format!("{}/classes/_GameSave?order=-updatedAt&limit=1", API), token)?
+        .into_json()
+        .context("Unexpected save list response")?;
+    let Some(save) = saves.results.into_iter().next() else {
+        bail!("The account has no cloud save");
+    };
+    let mut bytes = Vec::new();
+    ureq::get(&save.game_file.url)
+        .call()?
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .context("Failed to download the save file")?;
+
+    let mut save_data = decode::decode_save(&bytes)?;
+    let summary = STANDARD.decode(&save.summary).context("Summary is not valid base64")?;
+    save_data.save_info.summary = decode::decode_summary(&summary)?;
+    save_data.save_info.nickname = user.nickname;
+    save_data.save_info.updated_at = save.updated_at;
+
+    let player_dir = save_data_dir.join(&user.object_id);
+    fs::create_dir_all(&player_dir)?;
+    let path = player_dir.join("save.json");
+    let json = serde_json::to_vec_pretty(&save_data)?;
+    write_atomically(&path, |temp| Ok(fs::write(temp, &json)?))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(user.object_id)
+}
+
+/// Fetches every token's save into `save_data_dir`. A failing account is
+/// reported and skipped; the number of saves written is returned.
+pub fn fetch_saves(tokens: &[String], save_data_dir: &Path) -> usize {
+    let mut fetched = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 {
+            thread::sleep(DELAY);
+        }
+        match fetch_one(token, save_data_dir) {
+            Ok(player) => {
+                info!("Token #{}: saved {}", index + 1, player);
+                fetched += 1;
+            }
+            Err(err) => error!("Token #{}: {:#}", index + 1, err),
+        }
+    }
+    fetched
+}
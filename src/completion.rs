@@ -0,0 +1,130 @@
+//! `--songlist`: how much of a list of charts each player has played, full
+//! combo'd and All Perfect'd.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::{split_revision, Difficulty, ProcessedSave};
+
+pub const COMPLETION_FILE: &str = "completion.csv";
+pub const UNPLAYED_CHARTS_FILE: &str = "unplayed_charts.csv";
+
+/// A chart, by song id without its chart suffix and difficulty, the way
+/// --constants matches them.
+type Chart = (String, &'static str);
+
+/// Reads `song_id,difficulty` lines. A first line whose difficulty isn't
+/// known is taken as a header; charts listed twice count once.
+pub fn load_songlist(path: &Path) -> Result<Vec<Chart>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)
+        .with_context(|| format!("Failed to open songlist: {}", path.display()))?;
+    let mut seen = HashSet::new();
+    let mut charts = Vec::new();
+    for (index, row) in reader.records().enumerate() {
+        let line = index + 1;
+        let row = row.with_context(|| format!("{}: failed to read line {}", path.display(), line))?;
+        if row.len() != 2 {
+            bail!("{}: line {}: expected song_id,difficulty", path.display(), line);
+        }
+        let [song_id, difficulty] = [0, 1].map(|i| row.get(i).unwrap_or_default().trim());
+        let difficulty = match Difficulty::ALL.into_iter().find(|d| d.as_str().eq_ignore_ascii_case(difficulty)) {
+            Some(difficulty) => difficulty.as_str(),
+            None if line == 1 => continue,
+            None => bail!("{}: line {}: unknown difficulty `{}`", path.display(), line, difficulty),
+        };
+        let chart = (split_revision(song_id).0.to_string(), difficulty);
+        if seen.insert(chart.clone()) {
+            charts.push(chart);
+        }
+    }
+    Ok(charts)
+}
+
+/// Whether a player has full combo'd and All Perfect'd a chart, in any save.
+#[derive(Default, Clone, Copy)]
+struct Progress {
+    fc: bool,
+    ap: bool,
+}
+
+fn percentage(count: usize, total: usize) -> String {
+    if total == 0 {
+        String::new()
+    } else {
+        format!("{:.2}", count as f64 / total as f64 * 100.0)
+    }
+}
+
+/// Writes one row per player with how many of the songlist's charts they have
+/// a record, a full combo and an All Perfect on, to `completion_path`, and the
+/// charts no player has a record on to `unplayed_path` when given.
+pub fn write_completion(
+    saves: &[(&SaveFile, ProcessedSave)],
+    songlist: &[Chart],
+    completion_path: Option<&Path>,
+    unplayed_path: Option<&Path>,
+) -> Result<()> {
+    let listed: HashSet<_> = songlist.iter().map(|(song_id, difficulty)| (song_id.as_str(), *difficulty)).collect();
+    let mut players: BTreeMap<String, HashMap<Chart, Progress>> = BTreeMap::new();
+    for (save_file, save) in saves {
+        let charts = players.entry(save_file.player().to_string()).or_default();
+        for record in &save.records {
+            let song_id = split_revision(&record.song_id).0;
+            let Some(difficulty) = Difficulty::ALL.iter().map(|d| d.as_str()).find(|&d| d == record.difficulty) else {
+                continue;
+            };
+            if !listed.contains(&(song_id, difficulty)) {
+                continue;
+            }
+            let progress = charts.entry((song_id.to_string(), difficulty)).or_default();
+            progress.fc |= record.fc || record.ap;
+            progress.ap |= record.ap;
+        }
+    }
+
+    if let Some(path) = completion_path {
+        write_atomically(path, |temp| {
+            let mut writer = csv::Writer::from_path(temp)?;
+            writer.write_record(["player_id", "charts", "played", "fc", "ap", "played_pct", "fc_pct", "ap_pct"])?;
+            let total = songlist.len();
+            for (player_id, charts) in &players {
+                let fc = charts.values().filter(|progress| progress.fc).count();
+                let ap = charts.values().filter(|progress| progress.ap).count();
+                writer.write_record([
+                    player_id.clone(),
+                    total.to_string(),
+                    charts.len().to_string(),
+                    fc.to_string(),
+                    ap.to_string(),
+                    percentage(charts.len(), total),
+                    percentage(fc, total),
+                    percentage(ap, total),
+                ])?;
+            }
+            writer.flush()?;
+            Ok(())
+        })?;
+    }
+
+    if let Some(path) = unplayed_path {
+        write_atomically(path, |temp| {
+            let mut writer = csv::Writer::from_path(temp)?;
+            writer.write_record(["song_id", "difficulty"])?;
+            for chart in songlist {
+                if !players.values().any(|charts| charts.contains_key(chart)) {
+                    writer.write_record([chart.0.as_str(), chart.1])?;
+                }
+            }
+            writer.flush()?;
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
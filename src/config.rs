@@ -0,0 +1,804 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Cli;
+use crate::constants::ChartConstants;
+use crate::failures::ErrorPolicy;
+use crate::output::{
+    AccFormat, Column, Compression, Delimiter, ExistingFiles, GroupBy, Layout, OutputFormat, SplitBy,
+};
+use crate::sort::SortKey;
+use crate::{Difficulty, RevisionPolicy};
+
+pub const DEFAULT_CONFIG_FILE: &str = "phisavesong.toml";
+pub const DEFAULT_INPUT: &str = "saveData";
+pub const DEFAULT_OUTPUT: &str = "rks_data_output";
+pub const DEFAULT_SAVE_PATTERN: &str = "save.json";
+pub const INPUT_ENV: &str = "PHISAVESONG_INPUT";
+pub const OUTPUT_ENV: &str = "PHISAVESONG_OUTPUT";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Unset in the file means "fall back to env/platform defaults"; filled in by
+    /// [`Config::apply_cli`].
+    pub input: Vec<PathBuf>,
+    /// Where `input` was taken from, for errors about it.
+    #[serde(skip)]
+    pub input_source: PathSource,
+    /// Look for save files at any depth below the input directories.
+    pub recursive: bool,
+    /// Glob matched against file names to find saves in player directories.
+    pub save_pattern: String,
+    /// Session tokens of accounts whose cloud saves are fetched before the
+    /// run. Never written out by --print-config.
+    #[serde(skip_serializing)]
+    pub session_tokens: Vec<String>,
+    /// File with more session tokens, one per line.
+    pub tokens_file: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    /// Allow the output directory to be an input directory or inside one.
+    pub allow_nested_output: bool,
+    pub formats: Vec<OutputFormat>,
+    /// Difficulties to keep; empty means all of them.
+    pub difficulties: Vec<Difficulty>,
+    /// Song name globs to keep; empty means every song.
+    pub songs: Vec<String>,
+    pub case_sensitive: bool,
+    pub min_acc: Option<f64>,
+    pub min_score: Option<i32>,
+    pub fc_only: bool,
+    pub ap_only: bool,
+    /// Player directory names to process (`@file` reads one name per line).
+    pub players: Vec<String>,
+    pub exclude_players: Vec<String>,
+    /// File listing song names or raw song ids to drop.
+    pub exclude_songs: Option<PathBuf>,
+    pub sort_by: Vec<SortKey>,
+    /// Exported columns in order; empty means all of them.
+    pub columns: Vec<Column>,
+    pub existing: ExistingFiles,
+    pub delimiter: Delimiter,
+    pub json_compact: bool,
+    /// Stream jsonl output for every song into one `all_records.jsonl`.
+    pub jsonl_single: bool,
+    /// Also write every song into one `all_records.parquet`.
+    pub parquet_combined: bool,
+    /// Only put this many highest-scoring rows into markdown tables.
+    pub top: Option<usize>,
+    /// Write one `all_songs.xlsx` with a sheet per song instead of per-song files.
+    pub xlsx_single_workbook: bool,
+    pub xlsx_acc_format: AccFormat,
+    /// Highlight full-combo and all-perfect rows in xlsx output.
+    pub xlsx_colors: bool,
+    /// Compression applied to csv output.
+    pub compress: Compression,
+    pub compress_level: u32,
+    pub split_by: Option<SplitBy>,
+    /// Keep the difficulty column in files split by difficulty.
+    pub keep_difficulty_column: bool,
+    pub layout: Layout,
+    pub group_by: GroupBy,
+    /// Also write every record into `all_records.csv`/`.xlsx`/`.msgpack`.
+    pub combined: bool,
+    /// Write only the combined files, no per-song files.
+    pub combined_only: bool,
+    /// Merge new rows into existing per-song csv files.
+    pub append: bool,
+    /// Columns identifying a row when merging, defaults to all exported columns.
+    pub dedupe_on: Vec<Column>,
+    /// Threads reading the saves and writing the per-song files; all CPU
+    /// cores when unset.
+    pub jobs: Option<usize>,
+    /// Append to the per-song csv files one save at a time instead of holding
+    /// every record in memory.
+    pub low_memory: bool,
+    /// Keep `.phisavesong_cache.json` in the output directory, so unchanged
+    /// saves aren't parsed again and unchanged songs aren't rewritten.
+    pub cache: bool,
+    /// Also write `player_summary.csv` with the totals from each save's summary.
+    pub player_summary: bool,
+    /// Also write `fc_ap_summary.csv` with each player's FC and AP counts.
+    pub fc_ap_summary: bool,
+    /// Also write `player_totals.csv` with totals of each save's records.
+    pub player_summary_out: bool,
+    /// Keep only each player's latest save in `player_totals.csv`, and rank
+    /// it in the global leaderboard instead of the one with the highest rks.
+    pub latest_snapshot: bool,
+    /// `song_id,difficulty,constant` csv or tsv adding chart constants and
+    /// per-play rks to every record.
+    pub constants: Option<PathBuf>,
+    /// Read from `constants` once the configuration is complete.
+    #[serde(skip)]
+    pub chart_constants: ChartConstants,
+    /// `song_id,difficulty` csv of the charts `completion.csv` counts.
+    pub songlist: Option<PathBuf>,
+    /// Also write `leaderboards/{song}_{difficulty}.csv` ranking the players.
+    pub leaderboards: bool,
+    /// Rows kept in each leaderboard; all players when unset.
+    pub leaderboard_top: Option<usize>,
+    /// Also write `leaderboard_global.csv` ranking the players by rks.
+    pub global_leaderboard: bool,
+    /// Also write `histograms/{song}_{difficulty}.csv` with the acc spread.
+    pub histograms: bool,
+    /// Width of the histogram bins between 70 and 100 acc.
+    pub histogram_bin_width: f64,
+    /// Also draw `plots/{song}_{difficulty}.png` from the acc histograms.
+    pub plots: bool,
+    /// Charts with fewer players than this are not plotted.
+    pub plot_min_players: usize,
+    pub plot_width: u32,
+    pub plot_height: u32,
+    /// Also write `timeline/{player}` with rankingScore over the snapshots.
+    pub rks_timeline: bool,
+    /// Also write `history/{player}.csv` with record changes over the snapshots.
+    pub history: bool,
+    /// Also write `stats.csv` with aggregates per song and difficulty.
+    pub stats: bool,
+    /// Print the time spent scanning, parsing, grouping and writing, and
+    /// write it to `timings.json`.
+    pub timings: bool,
+    /// Also write `errors.json` with the saves that could not be read.
+    pub errors_json: bool,
+    /// Succeed even if some saves could not be read.
+    pub allow_errors: bool,
+    /// Whether to go on past saves that can't be read, malformed or invalid
+    /// records and files that can't be written, or stop at the first.
+    pub error_policy: ErrorPolicy,
+    /// Also write each player's N plays worth the most rks to `best/`.
+    pub best_n: Option<usize>,
+    /// Also write `push_acc/{player}.csv` with the acc each chart needs to
+    /// raise the player's rks.
+    pub push_acc: bool,
+    /// Also list unplayed charts of at least this constant in `push_acc/`.
+    pub push_acc_unplayed: Option<f64>,
+    /// Also write `suggestions/{player}.md` with the charts worth grinding.
+    pub suggestions: bool,
+    /// Acc added to each chart's current acc for `suggestions/`.
+    pub suggestion_step: f64,
+    /// Also write `rks_drift.csv` comparing each save's stored and computed rks.
+    pub rks_drift: bool,
+    /// Difference in rks above which `rks_drift.csv` flags a save.
+    pub drift_threshold: f64,
+    /// Also write `progress.csv` with each save's gameProgress section.
+    pub progress_out: bool,
+    /// Also write each save's profile (summary, user and settings) here, as
+    /// json if it ends in `.json` and csv otherwise.
+    pub profiles_out: Option<PathBuf>,
+    /// Add the save's avatar id to every exported record.
+    pub with_avatar: bool,
+    /// Add when each save was captured to every exported record.
+    pub with_timestamp: bool,
+    /// Add each record's acc percentile among the players of its chart.
+    pub with_percentiles: bool,
+    /// Add the numeric chart suffix of the song id to every exported record.
+    pub with_chart_id: bool,
+    /// How song ids that differ only in their chart suffix are exported.
+    pub revision_policy: RevisionPolicy,
+    /// Group songs whose names differ only in case into one file.
+    pub merge_case_insensitive: bool,
+    /// List the song ids whose names were changed by Unicode normalization.
+    pub report_normalization: bool,
+    /// Keep records with out-of-range score or acc, marked in an `invalid` column.
+    pub keep_invalid: bool,
+    /// Fail on saves whose summary lacks rankingScore or gameVersion.
+    pub require_summary: bool,
+    /// Clean comments, trailing commas and bare NaN / Infinity out of saves
+    /// before parsing them.
+    pub lenient_json: bool,
+    /// Export the player_id column by default.
+    pub player_id: bool,
+    /// Fail on score entries beyond the known difficulties or with out-of-range
+    /// values instead of warning.
+    pub strict: bool,
+    #[serde(flatten, skip_serializing)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            input: Vec::new(),
+            input_source: PathSource::default(),
+            recursive: false,
+            save_pattern: DEFAULT_SAVE_PATTERN.to_string(),
+            session_tokens: Vec::new(),
+            tokens_file: None,
+            output: None,
+            allow_nested_output: false,
+            formats: vec![OutputFormat::Csv, OutputFormat::Xlsx],
+            difficulties: Vec::new(),
+            songs: Vec::new(),
+            case_sensitive: false,
+            min_acc: None,
+            min_score: None,
+            fc_only: false,
+            ap_only: false,
+            players: Vec::new(),
+            exclude_players: Vec::new(),
+            exclude_songs: None,
+            sort_by: Vec::new(),
+            columns: Vec::new(),
+            existing: ExistingFiles::Error,
+            delimiter: Delimiter::COMMA,
+            json_compact: false,
+            jsonl_single: false,
+            parquet_combined: false,
+            top: None,
+            xlsx_single_workbook: false,
+            xlsx_acc_format: AccFormat::Number,
+            xlsx_colors: true,
+            compress: Compression::None,
+            compress_level: 6,
+            split_by: None,
+            keep_difficulty_column: false,
+            layout: Layout::Long,
+            group_by: GroupBy::Song,
+            combined: false,
+            combined_only: false,
+            append: false,
+            dedupe_on: Vec::new(),
+            jobs: None,
+            low_memory: false,
+            cache: true,
+            player_summary: false,
+            fc_ap_summary: false,
+            player_summary_out: false,
+            latest_snapshot: false,
+            constants: None,
+            chart_constants: ChartConstants::default(),
+            songlist: None,
+            leaderboards: false,
+            leaderboard_top: None,
+            global_leaderboard: false,
+            histograms: false,
+            histogram_bin_width: 1.0,
+            plots: false,
+            plot_min_players: 10,
+            plot_width: 800,
+            plot_height: 600,
+            rks_timeline: false,
+            history: false,
+            stats: false,
+            timings: false,
+            errors_json: false,
+            allow_errors: false,
+            error_policy: ErrorPolicy::Continue,
+            best_n: None,
+            push_acc: false,
+            push_acc_unplayed: None,
+            suggestions: false,
+            suggestion_step: 1.0,
+            rks_drift: false,
+            drift_threshold: 0.01,
+            progress_out: false,
+            profiles_out: None,
+            with_avatar: false,
+            with_timestamp: false,
+            with_percentiles: false,
+            with_chart_id: false,
+            revision_policy: RevisionPolicy::Merge,
+            merge_case_insensitive: false,
+            report_normalization: false,
+            keep_invalid: false,
+            require_summary: false,
+            lenient_json: false,
+            player_id: true,
+            strict: false,
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the file given by `--config`, or `phisavesong.toml` in the working
+    /// directory if it exists. Without either, the built-in defaults are used.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let default_path = PathBuf::from(DEFAULT_CONFIG_FILE);
+                if !default_path.is_file() {
+                    return Ok(Config::default());
+                }
+                default_path
+            }
+        };
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let config: Config = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+        for key in config.unknown.keys() {
+            warn!("Unknown key `{}` in {}", key, path.display());
+        }
+        Ok(config)
+    }
+
+    pub fn apply_cli(&mut self, cli: &Cli) {
+        let paths = resolve_paths(cli, |key| env::var_os(key), self, platform_input_dir());
+        debug!(
+            "input: {} (from {})",
+            paths.input.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "),
+            paths.input_source
+        );
+        debug!("output: {} (from {})", paths.output.display(), paths.output_source);
+        self.input = paths.input;
+        self.input_source = paths.input_source;
+        self.output = Some(paths.output);
+        if let Some(allow_nested_output) = cli.allow_nested_output {
+            self.allow_nested_output = allow_nested_output;
+        }
+        if let Some(recursive) = cli.recursive {
+            self.recursive = recursive;
+        }
+        if let Some(save_pattern) = &cli.save_pattern {
+            self.save_pattern = save_pattern.clone();
+        }
+        if !cli.session_token.is_empty() {
+            self.session_tokens = cli.session_token.clone();
+        }
+        if cli.tokens_file.is_some() {
+            self.tokens_file = cli.tokens_file.clone();
+        }
+        if !cli.format.is_empty() {
+            self.formats = cli.format.clone();
+        }
+        if !cli.difficulty.is_empty() {
+            self.difficulties = cli.difficulty.clone();
+        }
+        if !cli.song.is_empty() {
+            self.songs = cli.song.clone();
+        }
+        if let Some(case_sensitive) = cli.case_sensitive {
+            self.case_sensitive = case_sensitive;
+        }
+        if cli.min_acc.is_some() {
+            self.min_acc = cli.min_acc;
+        }
+        if cli.min_score.is_some() {
+            self.min_score = cli.min_score;
+        }
+        if let Some(fc_only) = cli.fc_only {
+            self.fc_only = fc_only;
+        }
+        if let Some(ap_only) = cli.ap_only {
+            self.ap_only = ap_only;
+        }
+        if !cli.players.is_empty() {
+            self.players = cli.players.clone();
+        }
+        if !cli.exclude_players.is_empty() {
+            self.exclude_players = cli.exclude_players.clone();
+        }
+        if let Some(exclude_songs) = &cli.exclude_songs {
+            self.exclude_songs = Some(exclude_songs.clone());
+        }
+        if !cli.sort_by.is_empty() {
+            self.sort_by = cli.sort_by.clone();
+        }
+        if !cli.columns.is_empty() {
+            self.columns = cli.columns.clone();
+        }
+        if cli.force {
+            self.existing = ExistingFiles::Overwrite;
+        } else if cli.skip_existing {
+            self.existing = ExistingFiles::Skip;
+        }
+        if let Some(delimiter) = cli.delimiter {
+            self.delimiter = delimiter;
+        }
+        if let Some(json_compact) = cli.json_compact {
+            self.json_compact = json_compact;
+        }
+        if let Some(jsonl_single) = cli.jsonl_single {
+            self.jsonl_single = jsonl_single;
+        }
+        if let Some(parquet_combined) = cli.parquet_combined {
+            self.parquet_combined = parquet_combined;
+        }
+        if cli.top.is_some() {
+            self.top = cli.top;
+        }
+        if let Some(xlsx_single_workbook) = cli.xlsx_single_workbook {
+            self.xlsx_single_workbook = xlsx_single_workbook;
+        }
+        if let Some(format) = cli.xlsx_acc_format {
+            self.xlsx_acc_format = format;
+        }
+        if let Some(no_xlsx_colors) = cli.no_xlsx_colors {
+            self.xlsx_colors = !no_xlsx_colors;
+        }
+        if let Some(compression) = cli.compress {
+            self.compress = compression;
+        }
+        if let Some(level) = cli.compress_level {
+            self.compress_level = level;
+        }
+        if cli.split_by.is_some() {
+            self.split_by = cli.split_by;
+        }
+        if let Some(keep_difficulty_column) = cli.keep_difficulty_column {
+            self.keep_difficulty_column = keep_difficulty_column;
+        }
+        if let Some(layout) = cli.layout {
+            self.layout = layout;
+        }
+        if let Some(group_by) = cli.group_by {
+            self.group_by = group_by;
+        }
+        if let Some(combined) = cli.combined {
+            self.combined = combined;
+        }
+        if let Some(combined_only) = cli.combined_only {
+            self.combined_only = combined_only;
+        }
+        if let Some(append) = cli.append {
+            self.append = append;
+        }
+        if !cli.dedupe_on.is_empty() {
+            self.dedupe_on = cli.dedupe_on.clone();
+        }
+        if cli.jobs.is_some() {
+            self.jobs = cli.jobs;
+        }
+        if let Some(low_memory) = cli.low_memory {
+            self.low_memory = low_memory;
+        }
+        if let Some(no_cache) = cli.no_cache {
+            self.cache = !no_cache;
+        }
+        if let Some(player_summary) = cli.player_summary {
+            self.player_summary = player_summary;
+        }
+        if let Some(fc_ap_summary) = cli.fc_ap_summary {
+            self.fc_ap_summary = fc_ap_summary;
+        }
+        if let Some(player_summary_out) = cli.player_summary_out {
+            self.player_summary_out = player_summary_out;
+        }
+        if let Some(latest_snapshot) = cli.latest_snapshot {
+            self.latest_snapshot = latest_snapshot;
+        }
+        if let Some(constants) = &cli.constants {
+            self.constants = Some(constants.clone());
+        }
+        if let Some(songlist) = &cli.songlist {
+            self.songlist = Some(songlist.clone());
+        }
+        if let Some(leaderboards) = cli.leaderboards {
+            self.leaderboards = leaderboards;
+        }
+        if cli.leaderboard_top.is_some() {
+            self.leaderboard_top = cli.leaderboard_top;
+        }
+        if let Some(global_leaderboard) = cli.global_leaderboard {
+            self.global_leaderboard = global_leaderboard;
+        }
+        if let Some(histograms) = cli.histograms {
+            self.histograms = histograms;
+        }
+        if let Some(histogram_bin_width) = cli.histogram_bin_width {
+            self.histogram_bin_width = histogram_bin_width;
+        }
+        if let Some(plots) = cli.plots {
+            self.plots = plots;
+        }
+        if let Some(plot_min_players) = cli.plot_min_players {
+            self.plot_min_players = plot_min_players;
+        }
+        if let Some(plot_width) = cli.plot_width {
+            self.plot_width = plot_width;
+        }
+        if let Some(plot_height) = cli.plot_height {
+            self.plot_height = plot_height;
+        }
+        if let Some(rks_timeline) = cli.rks_timeline {
+            self.rks_timeline = rks_timeline;
+        }
+        if let Some(history) = cli.history {
+            self.history = history;
+        }
+        if let Some(stats) = cli.stats {
+            self.stats = stats;
+        }
+        if let Some(timings) = cli.timings {
+            self.timings = timings;
+        }
+        if let Some(errors_json) = cli.errors_json {
+            self.errors_json = errors_json;
+        }
+        if let Some(allow_errors) = cli.allow_errors {
+            self.allow_errors = allow_errors;
+        }
+        if let Some(error_policy) = cli.error_policy {
+            self.error_policy = error_policy;
+        }
+        if cli.best_n.is_some() {
+            self.best_n = cli.best_n;
+        }
+        if let Some(push_acc) = cli.push_acc {
+            self.push_acc = push_acc;
+        }
+        if cli.push_acc_unplayed.is_some() {
+            self.push_acc_unplayed = cli.push_acc_unplayed;
+        }
+        if let Some(suggestions) = cli.suggestions {
+            self.suggestions = suggestions;
+        }
+        if let Some(suggestion_step) = cli.suggestion_step {
+            self.suggestion_step = suggestion_step;
+        }
+        if let Some(rks_drift) = cli.rks_drift {
+            self.rks_drift = rks_drift;
+        }
+        if let Some(drift_threshold) = cli.drift_threshold {
+            self.drift_threshold = drift_threshold;
+        }
+        if let Some(progress_out) = cli.progress_out {
+            self.progress_out = progress_out;
+        }
+        if let Some(profiles_out) = &cli.profiles_out {
+            self.profiles_out = Some(profiles_out.clone());
+        }
+        if let Some(with_avatar) = cli.with_avatar {
+            self.with_avatar = with_avatar;
+        }
+        if let Some(with_timestamp) = cli.with_timestamp {
+            self.with_timestamp = with_timestamp;
+        }
+        if let Some(with_percentiles) = cli.with_percentiles {
+            self.with_percentiles = with_percentiles;
+        }
+        if let Some(with_chart_id) = cli.with_chart_id {
+            self.with_chart_id = with_chart_id;
+        }
+        if let Some(revision_policy) = cli.revision_policy {
+            self.revision_policy = revision_policy;
+        }
+        if let Some(merge_case_insensitive) = cli.merge_case_insensitive {
+            self.merge_case_insensitive = merge_case_insensitive;
+        }
+        if let Some(report_normalization) = cli.report_normalization {
+            self.report_normalization = report_normalization;
+        }
+        if let Some(keep_invalid) = cli.keep_invalid {
+            self.keep_invalid = keep_invalid;
+        }
+        if let Some(require_summary) = cli.require_summary {
+            self.require_summary = require_summary;
+        }
+        if let Some(lenient_json) = cli.lenient_json {
+            self.lenient_json = lenient_json;
+        }
+        if let Some(no_player_id) = cli.no_player_id {
+            self.player_id = !no_player_id;
+        }
+        if let Some(strict) = cli.strict {
+            self.strict = strict;
+        }
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        self.output.as_deref().unwrap_or(Path::new(DEFAULT_OUTPUT))
+    }
+
+    /// `-o -` streams a single CSV to stdout instead of writing per-song files.
+    pub fn writes_to_stdout(&self) -> bool {
+        self.output_dir() == Path::new("-")
+    }
+
+    /// All session tokens to fetch cloud saves for, from the config and the
+    /// tokens file; blank lines and `#` comments in the file are skipped.
+    pub fn session_tokens(&self) -> Result<Vec<String>> {
+        let mut tokens = self.session_tokens.clone();
+        if let Some(path) = &self.tokens_file {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read tokens file: {}", path.display()))?;
+            tokens.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        Ok(tokens)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(self).context("Failed to serialize config")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathSource {
+    Cli,
+    Env,
+    ConfigFile,
+    PlatformDefault,
+    #[default]
+    BuiltinDefault,
+}
+
+impl fmt::Display for PathSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PathSource::Cli => "command line",
+            PathSource::Env => "environment",
+            PathSource::ConfigFile => "config file",
+            PathSource::PlatformDefault => "platform data directory",
+            PathSource::BuiltinDefault => "built-in default",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ResolvedPaths {
+    pub input: Vec<PathBuf>,
+    pub input_source: PathSource,
+    pub output: PathBuf,
+    pub output_source: PathSource,
+}
+
+/// Picks the input and output directories with precedence
+/// CLI > environment > config file > platform data dir (input only) > built-in default.
+pub fn resolve_paths(
+    cli: &Cli,
+    get_env: impl Fn(&str) -> Option<OsString>,
+    config: &Config,
+    platform_input: Option<PathBuf>,
+) -> ResolvedPaths {
+    let (input, input_source) = if !cli.input.is_empty() {
+        (cli.input.clone(), PathSource::Cli)
+    } else if let Some(value) = get_env(INPUT_ENV).filter(|value| !value.is_empty()) {
+        (env::split_paths(&value).collect(), PathSource::Env)
+    } else if !config.input.is_empty() {
+        (config.input.clone(), PathSource::ConfigFile)
+    } else if let Some(dir) = platform_input {
+        (vec![dir], PathSource::PlatformDefault)
+    } else {
+        (vec![PathBuf::from(DEFAULT_INPUT)], PathSource::BuiltinDefault)
+    };
+
+    let cli_output = if cli.stdout { Some(PathBuf::from("-")) } else { cli.output.clone() };
+    let (output, output_source) = if let Some(output) = cli_output {
+        (output, PathSource::Cli)
+    } else if let Some(value) = get_env(OUTPUT_ENV).filter(|value| !value.is_empty()) {
+        (PathBuf::from(value), PathSource::Env)
+    } else if let Some(output) = &config.output {
+        (output.clone(), PathSource::ConfigFile)
+    } else {
+        (PathBuf::from(DEFAULT_OUTPUT), PathSource::BuiltinDefault)
+    };
+
+    ResolvedPaths {
+        input,
+        input_source,
+        output,
+        output_source,
+    }
+}
+
+/// `<data dir>/phisavesong/saveData`, if that directory exists.
+fn platform_input_dir() -> Option<PathBuf> {
+    dirs::data_dir()
+        .map(|dir| dir.join("phisavesong").join(DEFAULT_INPUT))
+        .filter(|dir| dir.is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::parse_from(std::iter::once("phi-save-data").chain(args.iter().copied()))
+    }
+
+    fn env_with<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        move |name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| OsString::from(value))
+    }
+
+    fn config_with(input: &str, output: &str) -> Config {
+        Config { input: vec![PathBuf::from(input)], output: Some(PathBuf::from(output)), ..Config::default() }
+    }
+
+    const ENV: &[(&str, &str)] = &[(INPUT_ENV, "env_in"), (OUTPUT_ENV, "env_out")];
+
+    #[test]
+    fn cli_wins_over_everything() {
+        let paths = resolve_paths(
+            &cli(&["-i", "cli_in", "-o", "cli_out"]),
+            env_with(ENV),
+            &config_with("config_in", "config_out"),
+            Some(PathBuf::from("platform_in")),
+        );
+        assert_eq!((paths.input, paths.input_source), (vec![PathBuf::from("cli_in")], PathSource::Cli));
+        assert_eq!((paths.output, paths.output_source), (PathBuf::from("cli_out"), PathSource::Cli));
+    }
+
+    #[test]
+    fn stdout_counts_as_cli_output() {
+        let paths = resolve_paths(&cli(&["--stdout"]), env_with(ENV), &Config::default(), None);
+        assert_eq!((paths.output, paths.output_source), (PathBuf::from("-"), PathSource::Cli));
+    }
+
+    #[test]
+    fn env_wins_over_config_file() {
+        let paths = resolve_paths(
+            &cli(&[]),
+            env_with(ENV),
+            &config_with("config_in", "config_out"),
+            Some(PathBuf::from("platform_in")),
+        );
+        assert_eq!((paths.input, paths.input_source), (vec![PathBuf::from("env_in")], PathSource::Env));
+        assert_eq!((paths.output, paths.output_source), (PathBuf::from("env_out"), PathSource::Env));
+    }
+
+    #[test]
+    fn env_input_holds_several_directories() {
+        let joined = env::join_paths(["a", "b"]).unwrap();
+        let vars = [(INPUT_ENV, joined.to_str().unwrap())];
+        let paths = resolve_paths(&cli(&[]), env_with(&vars), &Config::default(), None);
+        assert_eq!(paths.input, vec![PathBuf::from("a"), PathBuf::from("b")]);
+    }
+
+    #[test]
+    fn empty_env_is_ignored() {
+        let vars = [(INPUT_ENV, ""), (OUTPUT_ENV, "")];
+        let paths = resolve_paths(&cli(&[]), env_with(&vars), &config_with("config_in", "config_out"), None);
+        assert_eq!(paths.input_source, PathSource::ConfigFile);
+        assert_eq!(paths.output_source, PathSource::ConfigFile);
+    }
+
+    #[test]
+    fn config_file_wins_over_platform_dir() {
+        let paths = resolve_paths(
+            &cli(&[]),
+            env_with(&[]),
+            &config_with("config_in", "config_out"),
+            Some(PathBuf::from("platform_in")),
+        );
+        assert_eq!((paths.input, paths.input_source), (vec![PathBuf::from("config_in")], PathSource::ConfigFile));
+        assert_eq!((paths.output, paths.output_source), (PathBuf::from("config_out"), PathSource::ConfigFile));
+    }
+
+    #[test]
+    fn platform_dir_is_used_for_input_only() {
+        let paths = resolve_paths(&cli(&[]), env_with(&[]), &Config::default(), Some(PathBuf::from("platform_in")));
+        assert_eq!(paths.input, vec![PathBuf::from("platform_in")]);
+        assert_eq!(paths.input_source, PathSource::PlatformDefault);
+        assert_eq!((paths.output, paths.output_source), (PathBuf::from(DEFAULT_OUTPUT), PathSource::BuiltinDefault));
+    }
+
+    #[test]
+    fn falls_back_to_builtin_defaults() {
+        let paths = resolve_paths(&cli(&[]), env_with(&[]), &Config::default(), None);
+        assert_eq!(paths.input, vec![PathBuf::from(DEFAULT_INPUT)]);
+        assert_eq!(paths.input_source, PathSource::BuiltinDefault);
+        assert_eq!((paths.output, paths.output_source), (PathBuf::from(DEFAULT_OUTPUT), PathSource::BuiltinDefault));
+    }
+
+    #[test]
+    fn switches_from_the_config_file_can_be_turned_off() {
+        let file = "recursive = true\nallow_nested_output = true\ncache = false\nstats = true\n";
+        let apply = |args: &[&str]| {
+            let mut config: Config = toml::from_str(file).unwrap();
+            config.apply_cli(&cli(args));
+            (config.recursive, config.allow_nested_output, config.cache, config.stats, config.history)
+        };
+        assert_eq!(apply(&[]), (true, true, false, true, false));
+        let off = ["--recursive=false", "--allow-nested-output=false", "--no-cache=false", "--stats=false"];
+        assert_eq!(apply(&off), (false, false, true, false, false));
+        assert_eq!(apply(&["-r", "--history"]), (true, true, false, true, true));
+    }
+}
@@ -0,0 +1,96 @@
+//! Chart constants, read from a `song_id,difficulty,constant` csv or tsv, to
+//! work out how much rks each play is worth.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{split_revision, Difficulty};
+
+/// Constants by song id without its chart suffix, then by difficulty.
+#[derive(Debug, Clone, Default)]
+pub struct ChartConstants {
+    by_song: HashMap<String, HashMap<&'static str, f64>>,
+}
+
+impl ChartConstants {
+    /// Reads a `.tsv` file as tab separated and anything else as csv. A first
+    /// line whose constant isn't a number is taken as a header.
+    pub fn load(path: &Path) -> Result<ChartConstants> {
+        let tsv = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("tsv"));
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(if tsv { b'\t' } else { b',' })
+            .from_path(path)
+            .with_context(|| format!("Failed to open constants file: {}", path.display()))?;
+        let mut constants = ChartConstants::default();
+        for (index, row) in reader.records().enumerate() {
+            let line = index + 1;
+            let row = row.with_context(|| format!("{}: failed to read line {}", path.display(), line))?;
+            let [song_id, difficulty, constant] = [0, 1, 2].map(|i| row.get(i).unwrap_or_default().trim());
+            if row.len() != 3 {
+                bail!("{}: line {}: expected song_id,difficulty,constant", path.display(), line);
+            }
+            let constant: f64 = match constant.parse() {
+                Ok(constant) => constant,
+                Err(_) if line == 1 => continue,
+                Err(_) => bail!("{}: line {}: invalid constant `{}`", path.display(), line, constant),
+            };
+            let Some(difficulty) = Difficulty::ALL.into_iter().find(|d| d.as_str().eq_ignore_ascii_case(difficulty))
+            else {
+                bail!("{}: line {}: unknown difficulty `{}`", path.display(), line, difficulty);
+            };
+            let (base, _) = split_revision(song_id);
+            constants.by_song.entry(base.to_string()).or_default().insert(difficulty.as_str(), constant);
+        }
+        Ok(constants)
+    }
+
+    /// The constant of a chart; revisions of a song share their constants.
+    pub fn get(&self, song_id: &str, difficulty: &str) -> Option<f64> {
+        let (base, _) = split_revision(song_id);
+        self.by_song.get(base)?.get(difficulty).copied()
+    }
+
+    /// Every chart listed, as its song id without chart suffix, difficulty
+    /// and constant.
+    pub fn charts(&self) -> impl Iterator<Item = (&str, &'static str, f64)> {
+        self.by_song.iter().flat_map(|(song_id, difficulties)| {
+            difficulties.iter().map(move |(&difficulty, &constant)| (song_id.as_str(), difficulty, constant))
+        })
+    }
+}
+
+/// The rks a play is worth: the full constant at 100% acc, falling off
+/// quadratically from there, and nothing below 70%.
+pub fn play_rks(constant: f64, acc: f64) -> f64 {
+    if acc < 70.0 {
+        0.0
+    } else {
+        constant * ((acc - 55.0) / 45.0).powi(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn below_70_is_worth_nothing() {
+        assert_eq!(play_rks(15.0, 69.99), 0.0);
+        assert_eq!(play_rks(15.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn full_acc_is_worth_the_constant() {
+        assert_eq!(play_rks(15.7, 100.0), 15.7);
+    }
+
+    #[test]
+    fn falls_off_quadratically() {
+        assert!((play_rks(15.0, 70.0) - 15.0 / 9.0).abs() < 1e-12);
+        assert!((play_rks(12.0, 77.5) - 3.0).abs() < 1e-12);
+    }
+}
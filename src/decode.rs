@@ -0,0 +1,339 @@
+//! Reads the game's own cloud save (`.save`): a zip archive whose entries are
+//! each a version byte followed by AES-256-CBC encrypted data. Only
+//! `gameRecord` is decoded; the summary is stored outside the file, so it is
+//! left missing unless it is decoded separately with [`decode_summary`].
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use anyhow::{bail, Context, Result};
+use zip::ZipArchive;
+
+use crate::lenient::Lenient;
+use crate::{SaveData, SaveInfo, ScoreRecord};
+
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+// `6Jaa0qVAJZuXkZCLiOa/Ax5tIZVu+taKUN1V1nqwkks=` in base64.
+const KEY: [u8; 32] = [
+    0xe8, 0x96, 0x9a, 0xd2, 0xa5, 0x40, 0x25, 0x9b, 0x97, 0x91, 0x90, 0x8b, 0x88, 0xe6, 0xbf, 0x03, 0x1e, 0x6d, 0x21,
+    0x95, 0x6e, 0xfa, 0xd6, 0x8a, 0x50, 0xdd, 0x55, 0xd6, 0x7a, 0xb0, 0x92, 0x4b,
+];
+// `Kk/wisgNYwcAV8WVGMgyUw==` in base64.
+const IV: [u8; 16] = [0x2a, 0x4f, 0xf0, 0x8a, 0xc8, 0x0d, 0x63, 0x07, 0x00, 0x57, 0xc5, 0x95, 0x18, 0xc8, 0x32, 0x53];
+
+/// The gameRecord layouts this module understands.
+const GAME_RECORD_VERSION: u8 = 1;
+
+/// Whether `bytes` look like a binary save rather than JSON.
+pub fn is_binary_save(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"PK\x03\x04")
+}
+
+pub fn decode_save(bytes: &[u8]) -> Result<SaveData> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).context("Not a valid save archive")?;
+    let mut entry = Vec::new();
+    archive
+        .by_name("gameRecord")
+        .context("Save archive has no gameRecord")?
+        .read_to_end(&mut entry)
+        .context("Failed to read gameRecord from the save archive")?;
+    let Some((&version, encrypted)) = entry.split_first() else {
+        bail!("gameRecord is empty");
+    };
+    if version != GAME_RECORD_VERSION {
+        bail!("Unsupported gameRecord version {} (expected {})", version, GAME_RECORD_VERSION);
+    }
+    let plain = Aes256CbcDec::new(&KEY.into(), &IV.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(encrypted)
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt gameRecord: wrong key or corrupt data"))?;
+    Ok(SaveData {
+        game_record: parse_game_record(&plain).context("Failed to parse the decrypted gameRecord")?,
+        save_info: SaveInfo::default(),
+        game_progress: None,
+        user: None,
+        settings: None,
+        lenient_fixes: Vec::new(),
+    })
+}
+
+/// The summary stored next to a cloud save: save version, challenge mode
+/// rank, rks, game version, avatar, then cleared / full combo / phi counts for
+/// each of EZ, HD, IN and AT.
+#[cfg(feature = "cloud")]
+pub fn decode_summary(data: &[u8]) -> Result<crate::Summary> {
+    let mut reader = Reader { data, position: 0 };
+    let save_version = reader.byte()?;
+    let challenge_mode_rank = reader.u16()?;
+    let ranking_score = reader.f32()?;
+    let game_version = reader.varint()?;
+    let avatar = reader.string()?;
+    let mut cleared = Vec::new();
+    let mut full_combo = Vec::new();
+    let mut phi = Vec::new();
+    for _ in 0..4 {
+        cleared.push(u32::from(reader.u16()?));
+        full_combo.push(u32::from(reader.u16()?));
+        phi.push(u32::from(reader.u16()?));
+    }
+    Ok(crate::Summary {
+        ranking_score: Some(f64::from(ranking_score)),
+        game_version: Some(i32::try_from(game_version)?),
+        cleared: Some(cleared),
+        full_combo: Some(full_combo),
+        phi: Some(phi),
+        challenge_mode_rank: Some(u32::from(challenge_mode_rank)),
+        avatar: Some(avatar),
+        save_version: Some(u32::from(save_version)),
+    })
+}
+
+/// Decrypted gameRecord: a varint song count, then per song its id, the
+/// byte length of its entry, a bitmask of the difficulties played, a bitmask
+/// of the full combos, and a little-endian i32 score and f32 acc for each
+/// difficulty played.
+fn parse_game_record(data: &[u8]) -> Result<HashMap<String, Vec<Option<Lenient<ScoreRecord>>>>> {
+    let mut reader = Reader { data, position: 0 };
+    let songs = reader.varint()?;
+    let mut game_record = HashMap::new();
+    for _ in 0..songs {
+        let song_id = reader.string()?;
+        let length = reader.varint()?;
+        let end = reader.position + length;
+        let played = reader.byte()?;
+        let full_combo = reader.byte()?;
+        let mut scores = Vec::new();
+        for level in 0..8 {
+            if played & (1 << level) == 0 {
+                continue;
+            }
+            scores.resize_with(level + 1, || None);
+            scores[level] = Some(Lenient(Ok(ScoreRecord {
+                score: reader.i32()?,
+                acc: f64::from(reader.f32()?),
+                fc: full_combo & (1 << level) != 0,
+            })));
+        }
+        if reader.position > end {
+            bail!("Entry for {} is longer than its declared {} bytes", song_id, length);
+        }
+        // Later game versions may append fields; skip what isn't understood.
+        reader.position = end;
+        game_record.insert(song_id, scores);
+    }
+    Ok(game_record)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8]> {
+        let Some(bytes) = self.data.get(self.position..self.position + count) else {
+            bail!("Unexpected end of data at byte {}", self.position);
+        };
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A little-endian base-128 integer, 7 bits per byte.
+    fn varint(&mut self) -> Result<usize> {
+        let mut value = 0;
+        for shift in (0..32).step_by(7) {
+            let byte = self.byte()?;
+            value |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Varint too long at byte {}", self.position)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.varint()?;
+        let position = self.position;
+        String::from_utf8(self.take(length)?.to_vec()).with_context(|| format!("Invalid UTF-8 at byte {}", position))
+    }
+
+    #[cfg(feature = "cloud")]
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use aes::cipher::BlockEncryptMut;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+    fn encrypt(version: u8, plain: &[u8]) -> Vec<u8> {
+        let mut entry = vec![version];
+        entry.extend(Aes256CbcEnc::new(&KEY.into(), &IV.into()).encrypt_padded_vec_mut::<Pkcs7>(plain));
+        entry
+    }
+
+    fn archive(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        [vec![text.len() as u8], text.as_bytes().to_vec()].concat()
+    }
+
+    /// A song's entry: played and full combo bitmasks, a score and acc per
+    /// difficulty played, then `extra` bytes a later game version might add.
+    fn song(song_id: &str, played: u8, full_combo: u8, scores: &[(i32, f32)], extra: &[u8]) -> Vec<u8> {
+        let mut body = vec![played, full_combo];
+        for (score, acc) in scores {
+            body.extend(score.to_le_bytes());
+            body.extend(acc.to_le_bytes());
+        }
+        body.extend(extra);
+        [string(song_id), vec![body.len() as u8], body].concat()
+    }
+
+    fn game_record(songs: &[Vec<u8>]) -> Vec<u8> {
+        [vec![songs.len() as u8], songs.concat()].concat()
+    }
+
+    /// `(score, acc, fc)` of each difficulty slot of `song_id`.
+    fn scores(save: &SaveData, song_id: &str) -> Vec<Option<(i32, f64, bool)>> {
+        save.game_record[song_id]
+            .iter()
+            .map(|entry| entry.as_ref().map(|record| record.0.as_ref().map(|r| (r.score, r.acc, r.fc)).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_the_records_of_each_difficulty_played() {
+        let record = game_record(&[
+            song("Song.Artist.0", 0b0101, 0b0100, &[(812345, 91.25), (1_000_000, 100.0)], &[]),
+            song("Other.Artist.0", 0b0010, 0b0000, &[(650000, 80.5)], &[]),
+        ]);
+        let bytes = archive(&[("gameRecord", encrypt(GAME_RECORD_VERSION, &record))]);
+        assert!(is_binary_save(&bytes));
+        let save = decode_save(&bytes).unwrap();
+        assert_eq!(
+            scores(&save, "Song.Artist.0"),
+            [Some((812345, 91.25, false)), None, Some((1_000_000, 100.0, true))]
+        );
+        assert_eq!(scores(&save, "Other.Artist.0"), [None, Some((650000, 80.5, false))]);
+        // The summary isn't in the file.
+        assert!(save.save_info.summary.ranking_score.is_none());
+        assert!(save.user.is_none() && save.settings.is_none());
+    }
+
+    #[test]
+    fn skips_fields_it_doesnt_know() {
+        let record = game_record(&[
+            song("Song.Artist.0", 0b0001, 0b0001, &[(990000, 99.5)], &[7, 7, 7]),
+            song("Other.Artist.0", 0b0001, 0b0000, &[(880000, 95.0)], &[]),
+        ]);
+        let bytes = archive(&[("user", vec![1, 2, 3]), ("gameRecord", encrypt(GAME_RECORD_VERSION, &record))]);
+        let save = decode_save(&bytes).unwrap();
+        assert_eq!(scores(&save, "Song.Artist.0"), [Some((990000, 99.5, true))]);
+        assert_eq!(scores(&save, "Other.Artist.0"), [Some((880000, 95.0, false))]);
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        let error = |bytes: &[u8]| format!("{:#}", decode_save(bytes).map(|_| ()).unwrap_err());
+        let record = game_record(&[song("Song.Artist.0", 0b0001, 0, &[(990000, 99.5)], &[])]);
+        assert_eq!(
+            error(&archive(&[("user", vec![1])])),
+            "Save archive has no gameRecord: specified file not found in archive"
+        );
+        assert_eq!(
+            error(&archive(&[("gameRecord", encrypt(2, &record))])),
+            "Unsupported gameRecord version 2 (expected 1)"
+        );
+        assert_eq!(
+            error(&archive(&[("gameRecord", vec![GAME_RECORD_VERSION, 1, 2, 3])])),
+            "Failed to decrypt gameRecord: wrong key or corrupt data"
+        );
+        let truncated = encrypt(GAME_RECORD_VERSION, &record[..record.len() - 2]);
+        let message = error(&archive(&[("gameRecord", truncated)]));
+        assert!(message.starts_with("Failed to parse the decrypted gameRecord: "), "{}", message);
+    }
+
+    /// Built apart from this module by `tests/fixtures/make_fixtures.py`.
+    const FIXTURE: &[u8] = include_bytes!("../tests/fixtures/cloud.save");
+
+    #[test]
+    fn decodes_the_fixture_save() {
+        assert!(is_binary_save(FIXTURE));
+        let save = decode_save(FIXTURE).unwrap();
+        assert_eq!(save.game_record.len(), 130);
+        assert_eq!(
+            scores(&save, "Glaciaxion.SunsetRay.0"),
+            [
+                Some((1_000_000, 100.0, true)),
+                Some((995112, f64::from(99.63f32), true)),
+                Some((962340, f64::from(97.41f32), false))
+            ]
+        );
+        assert_eq!(
+            scores(&save, "Rrhar'il.TeamGrimoire.0"),
+            [None, None, Some((883201, f64::from(94.12f32), false)), Some((712004, 86.5, false))]
+        );
+        let ap = Some((1_000_000, 100.0, true));
+        assert_eq!(scores(&save, "SpasModic(Haocore Mix).SULLIMaxi.0"), [None, None, None, ap]);
+        // The fifth slot is Legacy.
+        assert_eq!(
+            scores(&save, "Stasis.Maozon.0"),
+            [None, Some((1_000_000, 100.0, true)), None, None, Some((654321, 81.25, false))]
+        );
+        assert_eq!(scores(&save, "Song125.Fixture.0"), [Some((900125, 90.0, false))]);
+    }
+
+    #[cfg(feature = "cloud")]
+    #[test]
+    fn decodes_the_summary() {
+        let mut data = vec![6];
+        data.extend(438u16.to_le_bytes());
+        data.extend(14.5f32.to_le_bytes());
+        data.push(90);
+        data.extend(string("Glaciaxion"));
+        for count in 1..=12u16 {
+            data.extend(count.to_le_bytes());
+        }
+        let summary = decode_summary(&data).unwrap();
+        assert_eq!(summary.save_version, Some(6));
+        assert_eq!(summary.challenge_mode_rank, Some(438));
+        assert_eq!(summary.ranking_score, Some(14.5));
+        assert_eq!(summary.game_version, Some(90));
+        assert_eq!(summary.avatar.as_deref(), Some("Glaciaxion"));
+        assert_eq!(summary.cleared, Some(vec![1, 4, 7, 10]));
+        assert_eq!(summary.full_combo, Some(vec![2, 5, 8, 11]));
+        assert_eq!(summary.phi, Some(vec![3, 6, 9, 12]));
+        assert!(decode_summary(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! The `diff` subcommand: what changed between two saves of a player.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::path::Path;
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::config::Config;
+use crate::input::{SaveFile, SaveLocation};
+use crate::output::write_atomically;
+use crate::{process_save_file, Difficulty, ProcessedRecord};
+
+/// How a chart's record changed, in the order they are listed.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    /// Played for the first time.
+    New,
+    Improved,
+    /// Worse than before, which the game never does by itself; usually the
+    /// saves were given in the wrong order or belong to different players.
+    Lower,
+    /// In the old save only.
+    OnlyInOld,
+}
+
+impl Change {
+    fn as_str(self) -> &'static str {
+        match self {
+            Change::New => "new",
+            Change::Improved => "improved",
+            Change::Lower => "lower",
+            Change::OnlyInOld => "only_in_old",
+        }
+    }
+}
+
+/// A chart whose record differs between the two saves.
+struct Entry {
+    change: Change,
+    old: Option<ProcessedRecord>,
+    new: Option<ProcessedRecord>,
+}
+
+impl Entry {
+    fn record(&self) -> &ProcessedRecord {
+        self.new.as_ref().or(self.old.as_ref()).expect("an entry has at least one record")
+    }
+
+    fn gained(&self, flag: fn(&ProcessedRecord) -> bool) -> bool {
+        self.new.as_ref().is_some_and(flag) && !self.old.as_ref().is_some_and(flag)
+    }
+}
+
+type Records = BTreeMap<(String, String), ProcessedRecord>;
+
+/// Reads a save, or the save.json in a directory, into its rankingScore and
+/// its records by song id and difficulty.
+fn load(path: &Path, config: &Config) -> Result<(Option<f64>, Records)> {
+    let path = if path.is_dir() { path.join("save.json") } else { path.to_path_buf() };
+    let name = |name: Option<&OsStr>| name.map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let save_file = SaveFile {
+        player_id: name(path.parent().and_then(Path::file_name)),
+        snapshot: name(path.file_stem()),
+        location: SaveLocation::File(path),
+    };
+    let save = process_save_file(&save_file, config)?;
+    let records = save
+        .records
+        .into_iter()
+        .map(|record| ((record.song_id.clone(), record.difficulty.clone()), record))
+        .collect();
+    Ok((save.save_info.summary.ranking_score, records))
+}
+
+/// Pairs up the records of both saves, leaving out charts that didn't change.
+fn compare(mut old: Records, new: Records) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (key, new) in new {
+        let Some(old) = old.remove(&key) else {
+            entries.push(Entry { change: Change::New, old: None, new: Some(new) });
+            continue;
+        };
+        let ups = [new.score > old.score, new.acc > old.acc, new.fc && !old.fc, new.ap && !old.ap];
+        let downs = [new.score < old.score, new.acc < old.acc, old.fc && !new.fc, old.ap && !new.ap];
+        let change = if downs.contains(&true) {
+            Change::Lower
+        } else if ups.contains(&true) {
+            Change::Improved
+        } else {
+            continue;
+        };
+        entries.push(Entry { change, old: Some(old), new: Some(new) });
+    }
+    entries.extend(old.into_values().map(|old| Entry { change: Change::OnlyInOld, old: Some(old), new: None }));
+    entries.sort_by(|a, b| {
+        let (a_record, b_record) = (a.record(), b.record());
+        (a.change, &a_record.song_name, Difficulty::rank(&a_record.difficulty)).cmp(&(
+            b.change,
+            &b_record.song_name,
+            Difficulty::rank(&b_record.difficulty),
+        ))
+    });
+    entries
+}
+
+/// `old -> new (+delta)`, with `-` for a side that has no value.
+fn transition<T: Display>(old: Option<T>, new: Option<T>, delta: Option<String>) -> String {
+    let side = |value: Option<T>| value.map_or_else(|| "-".to_string(), |value| value.to_string());
+    match delta {
+        Some(delta) => format!("{} -> {} ({})", side(old), side(new), delta),
+        None => format!("{} -> {}", side(old), side(new)),
+    }
+}
+
+fn score_delta(entry: &Entry) -> Option<i32> {
+    Some(entry.new.as_ref()?.score - entry.old.as_ref()?.score)
+}
+
+fn acc_delta(entry: &Entry) -> Option<f64> {
+    Some(entry.new.as_ref()?.acc - entry.old.as_ref()?.acc)
+}
+
+fn ranking_score_line((old, new): (Option<f64>, Option<f64>)) -> String {
+    let delta = old.zip(new).map(|(old, new)| format!("{:+.4}", new - old));
+    transition(old.map(|rks| format!("{:.4}", rks)), new.map(|rks| format!("{:.4}", rks)), delta)
+}
+
+fn print_table(entries: &[Entry], ranking_score: (Option<f64>, Option<f64>)) {
+    println!("rankingScore: {}", ranking_score_line(ranking_score));
+    if entries.is_empty() {
+        println!("No records changed");
+        return;
+    }
+    let mut rows = vec![["status", "song", "difficulty", "score", "acc", "notes"].map(String::from)];
+    for entry in entries {
+        let record = entry.record();
+        let mut notes = Vec::new();
+        if entry.gained(|record| record.fc) {
+            notes.push("new FC");
+        }
+        if entry.gained(|record| record.ap) {
+            notes.push("new AP");
+        }
+        rows.push([
+            entry.change.as_str().to_string(),
+            record.song_name.clone(),
+            record.difficulty.clone(),
+            transition(
+                entry.old.as_ref().map(|old| old.score),
+                entry.new.as_ref().map(|new| new.score),
+                score_delta(entry).map(|delta| format!("{:+}", delta)),
+            ),
+            transition(
+                entry.old.as_ref().map(|old| format!("{:.2}", old.acc)),
+                entry.new.as_ref().map(|new| format!("{:.2}", new.acc)),
+                acc_delta(entry).map(|delta| format!("{:+.2}", delta)),
+            ),
+            notes.join(", "),
+        ]);
+    }
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        let cells: Vec<_> = row.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// One row per changed value, the first being the rankingScore.
+fn write_csv(entries: &[Entry], ranking_score: (Option<f64>, Option<f64>), path: &Path) -> Result<()> {
+    write_atomically(path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        writer.write_record(["status", "song_id", "song_name", "difficulty", "field", "old", "new", "delta"])?;
+        let (old, new) = ranking_score;
+        let status = match old.zip(new) {
+            Some((old, new)) if new > old => "improved",
+            Some((old, new)) if new < old => "lower",
+            Some(_) => "unchanged",
+            None => "missing",
+        };
+        let text = |value: Option<f64>| value.map_or_else(String::new, |value| value.to_string());
+        let delta = old.zip(new).map(|(old, new)| new - old);
+        writer.write_record([status, "", "", "", "ranking_score", &text(old), &text(new), &text(delta)])?;
+        for entry in entries {
+            let record = entry.record();
+            let values =
+                |value: fn(&ProcessedRecord) -> String| (entry.old.as_ref().map(value), entry.new.as_ref().map(value));
+            let fields = [
+                ("score", values(|record| record.score.to_string())),
+                ("acc", values(|record| record.acc.to_string())),
+                ("fc", values(|record| record.fc.to_string())),
+                ("ap", values(|record| record.ap.to_string())),
+            ];
+            for (field, (old, new)) in fields {
+                // Charts in both saves only list the values that changed.
+                if old.is_some() && old == new {
+                    continue;
+                }
+                let delta = match field {
+                    "score" => score_delta(entry).map(|delta| delta.to_string()),
+                    "acc" => acc_delta(entry).map(|delta| delta.to_string()),
+                    _ => None,
+                };
+                writer.write_record([
+                    entry.change.as_str(),
+                    &record.song_id,
+                    &record.song_name,
+                    &record.difficulty,
+                    field,
+                    old.as_deref().unwrap_or_default(),
+                    new.as_deref().unwrap_or_default(),
+                    delta.as_deref().unwrap_or_default(),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Prints what changed from the `old` save to the `new` one, and writes it to
+/// `out` as csv when given.
+pub fn run(old: &Path, new: &Path, out: Option<&Path>, config: &Config) -> Result<()> {
+    let (old_rks, old_records) = load(old, config)?;
+    let (new_rks, new_records) = load(new, config)?;
+    let entries = compare(old_records, new_records);
+    let only_in_old = entries.iter().filter(|entry| entry.change == Change::OnlyInOld).count();
+    if only_in_old > 0 {
+        warn!(
+            "{} records are in {} but not in {}; check that the saves are of one player and in order",
+            only_in_old,
+            old.display(),
+            new.display()
+        );
+    }
+    print_table(&entries, (old_rks, new_rks));
+    if let Some(out) = out {
+        write_csv(&entries, (old_rks, new_rks), out)?;
+        info!("Wrote {} changed records to {}", entries.len(), out.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+//! `--rks-drift`: how far the rks computed from the records is from the one
+//! stored in each save.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::best::{computed_rks, ranked_plays, BEST_SLOTS};
+use crate::config::Config;
+use crate::constants::play_rks;
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::{ProcessedRecord, ProcessedSave};
+
+pub const RKS_DRIFT_FILE: &str = "rks_drift.csv";
+
+/// Plays listed for each save whose delta is over the threshold.
+const TOP_PLAYS: usize = 5;
+
+/// How far a constant is moved to see how much a play's constant weighs on
+/// the delta; the constants tables are given to one decimal.
+const CONSTANT_STEP: f64 = 0.1;
+
+struct Drift {
+    player_id: String,
+    stored: Option<f64>,
+    computed: f64,
+    /// Whether too few of the charts played have a constant to trust
+    /// `computed`.
+    insufficient: bool,
+    /// The plays whose constant, moved by [`CONSTANT_STEP`] towards the
+    /// stored rks, closes the most of the delta, with how much it closes.
+    /// Only found for saves over the threshold.
+    top_plays: Vec<(ProcessedRecord, f64)>,
+}
+
+impl Drift {
+    fn delta(&self) -> Option<f64> {
+        match (self.insufficient, self.stored) {
+            (false, Some(stored)) => Some(self.computed - stored),
+            _ => None,
+        }
+    }
+}
+
+fn drift(save_file: &SaveFile, save: &ProcessedSave, threshold: f64) -> Drift {
+    let records = save.records.clone();
+    let uncovered = records.iter().any(|record| record.chart_constant.is_none());
+    let plays = ranked_plays(records.clone());
+    let mut drift = Drift {
+        player_id: save_file.player_id.clone(),
+        stored: save.save_info.summary.ranking_score,
+        computed: computed_rks(records),
+        // Charts without a constant could be among the best ones unless
+        // enough charts with one fill the best slots.
+        insufficient: uncovered && plays.len() < BEST_SLOTS,
+        top_plays: Vec::new(),
+    };
+    if let Some(delta) = drift.delta().filter(|delta| delta.abs() > threshold) {
+        drift.top_plays = contributing_plays(plays, delta);
+    }
+    drift
+}
+
+/// The plays contributing most to `delta`, the computed rks of `plays` less
+/// the stored one: each play's constant is moved by [`CONSTANT_STEP`] in the
+/// direction that brings the computed rks towards the stored one, and the
+/// plays are ranked by how much of the delta that closes. This is where an
+/// outdated constant would show, rather than simply the plays worth the most:
+/// an All Perfect counts in a phi slot too, and a play just below the best 27
+/// can move into them.
+fn contributing_plays(plays: Vec<ProcessedRecord>, delta: f64) -> Vec<(ProcessedRecord, f64)> {
+    let step = if delta > 0.0 { -CONSTANT_STEP } else { CONSTANT_STEP };
+    let computed = computed_rks(plays.clone());
+    let mut contributions: Vec<_> = (0..plays.len())
+        .filter_map(|index| {
+            let constant = plays[index].chart_constant? + step;
+            let mut moved = plays.clone();
+            moved[index].chart_constant = Some(constant);
+            moved[index].play_rks = Some(play_rks(constant, moved[index].acc));
+            let closed = delta.abs() - (delta + computed_rks(moved) - computed).abs();
+            (closed > 0.0).then(|| (plays[index].clone(), closed))
+        })
+        .collect();
+    contributions.sort_by(|(a, a_closed), (b, b_closed)| {
+        b_closed.total_cmp(a_closed).then_with(|| (&a.song_name, &a.difficulty).cmp(&(&b.song_name, &b.difficulty)))
+    });
+    contributions.truncate(TOP_PLAYS);
+    contributions
+}
+
+fn describe((play, closed): &(ProcessedRecord, f64)) -> String {
+    format!(
+        "{} {} {:.2}% (constant {}: {:.4})",
+        play.song_name,
+        play.difficulty,
+        play.acc,
+        play.chart_constant.unwrap_or_default(),
+        closed
+    )
+}
+
+/// Writes one row per save with its stored and computed rks and their
+/// difference, largest difference first. Saves off by more than
+/// --drift-threshold also list the plays contributing most to the difference,
+/// see [`contributing_plays`].
+pub fn write_rks_drift(saves: &[(&SaveFile, ProcessedSave)], config: &Config, output_path: &Path) -> Result<()> {
+    let mut drifts: Vec<_> =
+        saves.iter().map(|(save_file, save)| drift(save_file, save, config.drift_threshold)).collect();
+    // Saves without a delta go last.
+    drifts.sort_by(|a, b| match (a.delta(), b.delta()) {
+        (Some(a), Some(b)) => b.abs().total_cmp(&a.abs()),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+
+    write_atomically(output_path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        writer.write_record(["player_id", "ranking_score", "computed_rks", "delta", "status", "top_plays"])?;
+        for drift in &drifts {
+            let delta = drift.delta();
+            let status = match delta {
+                _ if drift.insufficient => "insufficient data",
+                None => "missing rankingScore",
+                Some(delta) if delta.abs() > config.drift_threshold => "drift",
+                Some(_) => "ok",
+            };
+            let top_plays = if status == "drift" {
+                drift.top_plays.iter().map(describe).collect::<Vec<_>>().join("; ")
+            } else {
+                String::new()
+            };
+            writer.write_record([
+                drift.player_id.clone(),
+                drift.stored.map_or_else(String::new, |rks| format!("{:.4}", rks)),
+                format!("{:.4}", drift.computed),
+                delta.map_or_else(String::new, |delta| format!("{:+.4}", delta)),
+                status.to_string(),
+                top_plays,
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(song_name: &str, constant: f64, acc: f64) -> ProcessedRecord {
+        ProcessedRecord {
+            song_id: song_name.to_string(),
+            song_name: song_name.to_string(),
+            difficulty: "IN".to_string(),
+            acc,
+            chart_constant: Some(constant),
+            play_rks: Some(play_rks(constant, acc)),
+            ..ProcessedRecord::default()
+        }
+    }
+
+    #[test]
+    fn plays_are_ranked_by_how_much_of_the_delta_their_constant_closes() {
+        // B is worth 16.5 * (44 / 45)^2 = 15.77 against A's 15, but A is an
+        // All Perfect in a phi slot too: 0.1 off its constant takes 0.2 / 30
+        // off the rks, and 0.1 off B's only 0.1 * (44 / 45)^2 / 30.
+        let plays = ranked_plays(vec![play("A", 15.0, 100.0), play("B", 16.5, 99.0), play("C", 12.0, 60.0)]);
+        let contributions = contributing_plays(plays, 0.05);
+        let names: Vec<_> = contributions.iter().map(|(play, _)| play.song_name.as_str()).collect();
+        assert_eq!(names, ["A", "B"]);
+        assert!((contributions[0].1 - 0.2 / 30.0).abs() < 1e-9);
+        assert!((contributions[1].1 - 0.1 * (44.0f64 / 45.0).powi(2) / 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn constant_moves_towards_the_stored_rks() {
+        // Below the stored rks, raising C's constant still leaves it under 70%
+        // acc and worth nothing, so it closes none of the delta.
+        let plays = ranked_plays(vec![play("A", 15.0, 100.0), play("C", 12.0, 60.0)]);
+        let contributions = contributing_plays(plays, -0.05);
+        assert_eq!(contributions.len(), 1);
+        assert_eq!(contributions[0].0.song_name, "A");
+        assert_eq!(contributions[0].0.chart_constant, Some(15.0));
+        assert!((contributions[0].1 - 0.2 / 30.0).abs() < 1e-9);
+    }
+}
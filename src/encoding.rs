@@ -0,0 +1,138 @@
+//! Saves as text: UTF-8 with or without a byte order mark, as some Windows
+//! tools write them, or UTF-16 in either byte order, told apart by its byte
+//! order mark.
+
+use std::fmt;
+
+use anyhow::{anyhow, bail, Result};
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 with a byte order mark",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+        })
+    }
+}
+
+impl Encoding {
+    /// The encoding named by the byte order mark `start` begins with, UTF-8
+    /// if there is none.
+    pub fn sniff(start: &[u8]) -> Encoding {
+        if start.starts_with(UTF8_BOM) {
+            Encoding::Utf8Bom
+        } else if start.starts_with(UTF16LE_BOM) {
+            Encoding::Utf16Le
+        } else if start.starts_with(UTF16BE_BOM) {
+            Encoding::Utf16Be
+        } else {
+            Encoding::Utf8
+        }
+    }
+
+    /// Length of the byte order mark.
+    pub fn bom_len(self) -> usize {
+        match self {
+            Encoding::Utf8 => 0,
+            Encoding::Utf8Bom => UTF8_BOM.len(),
+            Encoding::Utf16Le | Encoding::Utf16Be => UTF16LE_BOM.len(),
+        }
+    }
+}
+
+/// `bytes` as text, without its byte order mark.
+pub fn decode_text(mut bytes: Vec<u8>) -> Result<String> {
+    let encoding = Encoding::sniff(&bytes);
+    match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => {
+            bytes.drain(..encoding.bom_len());
+            String::from_utf8(bytes).map_err(|err| {
+                let hint = if encoding == Encoding::Utf8 { " (no byte order mark found)" } else { "" };
+                anyhow!("Not valid {}{}: {}", encoding, hint, err.utf8_error())
+            })
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let pairs = bytes[encoding.bom_len()..].chunks_exact(2);
+            if !pairs.remainder().is_empty() {
+                bail!("Not valid {} (found from its byte order mark): odd number of bytes", encoding);
+            }
+            let units = pairs.map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if encoding == Encoding::Utf16Le {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            });
+            match char::decode_utf16(units).collect::<Result<String, _>>() {
+                Ok(text) => Ok(text),
+                Err(err) => bail!("Not valid {} (found from its byte order mark): {}", encoding, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"{"song":"Spasmodic.姜米條.0"}"#;
+
+    fn utf16(bom: &[u8], text: &str, to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = bom.to_vec();
+        bytes.extend(text.encode_utf16().flat_map(to_bytes));
+        bytes
+    }
+
+    #[test]
+    fn decodes_each_encoding() {
+        let mut utf8_bom = UTF8_BOM.to_vec();
+        utf8_bom.extend(TEXT.as_bytes());
+        assert_eq!(decode_text(TEXT.as_bytes().to_vec()).unwrap(), TEXT);
+        assert_eq!(decode_text(utf8_bom).unwrap(), TEXT);
+        assert_eq!(decode_text(utf16(UTF16LE_BOM, TEXT, u16::to_le_bytes)).unwrap(), TEXT);
+        assert_eq!(decode_text(utf16(UTF16BE_BOM, TEXT, u16::to_be_bytes)).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn binary_file_is_not_text() {
+        let err = decode_text(vec![0x50, 0x4B, 0x03, 0x04, 0xC3, 0x28]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Not valid UTF-8 (no byte order mark found): invalid utf-8 sequence of 1 bytes from index 4"
+        );
+    }
+
+    #[test]
+    fn odd_length_utf16_is_rejected() {
+        let mut bytes = utf16(UTF16LE_BOM, TEXT, u16::to_le_bytes);
+        bytes.push(b'}');
+        let err = decode_text(bytes).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Not valid UTF-16LE (found from its byte order mark): odd number of bytes"
+        );
+    }
+
+    #[test]
+    fn unpaired_surrogate_is_rejected() {
+        let bytes = [UTF16BE_BOM, &[0xD8, 0x00, 0x00, 0x41]].concat();
+        let err = decode_text(bytes).unwrap_err();
+        assert!(err.to_string().starts_with("Not valid UTF-16BE (found from its byte order mark): "), "{}", err);
+    }
+}
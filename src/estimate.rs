@@ -0,0 +1,254 @@
+//! The `estimate-constants` subcommand: rough constants for charts missing
+//! from --constants, worked out from how the players who played them did.
+//!
+//! A play that counts towards a player's rks is worth about that rks, so each
+//! play implies a constant of `rks / ((acc - 55) / 45)^2`. Those are biased
+//! (not every play is a top play), so the median per chart is calibrated with
+//! a least-squares line against the charts whose constant is known.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+
+use crate::config::Config;
+use crate::exit::SavesFailed;
+use crate::failures::{self, SaveFailure};
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::run_summary::RunSummary;
+use crate::{process_save_file, split_revision, Difficulty};
+
+pub const ESTIMATED_CONSTANTS_FILE: &str = "estimated_constants.csv";
+
+/// Written in every row, so an estimate can't pass for an official constant.
+const SOURCE: &str = "estimated";
+
+/// Known charts needed to fit the calibration line.
+const MIN_CALIBRATION_CHARTS: usize = 3;
+
+/// The plays of one chart, by song id without chart suffix and difficulty.
+#[derive(Default)]
+struct Chart {
+    song_name: String,
+    /// The constant each player's best play here implies.
+    implied: HashMap<String, f64>,
+}
+
+impl Chart {
+    fn median(&self) -> f64 {
+        let mut implied: Vec<_> = self.implied.values().copied().collect();
+        implied.sort_by(f64::total_cmp);
+        let middle = implied.len() / 2;
+        if implied.len() % 2 == 0 {
+            (implied[middle - 1] + implied[middle]) / 2.0
+        } else {
+            implied[middle]
+        }
+    }
+
+    /// Standard error of the mean of the implied constants.
+    fn standard_error(&self) -> f64 {
+        let n = self.implied.len() as f64;
+        let mean = self.implied.values().sum::<f64>() / n;
+        let variance = self.implied.values().map(|c| (c - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+        (variance / n).sqrt()
+    }
+}
+
+/// The constant a play implies if it was worth exactly the player's rks;
+/// plays below 70% acc are worth nothing and imply nothing.
+fn implied_constant(ranking_score: f64, acc: f64) -> Option<f64> {
+    (acc >= 70.0 && ranking_score > 0.0).then(|| ranking_score / ((acc - 55.0) / 45.0).powi(2))
+}
+
+fn collect_charts(
+    save_files: &[SaveFile],
+    config: &Config,
+    summary: &mut RunSummary,
+) -> Result<BTreeMap<(String, String), Chart>> {
+    let mut charts: BTreeMap<(String, String), Chart> = BTreeMap::new();
+    for save_file in save_files {
+        let save = match process_save_file(save_file, config) {
+            Ok(save) => save,
+            Err(err) => {
+                let err = config.error_policy.handle(err, summary)?;
+                warn!("{}: skipped: {:#}", save_file.player_id, err);
+                summary.failed_saves.push(SaveFailure::new(save_file, &err));
+                continue;
+            }
+        };
+        for record in save.records {
+            let Some(implied) = implied_constant(record.ranking_score, record.acc) else {
+                continue;
+            };
+            let key = (split_revision(&record.song_id).0.to_string(), record.difficulty.clone());
+            let chart = charts.entry(key).or_default();
+            chart.song_name = record.song_name.clone();
+            // Several snapshots of a player count once, by their best play.
+            let best = chart.implied.entry(record.player_id.clone()).or_insert(implied);
+            *best = best.min(implied);
+        }
+    }
+    Ok(charts)
+}
+
+/// Least-squares `known = intercept + slope * implied` over `points`, and the
+/// standard deviation of its residuals.
+fn fit(points: &[(f64, f64)]) -> Option<(f64, f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>() / sxx;
+    let intercept = mean_y - slope * mean_x;
+    let residuals: f64 = points.iter().map(|(x, y)| (y - intercept - slope * x).powi(2)).sum();
+    Some((intercept, slope, (residuals / (n - 2.0).max(1.0)).sqrt()))
+}
+
+/// Writes `path` with an estimate for every chart without a known constant
+/// that at least `min_samples` players have played above 70%.
+pub fn run(save_files: &[SaveFile], config: &Config, min_samples: usize, path: &Path) -> Result<()> {
+    if config.constants.is_none() {
+        bail!("estimate-constants calibrates against known chart constants, which need --constants");
+    }
+    let mut summary = RunSummary::default();
+    let charts = collect_charts(save_files, config, &mut summary)?;
+    let enough = |chart: &&Chart| chart.implied.len() >= min_samples;
+
+    let points: Vec<_> = charts
+        .iter()
+        .filter(|(_, chart)| enough(chart))
+        .filter_map(|((song_id, difficulty), chart)| {
+            Some((chart.median(), config.chart_constants.get(song_id, difficulty)?))
+        })
+        .collect();
+    if points.len() < MIN_CALIBRATION_CHARTS {
+        bail!(
+            "Only {} charts with a known constant have {} or more players, at least {} are needed to calibrate",
+            points.len(),
+            min_samples,
+            MIN_CALIBRATION_CHARTS
+        );
+    }
+    let Some((intercept, slope, residual)) = fit(&points) else {
+        bail!("The charts with known constants all imply the same constant, nothing to calibrate with");
+    };
+    info!(
+        "Calibrated on {} charts: constant = {:.3} + {:.3} * implied, residual {:.2}",
+        points.len(),
+        intercept,
+        slope,
+        residual
+    );
+
+    let mut rows = Vec::new();
+    let mut omitted = 0;
+    for ((song_id, difficulty), chart) in &charts {
+        if config.chart_constants.get(song_id, difficulty).is_some() {
+            continue;
+        }
+        if !enough(&chart) {
+            debug!("{} {}: {} players, too few for an estimate", song_id, difficulty, chart.implied.len());
+            omitted += 1;
+            continue;
+        }
+        let estimate = intercept + slope * chart.median();
+        let spread = (residual.powi(2) + (slope * chart.standard_error()).powi(2)).sqrt();
+        rows.push((chart, song_id, difficulty, estimate, spread));
+    }
+    rows.sort_by(|a, b| (&a.0.song_name, Difficulty::rank(a.2)).cmp(&(&b.0.song_name, Difficulty::rank(b.2))));
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomically(path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        writer.write_record([
+            "song_id",
+            "song_name",
+            "difficulty",
+            "estimated_constant",
+            "samples",
+            "spread",
+            "source",
+        ])?;
+        for (chart, song_id, difficulty, estimate, spread) in &rows {
+            writer.write_record([
+                song_id.as_str(),
+                &chart.song_name,
+                difficulty,
+                &format!("{:.2}", estimate),
+                &chart.implied.len().to_string(),
+                &format!("{:.2}", spread),
+                SOURCE,
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    })?;
+    info!(
+        "Estimated {} charts into {}, omitted {} with fewer than {} players; these are not official constants",
+        rows.len(),
+        path.display(),
+        omitted,
+        min_samples
+    );
+    if !summary.failed_saves.is_empty() {
+        failures::print(&summary.failed_saves);
+        if !config.allow_errors {
+            return Err(SavesFailed { failed: summary.failed_saves.len(), found: save_files.len() }.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn implied_constant_inverts_play_rks() {
+        assert_close(implied_constant(12.0, 100.0).unwrap(), 12.0);
+        // (70 - 55) / 45 = 1 / 3, so the play is worth a ninth of the constant.
+        assert_close(implied_constant(12.0, 70.0).unwrap(), 108.0);
+        assert_close(implied_constant(14.4, 85.0).unwrap(), 14.4 / (4.0 / 9.0));
+    }
+
+    #[test]
+    fn plays_below_70_or_without_rks_imply_nothing() {
+        assert_eq!(implied_constant(12.0, 69.99), None);
+        assert_eq!(implied_constant(0.0, 95.0), None);
+    }
+
+    #[test]
+    fn fit_of_points_on_a_line_has_no_spread() {
+        let (intercept, slope, spread) = fit(&[(1.0, 3.0), (2.0, 5.0), (3.0, 7.0)]).unwrap();
+        assert_close(intercept, 1.0);
+        assert_close(slope, 2.0);
+        assert_close(spread, 0.0);
+    }
+
+    #[test]
+    fn fit_spread_is_the_residual_standard_deviation() {
+        // Flat at 1/3; residuals -1/3, 2/3, -1/3 over 3 - 2 degrees of freedom.
+        let (intercept, slope, spread) = fit(&[(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)]).unwrap();
+        assert_close(intercept, 1.0 / 3.0);
+        assert_close(slope, 0.0);
+        assert_close(spread, (2.0f64 / 3.0).sqrt());
+    }
+
+    #[test]
+    fn fit_needs_two_distinct_x() {
+        assert!(fit(&[(2.0, 1.0), (2.0, 3.0)]).is_none());
+    }
+}
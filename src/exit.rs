@@ -0,0 +1,100 @@
+//! The exit status of a run, for scripts to tell outcomes apart.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Listed at the end of `--help`.
+pub const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  success
+  1  fatal error, e.g. unreadable config or failing to write the output, or
+     any error with --error-policy fail-fast
+  2  invalid command line
+  3  some saves could not be read; the others were exported (see --allow-errors)
+  4  no saves found";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Success,
+    Fatal,
+    SavesFailed,
+    NoSaves,
+}
+
+impl Exit {
+    /// 2 is left to clap, which exits with it for an invalid command line.
+    fn code(self) -> u8 {
+        match self {
+            Exit::Success => 0,
+            Exit::Fatal => 1,
+            Exit::SavesFailed => 3,
+            Exit::NoSaves => 4,
+        }
+    }
+
+    /// The exit status for the error a run ended with.
+    pub fn of(err: &anyhow::Error) -> Exit {
+        if err.is::<SavesFailed>() {
+            Exit::SavesFailed
+        } else if err.is::<NoSaves>() {
+            Exit::NoSaves
+        } else {
+            Exit::Fatal
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit.code())
+    }
+}
+
+/// Some saves could not be read; everything else was written.
+#[derive(Debug)]
+pub struct SavesFailed {
+    pub failed: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for SavesFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} saves could not be read (use --allow-errors to exit successfully anyway)",
+            self.failed, self.found
+        )
+    }
+}
+
+impl std::error::Error for SavesFailed {}
+
+/// No save matched in any of the inputs, or every player was filtered out.
+#[derive(Debug)]
+pub struct NoSaves {
+    pub input: Vec<PathBuf>,
+    pub save_pattern: String,
+    /// Directories looked in, the input directories themselves not counted.
+    pub dirs_scanned: usize,
+    pub players_skipped: usize,
+}
+
+impl fmt::Display for NoSaves {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let input: Vec<_> = self.input.iter().map(|path| path.display().to_string()).collect();
+        write!(
+            f,
+            "No saves matching `{}` found in {} ({} directories scanned",
+            self.save_pattern,
+            input.join(", "),
+            self.dirs_scanned
+        )?;
+        if self.players_skipped > 0 {
+            write!(f, ", {} players skipped for --players / --exclude-players", self.players_skipped)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl std::error::Error for NoSaves {}
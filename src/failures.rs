@@ -0,0 +1,143 @@
+//! Saves that could not be read, listed at the end of the run and written to
+//! `errors.json` with --errors-json, and whether a run goes on past them.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::run_summary::RunSummary;
+
+pub const ERRORS_FILE: &str = "errors.json";
+
+/// What a run does on a save that can't be read, a malformed or invalid
+/// record, or an output file that can't be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorPolicy {
+    /// Report it, count it in the run summary and go on.
+    Continue,
+    /// Stop the run with it.
+    FailFast,
+}
+
+impl ErrorPolicy {
+    /// Returns `err` as the error to stop the run with, or under `Continue`
+    /// counts it in `summary` and hands it back to be reported.
+    pub fn handle(self, err: anyhow::Error, summary: &mut RunSummary) -> Result<anyhow::Error> {
+        match self {
+            ErrorPolicy::Continue => {
+                summary.continued_errors += 1;
+                Ok(err)
+            }
+            ErrorPolicy::FailFast => Err(err),
+        }
+    }
+}
+
+/// How far reading a save got before it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// The file or archive entry could not be opened or read.
+    Read,
+    /// A binary save could not be decoded.
+    Decode,
+    /// The save isn't valid UTF-8, or not JSON of the expected shape.
+    Parse,
+    /// The save was read but rejected, e.g. for --require-summary.
+    Check,
+}
+
+impl Phase {
+    fn as_str(self) -> &'static str {
+        match self {
+            Phase::Read => "read",
+            Phase::Decode => "decode",
+            Phase::Parse => "parse",
+            Phase::Check => "check",
+        }
+    }
+
+    /// An error that [`Phase::of`] attributes to this phase.
+    pub fn error(self, message: String) -> PhaseError {
+        PhaseError { phase: self, message }
+    }
+
+    /// The phase an error of reading a save happened in. Errors not made by
+    /// [`Phase::error`] come from opening or reading the file.
+    pub fn of(err: &anyhow::Error) -> Phase {
+        err.downcast_ref::<PhaseError>().map_or(Phase::Read, |err| err.phase)
+    }
+}
+
+/// Shows as its message alone, so tagging an error with its phase doesn't
+/// change how it reads.
+#[derive(Debug)]
+pub struct PhaseError {
+    phase: Phase,
+    message: String,
+}
+
+impl fmt::Display for PhaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PhaseError {}
+
+#[derive(Debug, Serialize)]
+pub struct SaveFailure {
+    pub path: String,
+    pub player_id: String,
+    pub phase: Phase,
+    pub error: String,
+}
+
+impl SaveFailure {
+    pub fn new(save_file: &SaveFile, err: &anyhow::Error) -> Self {
+        SaveFailure {
+            path: save_file.location.to_string(),
+            player_id: save_file.player_id.clone(),
+            phase: Phase::of(err),
+            error: format!("{:#}", err),
+        }
+    }
+}
+
+/// Lists the failures as a table, one save per row.
+pub fn print(failures: &[SaveFailure]) {
+    warn!("{} saves could not be read:", failures.len());
+    let rows: Vec<[&str; 3]> = failures
+        .iter()
+        .map(|failure| [failure.phase.as_str(), failure.player_id.as_str(), failure.path.as_str()])
+        .collect();
+    let mut widths = ["phase".len(), "player".len(), "path".len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let [phase, player, path] = widths;
+    warn!("  {:<phase$}  {:<player$}  {:<path$}  error", "phase", "player", "path");
+    for (row, failure) in rows.iter().zip(failures) {
+        warn!("  {:<phase$}  {:<player$}  {:<path$}  {}", row[0], row[1], row[2], failure.error);
+    }
+}
+
+pub fn write(failures: &[SaveFailure], path: &Path) -> Result<()> {
+    write_atomically(path, |temp| {
+        let mut writer = BufWriter::new(File::create(temp)?);
+        serde_json::to_writer_pretty(&mut writer, failures)?;
+        writer.flush()?;
+        Ok(())
+    })
+}
@@ -0,0 +1,128 @@
+//! `--fc-ap-summary`: each player's full combo and All Perfect counts per
+//! difficulty, checked against the counts the save reports itself.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::player_summary::COUNTED;
+use crate::{captured_at, ProcessedSave, Summary};
+
+pub const FC_AP_SUMMARY_FILE: &str = "fc_ap_summary.csv";
+
+#[derive(Default)]
+struct Counts {
+    /// Per difficulty in [`COUNTED`] order.
+    fc: [u32; COUNTED.len()],
+    ap: [u32; COUNTED.len()],
+}
+
+impl Counts {
+    fn total_fc(&self) -> u32 {
+        self.fc.iter().sum()
+    }
+
+    fn total_ap(&self) -> u32 {
+        self.ap.iter().sum()
+    }
+}
+
+#[derive(Default)]
+struct Player {
+    /// Whether each chart was full combo and All Perfect in the player's
+    /// best play of it, by song id and difficulty.
+    charts: HashMap<(String, String), (i32, f64, bool, bool)>,
+    /// The summary of the most recently captured save, and when it was.
+    latest: Option<(String, Summary)>,
+}
+
+impl Player {
+    fn counts(&self) -> Counts {
+        let mut counts = Counts::default();
+        for ((_, difficulty), &(_, _, fc, ap)) in &self.charts {
+            let Some(index) = COUNTED.iter().position(|counted| counted.as_str() == difficulty) else {
+                continue;
+            };
+            // An All Perfect is also a full combo, as the game counts them.
+            counts.fc[index] += u32::from(fc || ap);
+            counts.ap[index] += u32::from(ap);
+        }
+        counts
+    }
+}
+
+/// The counts that differ from the latest save's summary, e.g.
+/// `IN_fc (summary 53)`, `missing` when the summary has none, or empty.
+fn mismatch(counts: &Counts, summary: Option<&Summary>) -> String {
+    let (Some(full_combo), Some(phi)) =
+        (summary.and_then(|summary| summary.full_combo.as_ref()), summary.and_then(|summary| summary.phi.as_ref()))
+    else {
+        return "missing".to_string();
+    };
+    let mut mismatches = Vec::new();
+    for (index, difficulty) in COUNTED.iter().enumerate() {
+        for (kind, computed, reported) in
+            [("fc", counts.fc[index], full_combo.get(index)), ("ap", counts.ap[index], phi.get(index))]
+        {
+            match reported {
+                Some(&reported) if reported == computed => {}
+                Some(reported) => mismatches.push(format!("{}_{} (summary {})", difficulty.as_str(), kind, reported)),
+                None => mismatches.push(format!("{}_{} (summary missing)", difficulty.as_str(), kind)),
+            }
+        }
+    }
+    mismatches.join("; ")
+}
+
+/// Writes one row per player with FC and AP counts over the best play of
+/// each chart in any of their saves, most All Perfects first. A stale or
+/// edited summary shows up in the `summary_mismatch` column.
+pub fn write_fc_ap_summary(saves: &[(&SaveFile, ProcessedSave)], output_path: &Path) -> Result<()> {
+    let mut players: BTreeMap<String, Player> = BTreeMap::new();
+    for (save_file, save) in saves {
+        let player = players.entry(save_file.player_id.clone()).or_default();
+        for record in &save.records {
+            let play = (record.score, record.acc, record.fc, record.ap);
+            let best = player.charts.entry((record.song_id.clone(), record.difficulty.clone())).or_insert(play);
+            if (play.0, play.1) > (best.0, best.1) {
+                *best = play;
+            }
+        }
+        let captured_at = captured_at(save_file, &save.save_info);
+        if player.latest.as_ref().is_none_or(|(latest, _)| captured_at >= *latest) {
+            player.latest = Some((captured_at, save.save_info.summary.clone()));
+        }
+    }
+
+    let mut rows: Vec<_> = players.iter().map(|(player_id, player)| (player_id, player.counts(), player)).collect();
+    rows.sort_by(|(a_id, a, _), (b_id, b, _)| {
+        (b.total_ap(), b.total_fc()).cmp(&(a.total_ap(), a.total_fc())).then_with(|| a_id.cmp(b_id))
+    });
+
+    write_atomically(output_path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        let mut header = vec!["player_id".to_string()];
+        for difficulty in COUNTED {
+            header.push(format!("{}_fc", difficulty.as_str()));
+            header.push(format!("{}_ap", difficulty.as_str()));
+        }
+        header.extend(["total_fc", "total_ap", "summary_mismatch"].map(str::to_string));
+        writer.write_record(header)?;
+        for (player_id, counts, player) in rows {
+            let mut row = vec![player_id.clone()];
+            for index in 0..COUNTED.len() {
+                row.push(counts.fc[index].to_string());
+                row.push(counts.ap[index].to_string());
+            }
+            row.push(counts.total_fc().to_string());
+            row.push(counts.total_ap().to_string());
+            row.push(mismatch(&counts, player.latest.as_ref().map(|(_, summary)| summary)));
+            writer.write_record(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+
+use crate::config::Config;
+use crate::{Difficulty, ProcessedRecord};
+
+pub struct RecordFilter {
+    difficulties: Vec<Difficulty>,
+    songs: Vec<SongPattern>,
+    case_sensitive: bool,
+    min_acc: Option<f64>,
+    min_score: Option<i32>,
+    fc_only: bool,
+    ap_only: bool,
+    excluded_songs: HashSet<String>,
+}
+
+struct SongPattern {
+    raw: String,
+    pattern: Pattern,
+}
+
+impl SongPattern {
+    fn new(raw: &str) -> SongPattern {
+        // Song names may contain `[` on their own; fall back to a literal match
+        // when the string isn't a valid glob.
+        let pattern = Pattern::new(raw).unwrap_or_else(|_| Pattern::new(&Pattern::escape(raw)).unwrap());
+        SongPattern {
+            raw: raw.to_string(),
+            pattern,
+        }
+    }
+
+    fn matches(&self, song_name: &str, case_sensitive: bool) -> bool {
+        let options = MatchOptions {
+            case_sensitive,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        };
+        if case_sensitive {
+            self.raw == song_name || self.pattern.matches_with(song_name, options)
+        } else {
+            self.raw.to_lowercase() == song_name.to_lowercase()
+                || self.pattern.matches_with(song_name, options)
+        }
+    }
+}
+
+impl RecordFilter {
+    pub fn from_config(config: &Config) -> Result<RecordFilter> {
+        let excluded_songs = match &config.exclude_songs {
+            Some(path) => read_exclude_list(path)?,
+            None => HashSet::new(),
+        };
+        Ok(RecordFilter {
+            difficulties: config.difficulties.clone(),
+            songs: config.songs.iter().map(|raw| SongPattern::new(raw)).collect(),
+            case_sensitive: config.case_sensitive,
+            min_acc: config.min_acc,
+            min_score: config.min_score,
+            fc_only: config.fc_only,
+            ap_only: config.ap_only,
+            excluded_songs,
+        })
+    }
+
+    pub fn matches(&self, record: &ProcessedRecord) -> bool {
+        (self.difficulties.is_empty()
+            || self.difficulties.iter().any(|d| d.as_str() == record.difficulty))
+            && self.min_acc.is_none_or(|min| record.acc >= min)
+            && self.min_score.is_none_or(|min| record.score >= min)
+            && (!self.fc_only || record.fc)
+            && (!self.ap_only || record.ap)
+            && !self.excluded_songs.contains(&record.song_id)
+            && !self.excluded_songs.contains(&record.song_name)
+    }
+
+    pub fn matches_song(&self, song_name: &str) -> bool {
+        self.songs.is_empty()
+            || self.songs.iter().any(|p| p.matches(song_name, self.case_sensitive))
+    }
+
+    /// Returns the `--song` patterns that match none of `song_names`.
+    pub fn unmatched_songs<'a>(&'a self, song_names: &[String]) -> Vec<&'a str> {
+        self.songs
+            .iter()
+            .filter(|p| !song_names.iter().any(|name| p.matches(name, self.case_sensitive)))
+            .map(|p| p.raw.as_str())
+            .collect()
+    }
+
+    /// Returns the `--exclude-songs` entries equal to none of the `seen` song ids or names.
+    pub fn unmatched_excluded_songs(&self, seen: &HashSet<String>) -> Vec<&str> {
+        let mut unmatched: Vec<_> = self
+            .excluded_songs
+            .iter()
+            .filter(|entry| !seen.contains(*entry))
+            .map(String::as_str)
+            .collect();
+        unmatched.sort();
+        unmatched
+    }
+}
+
+/// Reads one song name or raw song id per line, skipping blank lines and `#` comments.
+fn read_exclude_list(path: &Path) -> Result<HashSet<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read exclude list: {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+pub enum PlayerDecision {
+    Keep,
+    NotAllowed,
+    Denied,
+}
+
+/// Decides which player directories are processed, by exact directory name.
+pub struct PlayerFilter {
+    allow: Option<HashSet<String>>,
+    deny: HashSet<String>,
+}
+
+impl PlayerFilter {
+    pub fn from_config(config: &Config) -> Result<PlayerFilter> {
+        let allow = if config.players.is_empty() {
+            None
+        } else {
+            Some(expand_id_list(&config.players)?)
+        };
+        Ok(PlayerFilter {
+            allow,
+            deny: expand_id_list(&config.exclude_players)?,
+        })
+    }
+
+    pub fn check(&self, player_id: &str) -> PlayerDecision {
+        if let Some(allow) = &self.allow {
+            if !allow.contains(player_id) {
+                return PlayerDecision::NotAllowed;
+            }
+        }
+        if self.deny.contains(player_id) {
+            return PlayerDecision::Denied;
+        }
+        PlayerDecision::Keep
+    }
+}
+
+/// Expands `@file` entries into the ids listed in the file, one per line.
+fn expand_id_list(entries: &[String]) -> Result<HashSet<String>> {
+    let mut ids = HashSet::new();
+    for entry in entries {
+        match entry.strip_prefix('@') {
+            Some(path) => {
+                let content = fs::read_to_string(Path::new(path))
+                    .with_context(|| format!("Failed to read player list: {}", path))?;
+                ids.extend(
+                    content
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            None => {
+                ids.insert(entry.clone());
+            }
+        }
+    }
+    Ok(ids)
+}
@@ -0,0 +1,211 @@
+//! `--histograms`: how each chart's players are spread over acc.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::config::Config;
+use crate::input::player_key;
+use crate::output::{split_by_difficulty, write_atomically};
+use crate::run_summary::RunSummary;
+use crate::{claim_output_file, ProcessedRecord};
+
+pub const HISTOGRAM_DIR: &str = "histograms";
+
+/// Acc below this goes into one bucket.
+const LOWEST: f64 = 70.0;
+
+const HIGHEST: f64 = 100.0;
+
+/// Slack for acc that lands on a bin edge but isn't exactly representable,
+/// e.g. 70.3 with 0.1-wide bins, in units of bins.
+const EDGE_TOLERANCE: f64 = 1e-9;
+
+/// Where an acc goes: below 70, in the bin `[70 + i * width, 70 + (i + 1) * width)`
+/// with the last bin ending at 100, or exactly 100 (or more, for records kept
+/// with --keep-invalid).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Bucket {
+    Below,
+    Bin(usize),
+    Full,
+}
+
+struct Bins {
+    width: f64,
+    count: usize,
+}
+
+impl Bins {
+    fn new(width: f64) -> Bins {
+        let count = ((HIGHEST - LOWEST) / width - EDGE_TOLERANCE).ceil() as usize;
+        Bins { width, count }
+    }
+
+    fn bucket(&self, acc: f64) -> Bucket {
+        if acc >= HIGHEST {
+            Bucket::Full
+        } else if acc < LOWEST {
+            Bucket::Below
+        } else {
+            let index = ((acc - LOWEST) / self.width + EDGE_TOLERANCE).floor() as usize;
+            Bucket::Bin(index.min(self.count - 1))
+        }
+    }
+
+    fn bounds(&self, index: usize) -> (f64, f64) {
+        let edge = |i: usize| (LOWEST + i as f64 * self.width).min(HIGHEST);
+        (edge(index), edge(index + 1))
+    }
+
+    /// Every bucket in acc order, with its label and bounds.
+    fn all(&self) -> Vec<(Bucket, String, String, String)> {
+        let mut buckets = vec![(Bucket::Below, format!("<{}", LOWEST), String::new(), LOWEST.to_string())];
+        for index in 0..self.count {
+            let (lower, upper) = self.bounds(index);
+            // Rounded so that edges like 70.30000000000001 print as 70.3.
+            let (lower, upper) = (round(lower), round(upper));
+            buckets.push((Bucket::Bin(index), format!("[{}, {})", lower, upper), lower, upper));
+        }
+        buckets.push((Bucket::Full, HIGHEST.to_string(), HIGHEST.to_string(), HIGHEST.to_string()));
+        buckets
+    }
+}
+
+/// One bucket of an [`AccHistogram`].
+pub struct Row {
+    /// `<70`, `[70, 71)` or `100`.
+    pub label: String,
+    /// Empty for the bucket below 70.
+    pub lower: String,
+    pub upper: String,
+    pub count: usize,
+}
+
+/// Each player's best acc on one chart, counted in buckets.
+pub struct AccHistogram {
+    /// Every bucket in acc order, including empty ones.
+    pub rows: Vec<Row>,
+    pub players: usize,
+}
+
+pub fn histogram(records: &[ProcessedRecord], bin_width: f64) -> AccHistogram {
+    let bins = Bins::new(bin_width);
+    let accs = best_acc(records);
+    let mut counts: HashMap<Bucket, usize> = HashMap::new();
+    for &acc in &accs {
+        *counts.entry(bins.bucket(acc)).or_default() += 1;
+    }
+    let rows = bins
+        .all()
+        .into_iter()
+        .map(|(bucket, label, lower, upper)| Row {
+            label,
+            lower,
+            upper,
+            count: counts.get(&bucket).copied().unwrap_or_default(),
+        })
+        .collect();
+    AccHistogram { rows, players: accs.len() }
+}
+
+fn round(edge: f64) -> String {
+    ((edge * 1e6).round() / 1e6).to_string()
+}
+
+/// Each player's best acc on the chart, across their snapshots.
+fn best_acc(records: &[ProcessedRecord]) -> Vec<f64> {
+    let mut best: HashMap<&str, f64> = HashMap::new();
+    for record in records {
+        let acc = best.entry(player_key(&record.player_id)).or_insert(record.acc);
+        *acc = acc.max(record.acc);
+    }
+    best.into_values().collect()
+}
+
+/// Writes `histograms/{stem}_{difficulty}.csv` for each difficulty of the
+/// song with file stem `stem` that has records: one row per bucket with its
+/// count and the percentage of players at or below it. Returns the files
+/// written, or that would be with `dry_run`.
+pub fn write_song_histograms(
+    stem: &str,
+    records: &[ProcessedRecord],
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let histogram_dir = config.output_dir().join(HISTOGRAM_DIR);
+    let mut written = Vec::new();
+    for (stem, records) in split_by_difficulty(stem, records) {
+        let path = histogram_dir.join(format!("{}.csv", stem));
+        if !claim_output_file(&path, config.existing, summary)? {
+            continue;
+        }
+        if !dry_run {
+            let histogram = histogram(&records, config.histogram_bin_width);
+            fs::create_dir_all(&histogram_dir)?;
+            write_atomically(&path, |temp| {
+                let mut writer = csv::Writer::from_path(temp)?;
+                writer.write_record(["bin", "lower", "upper", "count", "cumulative_percent"])?;
+                let mut cumulative = 0;
+                for row in histogram.rows {
+                    cumulative += row.count;
+                    let percent = cumulative as f64 * 100.0 / histogram.players as f64;
+                    let count = row.count.to_string();
+                    writer.write_record([row.label, row.lower, row.upper, count, format!("{:.2}", percent)])?;
+                }
+                writer.flush()?;
+                Ok(())
+            })?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acc_on_an_inexact_edge_goes_into_the_bin_it_starts() {
+        let bins = Bins::new(0.1);
+        assert_eq!(bins.count, 300);
+        assert_eq!(bins.bucket(70.3), Bucket::Bin(3));
+        assert_eq!(bins.bucket(70.29999), Bucket::Bin(2));
+        assert_eq!(bins.all()[4].1, "[70.3, 70.4)");
+    }
+
+    #[test]
+    fn exactly_70_starts_the_first_bin() {
+        let bins = Bins::new(0.1);
+        assert_eq!(bins.bucket(70.0), Bucket::Bin(0));
+        assert_eq!(bins.bucket(69.999), Bucket::Below);
+        assert_eq!(Bins::new(1.0).bucket(70.0), Bucket::Bin(0));
+    }
+
+    #[test]
+    fn just_below_100_is_in_the_last_bin() {
+        assert_eq!(Bins::new(0.1).bucket(99.999), Bucket::Bin(299));
+        assert_eq!(Bins::new(1.0).bucket(99.999), Bucket::Bin(29));
+    }
+
+    #[test]
+    fn exactly_100_gets_its_own_bucket() {
+        let bins = Bins::new(1.0);
+        assert_eq!(bins.bucket(100.0), Bucket::Full);
+        assert_eq!(bins.bucket(100.5), Bucket::Full);
+        let all = bins.all();
+        assert_eq!((all[0].1.as_str(), all[30].1.as_str(), all[31].1.as_str()), ("<70", "[99, 100)", "100"));
+    }
+
+    #[test]
+    fn last_bin_is_cut_short_at_100() {
+        let bins = Bins::new(7.0);
+        assert_eq!(bins.count, 5);
+        assert_eq!(bins.bounds(4), (98.0, 100.0));
+        assert_eq!(bins.bucket(99.0), Bucket::Bin(4));
+    }
+}
@@ -0,0 +1,190 @@
+//! `--history`: how each player's records changed from one snapshot to the next.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chrono::DateTime;
+
+use crate::config::Config;
+use crate::filter::RecordFilter;
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::run_summary::RunSummary;
+use crate::{apply_song_aliases, claim_output_file, player_file_stem, Difficulty, ProcessedRecord, ProcessedSave};
+
+pub const HISTORY_DIR: &str = "history";
+
+/// The selected records of one save, by song id and difficulty.
+struct Snapshot {
+    /// Seconds since the Unix epoch, None when the capture time is unknown.
+    timestamp: Option<i64>,
+    name: String,
+    records: BTreeMap<(String, String), ProcessedRecord>,
+}
+
+/// Each player's snapshots ordered by capture time, then by name; snapshots
+/// without a capture time come last.
+fn snapshots(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+) -> BTreeMap<String, Vec<Snapshot>> {
+    let mut players: BTreeMap<String, Vec<Snapshot>> = BTreeMap::new();
+    for (save_file, save) in saves {
+        let mut records = save.records.clone();
+        apply_song_aliases(&mut records, aliases);
+        let timestamp = records
+            .first()
+            .and_then(|record| DateTime::parse_from_rfc3339(&record.captured_at).ok())
+            .map(|time| time.timestamp());
+        let records = records
+            .into_iter()
+            .filter(|record| filter.matches(record) && filter.matches_song(&record.song_name))
+            .map(|record| ((record.song_id.clone(), record.difficulty.clone()), record))
+            .collect();
+        players.entry(save_file.player().to_string()).or_default().push(Snapshot {
+            timestamp,
+            name: format!("{}/{}", save_file.player_id, save_file.snapshot),
+            records,
+        });
+    }
+    for snapshots in players.values_mut() {
+        snapshots.sort_by(|a, b| {
+            (a.timestamp.is_none(), a.timestamp, &a.name).cmp(&(b.timestamp.is_none(), b.timestamp, &b.name))
+        });
+    }
+    players
+}
+
+/// The records of each snapshot after the first that differ in score, acc or
+/// fc from the same chart in the player's previous snapshot that has it. The
+/// first snapshot is the baseline and lists nothing itself; charts first
+/// played in a later snapshot are listed with the previous values left empty.
+fn changes(snapshots: Vec<Snapshot>) -> Vec<(String, Option<ProcessedRecord>, ProcessedRecord)> {
+    let mut snapshots = snapshots.into_iter();
+    let mut last: HashMap<(String, String), ProcessedRecord> =
+        snapshots.next().map(|baseline| baseline.records.into_iter().collect()).unwrap_or_default();
+    let mut changes = Vec::new();
+    for snapshot in snapshots {
+        let mut changed = Vec::new();
+        for (key, record) in snapshot.records {
+            let previous = last.get(&key);
+            if previous.is_some_and(|previous| {
+                previous.score == record.score && previous.acc == record.acc && previous.fc == record.fc
+            }) {
+                continue;
+            }
+            changed.push((snapshot.name.clone(), last.insert(key, record.clone()), record));
+        }
+        changed.sort_by(|(_, _, a), (_, _, b)| {
+            (&a.song_name, Difficulty::rank(&a.difficulty)).cmp(&(&b.song_name, Difficulty::rank(&b.difficulty)))
+        });
+        changes.extend(changed);
+    }
+    changes
+}
+
+/// Writes `history/{player}.csv` for each player with at least two
+/// snapshots. Returns the files written, or that would be with `dry_run`.
+pub fn write_histories(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let history_dir = config.output_dir().join(HISTORY_DIR);
+    let mut written = Vec::new();
+    for (player, snapshots) in snapshots(saves, filter, aliases) {
+        if snapshots.len() < 2 {
+            continue;
+        }
+        let path = history_dir.join(format!("{}.csv", player_file_stem(&player)));
+        if !claim_output_file(&path, config.existing, summary)? {
+            continue;
+        }
+        if !dry_run {
+            fs::create_dir_all(&history_dir)?;
+            write_atomically(&path, |temp| {
+                let mut writer = csv::Writer::from_path(temp)?;
+                writer.write_record([
+                    "captured_at",
+                    "snapshot",
+                    "song_name",
+                    "difficulty",
+                    "previous_score",
+                    "score",
+                    "previous_acc",
+                    "acc",
+                    "previous_fc",
+                    "fc",
+                ])?;
+                for (snapshot, previous, record) in changes(snapshots) {
+                    let previous_value =
+                        |value: fn(&ProcessedRecord) -> String| previous.as_ref().map_or_else(String::new, value);
+                    writer.write_record([
+                        record.captured_at.clone(),
+                        snapshot,
+                        record.song_name.clone(),
+                        record.difficulty.clone(),
+                        previous_value(|record| record.score.to_string()),
+                        record.score.to_string(),
+                        previous_value(|record| record.acc.to_string()),
+                        record.acc.to_string(),
+                        previous_value(|record| record.fc.to_string()),
+                        record.fc.to_string(),
+                    ])?;
+                }
+                writer.flush()?;
+                Ok(())
+            })?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(name: &str, records: &[(&str, i32, bool)]) -> Snapshot {
+        let records = records
+            .iter()
+            .map(|&(song_name, score, fc)| {
+                let record = ProcessedRecord {
+                    song_name: song_name.to_string(),
+                    difficulty: "IN".to_string(),
+                    score,
+                    fc,
+                    ..ProcessedRecord::default()
+                };
+                ((song_name.to_string(), "IN".to_string()), record)
+            })
+            .collect();
+        Snapshot { timestamp: None, name: name.to_string(), records }
+    }
+
+    #[test]
+    fn lists_only_what_changed_after_the_first_snapshot() {
+        let changes = changes(vec![
+            snapshot("p1/save_1", &[("A", 900_000, false), ("B", 950_000, false)]),
+            snapshot("p1/save_2", &[("A", 900_000, false), ("B", 950_000, true), ("C", 800_000, false)]),
+            snapshot("p1/save_3", &[("A", 910_000, false), ("B", 950_000, true), ("C", 800_000, false)]),
+        ]);
+        let listed: Vec<_> = changes
+            .iter()
+            .map(|(name, previous, record)| {
+                (name.as_str(), previous.as_ref().map(|previous| previous.score), record.song_name.as_str())
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            [("p1/save_2", Some(950_000), "B"), ("p1/save_2", None, "C"), ("p1/save_3", Some(900_000), "A")]
+        );
+        assert!(!changes[0].1.as_ref().unwrap().fc && changes[0].2.fc);
+    }
+}
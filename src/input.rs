@@ -0,0 +1,343 @@
+//! Finding saves in the input directories and zip archives, and reading them.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+use crate::filter::{PlayerDecision, PlayerFilter};
+use crate::run_summary::RunSummary;
+
+/// Where a save is read from.
+pub enum SaveLocation {
+    File(PathBuf),
+    /// An entry of a zip archive, read without extracting it.
+    Zip { archive: PathBuf, entry: String },
+}
+
+impl fmt::Display for SaveLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveLocation::File(path) => write!(f, "{}", path.display()),
+            SaveLocation::Zip { archive, entry } => write!(f, "{}:{}", archive.display(), entry),
+        }
+    }
+}
+
+/// A save file and the player it belongs to.
+pub struct SaveFile {
+    pub location: SaveLocation,
+    /// The save's directory relative to its input directory, e.g. `player`,
+    /// or `player/date` for saves found with --recursive. In a zip archive it
+    /// is the name of the folder holding the save.
+    pub player_id: String,
+    /// File name without extension, telling apart several saves of one player.
+    pub snapshot: String,
+}
+
+/// The player a `player_id` belongs to: its top-level directory, so that
+/// `player/2024-01-01` from --recursive and `player` with several saves
+/// matching --save-pattern are all snapshots of `player`. Reports that merge
+/// snapshots group by this.
+pub fn player_key(player_id: &str) -> &str {
+    player_id.split('/').next().unwrap_or_default()
+}
+
+impl SaveFile {
+    /// See [`player_key`].
+    pub fn player(&self) -> &str {
+        player_key(&self.player_id)
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match &self.location {
+            SaveLocation::File(path) => {
+                fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))
+            }
+            SaveLocation::Zip { archive, entry } => {
+                let mut content = Vec::new();
+                open_archive(archive)?
+                    .by_name(entry)
+                    .with_context(|| format!("Failed to open {}", self.location))?
+                    .read_to_end(&mut content)
+                    .with_context(|| format!("Failed to read {}", self.location))?;
+                Ok(content)
+            }
+        }
+    }
+
+    /// The save file, or the archive it is in.
+    fn path(&self) -> &Path {
+        match &self.location {
+            SaveLocation::File(path) => path,
+            SaveLocation::Zip { archive, .. } => archive,
+        }
+    }
+
+    /// Modification time of the save, or of the archive it is in.
+    pub fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(self.path()).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    /// Size and modification time of the save, or of the archive it is in,
+    /// to tell whether it changed since it was last read.
+    pub fn stamp(&self) -> Option<(u64, SystemTime)> {
+        let metadata = fs::metadata(self.path()).ok()?;
+        Some((metadata.len(), metadata.modified().ok()?))
+    }
+}
+
+fn open_archive(path: &Path) -> Result<ZipArchive<File>> {
+    let file = File::open(path).with_context(|| format!("Failed to open archive: {}", path.display()))?;
+    ZipArchive::new(file).with_context(|| format!("Failed to read archive: {}", path.display()))
+}
+
+pub fn is_zip(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+fn snapshot_name(path: &Path) -> String {
+    path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+/// `path` made absolute with links resolved as far as it exists, the rest
+/// (such as an output directory not created yet) appended as given.
+fn canonical(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        let dir = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+        if let Ok(canonical) = fs::canonicalize(dir) {
+            return rest.iter().rev().fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Fails if `output_dir` is one of the input directories or inside one, where
+/// the files written would be scanned along with the saves on the next run.
+pub fn check_output_dir(inputs: &[PathBuf], output_dir: &Path) -> Result<()> {
+    let output = canonical(output_dir);
+    for input in inputs.iter().filter(|input| !is_zip(input)) {
+        let input_dir = canonical(input);
+        if output.starts_with(&input_dir) {
+            bail!(
+                "The output directory {} is {} the input directory {}, so later runs would read the files \
+                 written there as saves; choose another --output or pass --allow-nested-output",
+                output_dir.display(),
+                if output == input_dir { "the same as" } else { "inside" },
+                input.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Finds the files matching `save_pattern` (`save.json` by default) in each
+/// `<input>/<player>` directory, or at any depth with `recursive`. Inputs that
+/// are `.zip` files are searched for `<player>/<save>` entries instead.
+/// Players are filtered by their top-level directory name. Nothing inside
+/// `output_dir` is taken for a save.
+pub fn find_save_files(
+    inputs: &[PathBuf],
+    output_dir: Option<&Path>,
+    player_filter: &PlayerFilter,
+    save_pattern: &str,
+    recursive: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<SaveFile>> {
+    let save_pattern =
+        glob::Pattern::new(save_pattern).with_context(|| format!("Invalid --save-pattern `{}`", save_pattern))?;
+    let output_dir = output_dir.map(canonical);
+    let mut found = Vec::new();
+    for input in inputs {
+        if is_zip(input) {
+            find_in_archive(input, &save_pattern, &mut found)?;
+        } else {
+            summary.dirs_scanned += find_in_dir(input, output_dir.as_deref(), &save_pattern, recursive, &mut found);
+        }
+    }
+
+    let mut save_files = Vec::new();
+    for (save_file, player_dir) in found {
+        match player_filter.check(&player_dir) {
+            PlayerDecision::Keep => save_files.push(save_file),
+            PlayerDecision::NotAllowed => summary.players_not_allowed += 1,
+            PlayerDecision::Denied => summary.players_denied += 1,
+        }
+    }
+    // Walk order isn't sorted; keep a player's snapshots in name order.
+    save_files.sort_by(|a, b| (&a.player_id, &a.snapshot).cmp(&(&b.player_id, &b.snapshot)));
+    summary.saves_found = save_files.len();
+    Ok(save_files)
+}
+
+/// Adds each save below `save_data_dir` with the top-level directory it is in,
+/// leaving out `output_dir` (given canonical) if it is below it. Returns how
+/// many directories were looked in.
+fn find_in_dir(
+    save_data_dir: &Path,
+    output_dir: Option<&Path>,
+    save_pattern: &glob::Pattern,
+    recursive: bool,
+    found: &mut Vec<(SaveFile, String)>,
+) -> usize {
+    let walker = if recursive {
+        // Following links reports symlink loops as errors instead of walking them forever.
+        WalkDir::new(save_data_dir).min_depth(1).follow_links(true)
+    } else {
+        WalkDir::new(save_data_dir).min_depth(1).max_depth(2)
+    };
+    let mut dirs_scanned = 0;
+    // As the walk names it. The output directory being the input directory
+    // itself (with --allow-nested-output) leaves nothing to skip.
+    let skipped = output_dir
+        .and_then(|output_dir| output_dir.strip_prefix(canonical(save_data_dir)).ok())
+        .filter(|relative| !relative.as_os_str().is_empty())
+        .map(|relative| save_data_dir.join(relative));
+    let walker = walker.into_iter().filter_entry(|entry| {
+        let skip = skipped.as_deref() == Some(entry.path());
+        if skip {
+            debug!("Not looking for saves in the output directory {}", entry.path().display());
+        }
+        !skip
+    });
+    for entry in walker.filter_map(|e| match e {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            warn!("Skipping {}", err);
+            None
+        }
+    }) {
+        if entry.file_type().is_dir() {
+            // Without `recursive` the directories in player directories are
+            // listed but not looked in.
+            if recursive || entry.depth() == 1 {
+                dirs_scanned += 1;
+            }
+            continue;
+        }
+        // Saves are in player directories, never right in the input directory.
+        if entry.depth() < 2
+            || !entry.file_type().is_file()
+            || !save_pattern.matches(&entry.file_name().to_string_lossy())
+        {
+            continue;
+        }
+        let Some(dir) = entry.path().parent().and_then(|dir| dir.strip_prefix(save_data_dir).ok()) else {
+            continue;
+        };
+        let components: Vec<_> = dir.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+        let save_file = SaveFile {
+            location: SaveLocation::File(entry.path().to_path_buf()),
+            player_id: components.join("/"),
+            snapshot: snapshot_name(entry.path()),
+        };
+        found.push((save_file, components[0].to_string()));
+    }
+    dirs_scanned
+}
+
+/// Adds each save inside a zip archive, named after the folder it is in.
+/// Entries that can't be read are reported and skipped.
+fn find_in_archive(path: &Path, save_pattern: &glob::Pattern, found: &mut Vec<(SaveFile, String)>) -> Result<()> {
+    let mut archive = open_archive(path)?;
+    for index in 0..archive.len() {
+        let entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("{}: skipping entry {}: {}", path.display(), index, err);
+                continue;
+            }
+        };
+        let Some(entry_path) = entry.enclosed_name() else {
+            warn!("{}: skipping entry with unsafe path {}", path.display(), entry.name());
+            continue;
+        };
+        let file_name = entry_path.file_name().unwrap_or_default().to_string_lossy();
+        if !entry.is_file() || !save_pattern.matches(&file_name) {
+            continue;
+        }
+        let Some(player_id) = entry_path.parent().and_then(Path::file_name) else {
+            continue;
+        };
+        let player_id = player_id.to_string_lossy().into_owned();
+        let save_file = SaveFile {
+            location: SaveLocation::Zip { archive: path.to_path_buf(), entry: entry.name().to_string() },
+            player_id: player_id.clone(),
+            snapshot: snapshot_name(&entry_path),
+        };
+        found.push((save_file, player_id));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// Finds the saves matching `save_pattern` in a fresh input directory with
+    /// the files `files` (relative paths) in it, as `(player, snapshot)`.
+    fn find(name: &str, files: &[&str], save_pattern: &str) -> Vec<(String, String)> {
+        let input = std::env::temp_dir().join(format!("phi-save-data-input-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&input);
+        for file in files {
+            let path = input.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "{}").unwrap();
+        }
+        fs::create_dir_all(&input).unwrap();
+        let filter = PlayerFilter::from_config(&Config::default()).unwrap();
+        let mut summary = RunSummary::default();
+        let inputs = std::slice::from_ref(&input);
+        let found = find_save_files(inputs, None, &filter, save_pattern, false, &mut summary).unwrap();
+        fs::remove_dir_all(&input).unwrap();
+        assert_eq!(summary.saves_found, found.len());
+        found.into_iter().map(|save| (save.player_id, save.snapshot)).collect()
+    }
+
+    fn saves(saves: &[(&str, &str)]) -> Vec<(String, String)> {
+        saves.iter().map(|&(player, snapshot)| (player.to_string(), snapshot.to_string())).collect()
+    }
+
+    #[test]
+    fn no_matching_files() {
+        assert_eq!(find("none", &[], "save.json"), saves(&[]));
+        assert_eq!(find("none-matching", &["p1/backup.json", "p1/save.txt", "save.json"], "save.json"), saves(&[]));
+    }
+
+    #[test]
+    fn one_file() {
+        assert_eq!(find("one", &["p1/save.json", "p1/notes.txt"], "save.json"), saves(&[("p1", "save")]));
+    }
+
+    #[test]
+    fn several_files_per_player_are_snapshots_in_name_order() {
+        let files = ["p2/save.json", "p1/save_20240201.json", "p1/save.json", "p1/save_20240101.json", "p1/b.json"];
+        assert_eq!(
+            find("three", &files, "save*.json"),
+            saves(&[("p1", "save"), ("p1", "save_20240101"), ("p1", "save_20240201"), ("p2", "save")])
+        );
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        let filter = PlayerFilter::from_config(&Config::default()).unwrap();
+        let found = find_save_files(&[], None, &filter, "save[.json", false, &mut RunSummary::default());
+        let err = found.map(|_| ()).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid --save-pattern `save[.json`");
+    }
+}
@@ -0,0 +1,284 @@
+//! `--leaderboards`: every chart's players ranked by their best score, and
+//! `--global-leaderboard`: every player ranked by rks.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+use xlsxwriter::Format;
+
+use crate::best::computed_rks;
+use crate::config::Config;
+use crate::input::{player_key, SaveFile};
+use crate::output::{create_workbook, split_by_difficulty, write_atomically, OutputFormat};
+use crate::run_summary::RunSummary;
+use crate::{captured_at, claim_output_file, ProcessedRecord, ProcessedSave};
+
+pub const LEADERBOARD_DIR: &str = "leaderboards";
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    rank: usize,
+    player_id: &'a str,
+    score: i32,
+    acc: f64,
+    fc: bool,
+}
+
+/// Each player's best record across their snapshots, by score and then acc,
+/// best first. Players
+/// with the same score and acc share a rank and the next rank is skipped
+/// (1, 2, 2, 4); `top` limits the number of rows.
+fn rank(records: &[ProcessedRecord], top: Option<usize>) -> Vec<Entry<'_>> {
+    let mut best: HashMap<&str, &ProcessedRecord> = HashMap::new();
+    for record in records {
+        let entry = best.entry(player_key(&record.player_id)).or_insert(record);
+        if (record.score, record.acc) > (entry.score, entry.acc) {
+            *entry = record;
+        }
+    }
+    let mut best: Vec<_> = best.into_values().collect();
+    best.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| b.acc.total_cmp(&a.acc))
+            .then_with(|| player_key(&a.player_id).cmp(player_key(&b.player_id)))
+    });
+    let mut entries: Vec<Entry<'_>> = Vec::new();
+    for (index, record) in best.into_iter().enumerate() {
+        let rank = match entries.last() {
+            Some(last) if (last.score, last.acc) == (record.score, record.acc) => last.rank,
+            _ => index + 1,
+        };
+        let player_id = player_key(&record.player_id);
+        entries.push(Entry { rank, player_id, score: record.score, acc: record.acc, fc: record.fc });
+    }
+    if let Some(top) = top {
+        entries.truncate(top);
+    }
+    entries
+}
+
+/// Writes `leaderboards/{stem}_{difficulty}.csv` for each difficulty of the
+/// song with file stem `stem` that has records, returning the files written,
+/// or that would be with `dry_run`.
+pub fn write_song_leaderboards(
+    stem: &str,
+    records: &[ProcessedRecord],
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let leaderboard_dir = config.output_dir().join(LEADERBOARD_DIR);
+    let mut written = Vec::new();
+    for (stem, records) in split_by_difficulty(stem, records) {
+        let path = leaderboard_dir.join(format!("{}.csv", stem));
+        if !claim_output_file(&path, config.existing, summary)? {
+            continue;
+        }
+        if !dry_run {
+            fs::create_dir_all(&leaderboard_dir)?;
+            write_atomically(&path, |temp| {
+                let mut writer = csv::Writer::from_path(temp)?;
+                for entry in rank(&records, config.leaderboard_top) {
+                    writer.serialize(entry)?;
+                }
+                writer.flush()?;
+                Ok(())
+            })?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+pub const GLOBAL_LEADERBOARD_FILE: &str = "leaderboard_global";
+
+struct Standing {
+    player_id: String,
+    captured_at: String,
+    ranking_score: Option<f64>,
+    computed_rks: Option<f64>,
+    game_version: Option<i32>,
+    ap_count: usize,
+    fc_count: usize,
+}
+
+impl Standing {
+    /// What players are ranked by: the computed rks when constants are
+    /// loaded, the one stored in the save otherwise.
+    fn rks(&self) -> Option<f64> {
+        self.computed_rks.or(self.ranking_score)
+    }
+}
+
+fn standing(save_file: &SaveFile, save: &ProcessedSave, config: &Config) -> Standing {
+    let (save_info, records) = (&save.save_info, &save.records);
+    Standing {
+        player_id: save_file.player().to_string(),
+        captured_at: captured_at(save_file, save_info),
+        ranking_score: save_info.summary.ranking_score,
+        game_version: save_info.summary.game_version,
+        ap_count: records.iter().filter(|record| record.ap).count(),
+        fc_count: records.iter().filter(|record| record.fc).count(),
+        computed_rks: config.constants.is_some().then(|| computed_rks(records.clone())),
+    }
+}
+
+/// One standing per player: the save with the highest rks, or the most
+/// recently captured one with --latest-snapshot.
+fn one_per_player(standings: Vec<Standing>, latest: bool) -> Vec<Standing> {
+    let mut by_player: HashMap<String, Standing> = HashMap::new();
+    for standing in standings {
+        match by_player.get(&standing.player_id) {
+            Some(kept) if latest && kept.captured_at > standing.captured_at => {}
+            Some(kept) if !latest && kept.rks() >= standing.rks() => {}
+            _ => {
+                by_player.insert(standing.player_id.clone(), standing);
+            }
+        }
+    }
+    by_player.into_values().collect()
+}
+
+/// Players ranked by rks with shared ranks for ties; players without one
+/// come last, unranked.
+fn ranked(mut standings: Vec<Standing>) -> Vec<(Option<usize>, Standing)> {
+    standings.sort_by(|a, b| match (a.rks(), b.rks()) {
+        (Some(a_rks), Some(b_rks)) => b_rks.total_cmp(&a_rks).then_with(|| a.player_id.cmp(&b.player_id)),
+        (a_rks, b_rks) => b_rks.is_some().cmp(&a_rks.is_some()).then_with(|| a.player_id.cmp(&b.player_id)),
+    });
+    let mut ranked: Vec<(Option<usize>, Standing)> = Vec::new();
+    for (index, standing) in standings.into_iter().enumerate() {
+        let rank = match ranked.last() {
+            _ if standing.rks().is_none() => None,
+            Some((rank, last)) if last.rks() == standing.rks() => *rank,
+            _ => Some(index + 1),
+        };
+        ranked.push((rank, standing));
+    }
+    ranked
+}
+
+const GLOBAL_HEADER: [&str; 7] =
+    ["rank", "player_id", "ranking_score", "computed_rks", "game_version", "ap_count", "fc_count"];
+
+fn write_global_xlsx(ranked: &[(Option<usize>, Standing)], path: &Path) -> Result<()> {
+    let workbook = create_workbook(path)?;
+    let mut sheet = workbook.add_worksheet(Some("Leaderboard"))?;
+    let mut header = Format::new();
+    header.set_bold();
+    let mut rks = Format::new();
+    rks.set_num_format("0.0000");
+    for (col, name) in GLOBAL_HEADER.into_iter().enumerate() {
+        sheet.write_string(0, col as u16, name, Some(&header))?;
+    }
+    for (row, (rank, standing)) in ranked.iter().enumerate() {
+        let row = row as u32 + 1;
+        if let Some(rank) = rank {
+            sheet.write_number(row, 0, *rank as f64, None)?;
+        }
+        sheet.write_string(row, 1, &standing.player_id, None)?;
+        if let Some(score) = standing.ranking_score {
+            sheet.write_number(row, 2, score, Some(&rks))?;
+        }
+        if let Some(score) = standing.computed_rks {
+            sheet.write_number(row, 3, score, Some(&rks))?;
+        }
+        if let Some(version) = standing.game_version {
+            sheet.write_number(row, 4, f64::from(version), None)?;
+        }
+        sheet.write_number(row, 5, standing.ap_count as f64, None)?;
+        sheet.write_number(row, 6, standing.fc_count as f64, None)?;
+    }
+    sheet.set_column(1, 1, 20.0, None)?;
+    sheet.set_column(2, 6, 14.0, None)?;
+    sheet.freeze_panes(1, 0);
+    sheet.autofilter(0, 0, ranked.len() as u32, GLOBAL_HEADER.len() as u16 - 1)?;
+    workbook.close()?;
+    Ok(())
+}
+
+/// Writes `leaderboard_global.csv`, and `.xlsx` too when xlsx is among the
+/// formats, ranking every player by rks. Returns the files written, or that
+/// would be with `dry_run`.
+pub fn write_global_leaderboard(
+    saves: &[(&SaveFile, ProcessedSave)],
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![config.output_dir().join(format!("{}.csv", GLOBAL_LEADERBOARD_FILE))];
+    if config.formats.contains(&OutputFormat::Xlsx) {
+        paths.push(config.output_dir().join(format!("{}.xlsx", GLOBAL_LEADERBOARD_FILE)));
+    }
+    let mut claimed = Vec::new();
+    for path in paths {
+        if claim_output_file(&path, config.existing, summary)? {
+            claimed.push(path);
+        }
+    }
+    if dry_run || claimed.is_empty() {
+        return Ok(claimed);
+    }
+
+    let standings = saves.iter().map(|(save_file, save)| standing(save_file, save, config)).collect();
+    let ranked = ranked(one_per_player(standings, config.latest_snapshot));
+    for path in &claimed {
+        if path.extension().is_some_and(|extension| extension == "xlsx") {
+            write_atomically(path, |temp| write_global_xlsx(&ranked, temp))?;
+        } else {
+            write_atomically(path, |temp| write_global_csv(&ranked, temp))?;
+        }
+    }
+    Ok(claimed)
+}
+
+fn write_global_csv(ranked: &[(Option<usize>, Standing)], path: &Path) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(GLOBAL_HEADER)?;
+    for (rank, standing) in ranked {
+        let optional = |value: Option<f64>| value.map_or_else(String::new, |value| value.to_string());
+        writer.write_record([
+            rank.map_or_else(String::new, |rank| rank.to_string()),
+            standing.player_id.clone(),
+            optional(standing.ranking_score),
+            optional(standing.computed_rks),
+            standing.game_version.map_or_else(String::new, |version| version.to_string()),
+            standing.ap_count.to_string(),
+            standing.fc_count.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(player_id: &str, score: i32, acc: f64) -> ProcessedRecord {
+        ProcessedRecord { player_id: player_id.to_string(), score, acc, ..ProcessedRecord::default() }
+    }
+
+    fn ranks<'a>(entries: &[Entry<'a>]) -> Vec<(usize, &'a str)> {
+        entries.iter().map(|entry| (entry.rank, entry.player_id)).collect()
+    }
+
+    #[test]
+    fn ties_share_a_rank_and_acc_breaks_equal_scores() {
+        let records = [
+            record("p4", 970_000, 97.0),
+            record("p1/2024-01-01", 950_000, 96.0),
+            record("p3", 980_000, 98.5),
+            record("p1/2024-02-01", 990_000, 99.0),
+            record("p5", 980_000, 98.0),
+            record("p2", 980_000, 98.5),
+        ];
+        assert_eq!(ranks(&rank(&records, None)), [(1, "p1"), (2, "p2"), (2, "p3"), (4, "p5"), (5, "p4")]);
+        assert_eq!(rank(&records, None)[0].score, 990_000);
+        assert_eq!(ranks(&rank(&records, Some(3))), [(1, "p1"), (2, "p2"), (2, "p3")]);
+    }
+}
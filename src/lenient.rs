@@ -0,0 +1,154 @@
+//! Deserializers that accept the looser encodings found in older exported
+//! saves: integral floats for scores, numeric strings for acc and 0/1 for fc.
+
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use serde_path_to_error::{Path, Segment};
+
+/// Why a value could not be read, and where inside it.
+#[derive(Debug, Clone)]
+pub struct Invalid {
+    pub path: Path,
+    pub message: String,
+}
+
+/// A value that failed to deserialize keeps the error instead of failing the
+/// surrounding document, so one malformed score entry only loses that entry.
+#[derive(Debug, Clone)]
+pub struct Lenient<T>(pub Result<T, Invalid>);
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Lenient<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Ok(Lenient(serde_path_to_error::deserialize(value).map_err(|err| Invalid {
+            path: err.path().clone(),
+            message: err.inner().to_string(),
+        })))
+    }
+}
+
+/// Written back as the value, or as null if it could not be read.
+impl<T: Serialize> Serialize for Lenient<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.0 {
+            Ok(value) => value.serialize(serializer),
+            Err(_) => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Appends `path` to `base` in the notation of the save itself, e.g.
+/// `gameRecord["Rrhar'il.Team Grimoire.0"][2].acc`; keys that aren't plain
+/// identifiers are quoted.
+pub fn json_path(mut base: String, path: &Path) -> String {
+    for segment in path.iter() {
+        match segment {
+            Segment::Seq { index } => base.push_str(&format!("[{}]", index)),
+            Segment::Map { key } | Segment::Enum { variant: key } => {
+                let identifier = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !identifier {
+                    base.push_str(&format!("[{:?}]", key));
+                } else if base.is_empty() {
+                    base.push_str(key);
+                } else {
+                    base.push_str(&format!(".{}", key));
+                }
+            }
+            Segment::Unknown => base.push_str("[?]"),
+        }
+    }
+    base
+}
+
+/// An integer, or a float with no fractional part such as `983211.0`.
+pub fn score<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    let integral = match &value {
+        Value::Number(number) => number
+            .as_i64()
+            .or_else(|| number.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64)),
+        _ => None,
+    };
+    integral
+        .and_then(|n| i32::try_from(n).ok())
+        .ok_or_else(|| D::Error::custom(format!("invalid score {}", value)))
+}
+
+/// A number, or a string holding one such as `"99.53"`.
+pub fn acc<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    let acc = match &value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    };
+    acc.ok_or_else(|| D::Error::custom(format!("invalid acc {}", value)))
+}
+
+/// A boolean, or `0` / `1`.
+pub fn fc<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    let fc = match &value {
+        Value::Bool(fc) => Some(*fc),
+        Value::Number(number) => match number.as_u64() {
+            Some(0) => Some(false),
+            Some(1) => Some(true),
+            _ => None,
+        },
+        _ => None,
+    };
+    fc.ok_or_else(|| D::Error::custom(format!("invalid fc {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Entry {
+        #[serde(deserialize_with = "score")]
+        score: i32,
+        #[serde(deserialize_with = "acc")]
+        acc: f64,
+        #[serde(deserialize_with = "fc")]
+        fc: bool,
+    }
+
+    fn entry(json: &str) -> Result<Entry, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn reads_the_plain_encoding() {
+        let entry = entry(r#"{"score": 983211, "acc": 99.53, "fc": true}"#).unwrap();
+        assert_eq!((entry.score, entry.acc, entry.fc), (983211, 99.53, true));
+    }
+
+    #[test]
+    fn reads_the_older_encodings() {
+        let entry = entry(r#"{"score": 983211.0, "acc": " 99.53 ", "fc": 0}"#).unwrap();
+        assert_eq!((entry.score, entry.acc, entry.fc), (983211, 99.53, false));
+        assert!(self::entry(r#"{"score": 1, "acc": 1, "fc": 1}"#).unwrap().fc);
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_value() {
+        let err = |json| entry(json).unwrap_err().to_string();
+        assert!(err(r#"{"score": 983211.5, "acc": 99.5, "fc": true}"#).starts_with("invalid score 983211.5"));
+        assert!(err(r#"{"score": 3000000000, "acc": 99.5, "fc": true}"#).starts_with("invalid score 3000000000"));
+        assert!(err(r#"{"score": 1, "acc": "high", "fc": true}"#).starts_with(r#"invalid acc "high""#));
+        assert!(err(r#"{"score": 1, "acc": 99.5, "fc": 2}"#).starts_with("invalid fc 2"));
+    }
+
+    #[test]
+    fn lenient_keeps_the_error_and_where_it_is() {
+        let json = r#"[{"score": 1, "acc": 9, "fc": true}, {"score": 1, "acc": [], "fc": true}]"#;
+        let entries: Vec<Lenient<Entry>> = serde_json::from_str(json).unwrap();
+        assert!(entries[0].0.is_ok());
+        let invalid = entries[1].0.as_ref().unwrap_err();
+        assert_eq!(json_path(String::new(), &invalid.path), "acc");
+        assert_eq!(invalid.message, "invalid acc []");
+    }
+}
@@ -0,0 +1,139 @@
+//! `--lenient-json`: what hand-edited saves tend to have that JSON doesn't
+//! allow is taken out before parsing. Comments are removed, trailing commas
+//! dropped, and `NaN` and `Infinity` quoted, so they reach the acc as numbers
+//! and are checked like any other out-of-range value.
+
+/// What [`clean`] changed.
+#[derive(Debug, Default)]
+pub struct Fixes {
+    comments: usize,
+    trailing_commas: usize,
+    non_finite: usize,
+}
+
+impl Fixes {
+    /// One entry per kind of change, empty if the text was valid JSON already.
+    pub fn describe(&self) -> Vec<String> {
+        [
+            (self.comments, "comments removed"),
+            (self.trailing_commas, "trailing commas removed"),
+            (self.non_finite, "NaN / Infinity values quoted"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, change)| format!("{} {}", count, change))
+        .collect()
+    }
+}
+
+const NON_FINITE: [&[u8]; 2] = [b"NaN", b"Infinity"];
+
+/// `text` with `//` and `/* */` comments, trailing commas in objects and
+/// arrays, and bare `NaN`, `Infinity` and `-Infinity` turned into JSON.
+/// Strings are left as they are.
+pub fn clean(text: &str) -> (String, Fixes) {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut fixes = Fixes::default();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(bytes.len());
+                out.extend_from_slice(&bytes[start..i]);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                fixes.comments += 1;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+                    i += 1;
+                }
+                i = (i + 2).min(bytes.len());
+                // Keeps tokens on either side apart.
+                out.push(b' ');
+                fixes.comments += 1;
+            }
+            close @ (b'}' | b']') => {
+                let end = out.len() - out.iter().rev().take_while(|byte| byte.is_ascii_whitespace()).count();
+                if end > 0 && out[end - 1] == b',' {
+                    out.remove(end - 1);
+                    fixes.trailing_commas += 1;
+                }
+                out.push(close);
+                i += 1;
+            }
+            byte => {
+                let sign = usize::from(matches!(byte, b'-' | b'+'));
+                match NON_FINITE.iter().find(|word| bytes[i + sign..].starts_with(word)) {
+                    Some(word) => {
+                        out.push(b'"');
+                        if byte == b'-' {
+                            out.push(b'-');
+                        }
+                        out.extend_from_slice(word);
+                        out.push(b'"');
+                        i += sign + word.len();
+                        fixes.non_finite += 1;
+                    }
+                    None => {
+                        out.push(byte);
+                        i += 1;
+                    }
+                }
+            }
+        }
+    }
+    // Only ASCII is added or removed, and comments are removed whole.
+    (String::from_utf8(out).expect("cleaning keeps the text UTF-8"), fixes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_comments_outside_strings() {
+        let (text, fixes) = clean("{\n  // a note\n  \"url\": \"https://example.com\", /* inline */ \"a\": 1\n}");
+        assert_eq!(text, "{\n  \n  \"url\": \"https://example.com\",   \"a\": 1\n}");
+        assert_eq!(fixes.describe(), ["2 comments removed"]);
+    }
+
+    #[test]
+    fn keeps_escaped_quotes_in_strings() {
+        let text = r#"{"name": "say \"// hi\"", "b": "/* not a comment */"}"#;
+        let (cleaned, fixes) = clean(text);
+        assert_eq!(cleaned, text);
+        assert!(fixes.describe().is_empty());
+    }
+
+    #[test]
+    fn drops_trailing_commas() {
+        let (text, fixes) = clean("{\"a\": [1, 2,\n], \"b\": {\"c\": 3, },}");
+        assert_eq!(text, "{\"a\": [1, 2\n], \"b\": {\"c\": 3 }}");
+        assert_eq!(fixes.describe(), ["3 trailing commas removed"]);
+        assert!(serde_json::from_str::<serde_json::Value>(&text).is_ok());
+    }
+
+    #[test]
+    fn quotes_non_finite_numbers() {
+        let (text, fixes) = clean(r#"[NaN, Infinity, -Infinity, +Infinity, "NaN"]"#);
+        assert_eq!(text, r#"["NaN", "Infinity", "-Infinity", "Infinity", "NaN"]"#);
+        assert_eq!(fixes.describe(), ["4 NaN / Infinity values quoted"]);
+    }
+
+    #[test]
+    fn unterminated_input_is_kept() {
+        assert_eq!(clean(r#"{"a": "open"#).0, r#"{"a": "open"#);
+        assert_eq!(clean("[1 /* open").0, "[1  ");
+    }
+}
@@ -0,0 +1,5 @@
+//! Helpers for reading phi-save-data exports from other Rust programs.
+
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod songs;
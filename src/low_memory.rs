@@ -0,0 +1,314 @@
+//! `--low-memory`: each save is read and its records appended to the per-song
+//! csv files right away, so only one save's records are held at a time. The
+//! files are kept open in a small pool and reopened for appending once they
+//! have been closed to make room. Each file is written under a temporary
+//! name and only moved into place once every save has been read.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+
+use crate::config::Config;
+use crate::filter::RecordFilter;
+use crate::input::SaveFile;
+use crate::output::{
+    self, Compression, ExistingFiles, FileStems, GroupBy, Layout, OutputFormat, OutputOptions, TempFile,
+    FILENAME_MAP_FILE,
+};
+use crate::run_summary::RunSummary;
+use crate::sort::sort_records;
+use crate::{claim_output_file, process_save_file, refuse_existing, ProcessedRecord, SaveChecks};
+
+/// Per-song files kept open at once.
+const MAX_OPEN_FILES: usize = 64;
+
+/// Fails for the options that need every record of a song, or of the run, at
+/// once.
+pub fn check_options(config: &Config, to_stdout: bool) -> Result<()> {
+    for &format in &config.formats {
+        match format {
+            OutputFormat::Csv => {}
+            OutputFormat::Xlsx => bail!("--low-memory can't write xlsx, which needs all of a song's records at once"),
+            _ => bail!("--low-memory only writes csv, not {}", format.extension()),
+        }
+    }
+    let unsupported = [
+        (to_stdout, "writing to stdout"),
+        (config.layout == Layout::Wide, "--layout wide"),
+        (config.compress != Compression::None, "--compress"),
+        (config.append, "--append"),
+        (config.split_by.is_some(), "--split-by"),
+        (config.group_by == GroupBy::Player, "--group-by player"),
+        (config.combined || config.combined_only, "--combined"),
+        (config.merge_case_insensitive, "--merge-case-insensitive"),
+        (config.with_percentiles, "--with-percentiles"),
+        (config.stats, "--stats"),
+        (config.histograms, "--histograms"),
+        (config.plots, "--plots"),
+        (config.leaderboards, "--leaderboards"),
+        (config.global_leaderboard, "--global-leaderboard"),
+        (config.player_summary, "--player-summary"),
+        (config.fc_ap_summary, "--fc-ap-summary"),
+        (config.player_summary_out, "--player-summary-out"),
+        (config.songlist.is_some(), "--songlist"),
+        (config.rks_timeline, "--rks-timeline"),
+        (config.history, "--history"),
+        (config.best_n.is_some(), "--best-n"),
+        (config.push_acc, "--push-acc"),
+        (config.suggestions, "--suggestions"),
+        (config.rks_drift, "--rks-drift"),
+        (config.progress_out, "--progress-out"),
+        (config.profiles_out.is_some(), "--profiles-out"),
+    ];
+    if let Some((_, option)) = unsupported.iter().find(|(set, _)| *set) {
+        bail!("--low-memory only writes the per-song csv files and can't be combined with {}", option);
+    }
+    Ok(())
+}
+
+/// The per-song csv files being written, at most [`MAX_OPEN_FILES`] of them
+/// open at a time.
+struct SongFiles<'a> {
+    output_dir: &'a Path,
+    options: &'a OutputOptions,
+    existing: ExistingFiles,
+    dry_run: bool,
+    open: BTreeMap<String, csv::Writer<BufWriter<File>>>,
+    /// Open files, least recently written first.
+    recent: VecDeque<String>,
+    /// Rows per song, whose files have been started in this run.
+    written: BTreeMap<String, usize>,
+    /// The temporary file of each started song, removed if dropped before
+    /// [`SongFiles::close_all`] moves it into place.
+    temps: BTreeMap<String, TempFile>,
+    /// Songs whose files existed and are left alone with --skip-existing.
+    skipped: HashSet<String>,
+    /// Songs whose file could not be written, left out from then on with
+    /// `--error-policy continue`.
+    failed: HashSet<String>,
+    /// Given in the order the songs are first seen in.
+    stems: FileStems,
+}
+
+impl SongFiles<'_> {
+    fn path(&mut self, song_name: &str) -> PathBuf {
+        let stem = self.stems.stem(song_name);
+        output::output_path(self.output_dir, stem, OutputFormat::Csv, self.options)
+    }
+
+    fn append(&mut self, song_name: &str, records: &[ProcessedRecord], summary: &mut RunSummary) -> Result<()> {
+        if self.skipped.contains(song_name) || self.failed.contains(song_name) {
+            return Ok(());
+        }
+        let path = self.path(song_name);
+        if !self.written.contains_key(song_name) && !claim_output_file(&path, self.existing, summary)? {
+            self.skipped.insert(song_name.to_string());
+            return Ok(());
+        }
+        *self.written.entry(song_name.to_string()).or_default() += records.len();
+        if self.dry_run {
+            return Ok(());
+        }
+        if let Some(position) = self.recent.iter().position(|name| name == song_name) {
+            self.recent.remove(position);
+        } else {
+            if self.open.len() >= MAX_OPEN_FILES {
+                self.close_oldest()?;
+            }
+            let writer = match self.temps.get(song_name) {
+                Some(temp) => {
+                    let file = OpenOptions::new()
+                        .append(true)
+                        .open(temp.path())
+                        .with_context(|| format!("Failed to reopen {}", temp.path().display()))?;
+                    self.options.csv_writer().from_writer(BufWriter::new(file))
+                }
+                None => {
+                    let temp = TempFile::new(&path);
+                    let file = File::create(temp.path())
+                        .with_context(|| format!("Failed to create {}", temp.path().display()))?;
+                    self.temps.insert(song_name.to_string(), temp);
+                    let mut writer = self.options.csv_writer().from_writer(BufWriter::new(file));
+                    writer.write_record(self.options.columns.iter().map(|column| column.name()))?;
+                    writer
+                }
+            };
+            self.open.insert(song_name.to_string(), writer);
+        }
+        self.recent.push_back(song_name.to_string());
+        let Some(writer) = self.open.get_mut(song_name) else {
+            bail!("No open file for {}", song_name);
+        };
+        output::write_csv_rows(writer, records, self.options)
+    }
+
+    /// Gives up on the file of `song_name`, removing what was written of it
+    /// and leaving the file of an earlier run as it was.
+    fn fail(&mut self, song_name: String) {
+        self.open.remove(&song_name);
+        self.recent.retain(|name| *name != song_name);
+        self.written.remove(&song_name);
+        self.temps.remove(&song_name);
+        self.failed.insert(song_name);
+    }
+
+    /// Removes the temporary file of every song started in this run, which a
+    /// run stopped halfway would otherwise leave with the rows of only some of
+    /// the saves. The files of earlier runs are left as they were.
+    fn discard(&mut self) {
+        self.open.clear();
+        self.recent.clear();
+        self.temps.clear();
+    }
+
+    fn close_oldest(&mut self) -> Result<()> {
+        if let Some(song_name) = self.recent.pop_front() {
+            if let Some(mut writer) = self.open.remove(&song_name) {
+                writer.flush().with_context(|| format!("Failed to write {}", self.path(&song_name).display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the open files, then moves every finished file into place.
+    fn close_all(&mut self) -> Result<()> {
+        while !self.recent.is_empty() {
+            self.close_oldest()?;
+        }
+        while let Some((_, temp)) = self.temps.pop_first() {
+            temp.persist()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads every save for its song names alone and refuses to start when one
+/// of the files they make is already there, so that an existing file never
+/// stops the run with only some of the songs written. Saves that can't be
+/// read are left for the pass that writes the files to report. Returns the
+/// file stems, given in the order that pass sees the songs in.
+fn check_existing(
+    save_files: &[SaveFile],
+    filter: &RecordFilter,
+    config: &Config,
+    options: &OutputOptions,
+) -> Result<FileStems> {
+    let output_dir = config.output_dir();
+    let mut stems = FileStems::default();
+    let mut seen = HashSet::new();
+    let mut planned = Vec::new();
+    for save_file in save_files {
+        let Ok(save) = process_save_file(save_file, config) else {
+            continue;
+        };
+        let song_names: BTreeSet<_> = save
+            .records
+            .into_iter()
+            .filter(|record| filter.matches(record) && filter.matches_song(&record.song_name))
+            .map(|record| record.song_name)
+            .collect();
+        for song_name in song_names {
+            if seen.insert(song_name.clone()) {
+                planned.push(output::output_path(output_dir, stems.stem(&song_name), OutputFormat::Csv, options));
+            }
+        }
+    }
+    if !stems.renamed().is_empty() {
+        planned.push(output_dir.join(FILENAME_MAP_FILE));
+    }
+    refuse_existing(planned)?;
+    Ok(stems)
+}
+
+/// Reads the saves one at a time, appending each one's records to the files
+/// of its songs.
+fn stream_saves(
+    save_files: &[SaveFile],
+    files: &mut SongFiles,
+    filter: &RecordFilter,
+    config: &Config,
+    summary: &mut RunSummary,
+) -> Result<()> {
+    let mut checks = SaveChecks::default();
+    for save_file in save_files {
+        let processed = process_save_file(save_file, config);
+        let save = checks.add(save_file, processed, filter, config, summary)?;
+        let records = save.map_or_else(Vec::new, |save| save.records);
+        let mut songs: BTreeMap<String, Vec<ProcessedRecord>> = BTreeMap::new();
+        for record in records {
+            if filter.matches(&record) && filter.matches_song(&record.song_name) {
+                songs.entry(record.song_name.clone()).or_default().push(record);
+            }
+        }
+        for (song_name, mut records) in songs {
+            // Only the rows of each save can be sorted.
+            sort_records(&mut records, &config.sort_by);
+            if let Err(err) = files.append(&song_name, &records, summary) {
+                let err = config.error_policy.handle(err, summary)?;
+                error!("{}: {:#}", song_name, err);
+                summary.files_failed += 1;
+                files.fail(song_name);
+            }
+        }
+    }
+    checks.finish(filter, config, summary);
+    Ok(())
+}
+
+/// Writes the per-song csv files one save at a time, and filename_map.csv if
+/// any song was renamed for its file name. Returns the rows and file of each
+/// song, then of the map, for the dry-run plan.
+pub fn write_song_files(
+    save_files: &[SaveFile],
+    filter: &RecordFilter,
+    config: &Config,
+    options: &OutputOptions,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<(String, usize, PathBuf)>> {
+    let stems = if config.existing == ExistingFiles::Error {
+        check_existing(save_files, filter, config, options)?
+    } else {
+        FileStems::default()
+    };
+    let mut files = SongFiles {
+        output_dir: config.output_dir(),
+        options,
+        existing: config.existing,
+        dry_run,
+        open: BTreeMap::new(),
+        recent: VecDeque::new(),
+        written: BTreeMap::new(),
+        temps: BTreeMap::new(),
+        skipped: HashSet::new(),
+        failed: HashSet::new(),
+        stems,
+    };
+    if let Err(err) = stream_saves(save_files, &mut files, filter, config, summary).and_then(|()| files.close_all()) {
+        files.discard();
+        return Err(err);
+    }
+    let output_dir = config.output_dir();
+    let SongFiles { written, mut stems, .. } = files;
+    summary.songs_written = written.len();
+    let mut plan: Vec<_> = written
+        .into_iter()
+        .map(|(song_name, rows)| {
+            let path = output::output_path(output_dir, stems.stem(&song_name), OutputFormat::Csv, options);
+            (song_name, rows, path)
+        })
+        .collect();
+    let renamed = stems.renamed();
+    let filename_map_path = output_dir.join(FILENAME_MAP_FILE);
+    if !renamed.is_empty() && claim_output_file(&filename_map_path, config.existing, summary)? {
+        if !dry_run {
+            output::write_filename_map(&renamed, &filename_map_path)?;
+        }
+        plan.push((FILENAME_MAP_FILE.to_string(), renamed.len(), filename_map_path));
+    }
+    Ok(plan)
+}
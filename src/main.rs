@@ -1,188 +1,2389 @@
-use std::collections::{HashMap, HashSet};
+mod best;
+mod cache;
+mod cli;
+#[cfg(feature = "cloud")]
+mod cloud;
+mod completion;
+mod config;
+mod constants;
+mod decode;
+mod diff;
+mod drift;
+mod encoding;
+mod estimate;
+mod exit;
+mod failures;
+mod fc_ap;
+mod filter;
+mod histogram;
+mod history;
+mod input;
+mod leaderboard;
+mod lenient;
+mod lenient_json;
+mod logging;
+mod low_memory;
+mod output;
+mod percentile;
+mod player_summary;
+mod player_totals;
+#[cfg(feature = "plots")]
+mod plots;
+mod profiles;
+mod push_acc;
+mod progress;
+mod run_summary;
+mod sort;
+mod stats;
+mod suggestions;
+mod timeline;
+mod timings;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use anyhow::{Context, Result};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{panic, thread};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Parser, ValueEnum};
+use log::{debug, error, info, trace, warn};
+use phi_save_data::songs::get_all_song_names;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use walkdir::WalkDir;
-use rayon::iter::IntoParallelRefIterator;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::cache::Cache;
+use crate::cli::{Cli, Command};
+use crate::completion::{COMPLETION_FILE, UNPLAYED_CHARTS_FILE};
+use crate::config::Config;
+use crate::constants::{play_rks, ChartConstants};
+use crate::drift::RKS_DRIFT_FILE;
+use crate::estimate::ESTIMATED_CONSTANTS_FILE;
+use crate::exit::{Exit, NoSaves, SavesFailed};
+use crate::failures::{ErrorPolicy, Phase, SaveFailure, ERRORS_FILE};
+use crate::fc_ap::FC_AP_SUMMARY_FILE;
+use crate::filter::{PlayerFilter, RecordFilter};
+use crate::input::{find_save_files, SaveFile, SaveLocation};
+use crate::leaderboard::GLOBAL_LEADERBOARD_FILE;
+use crate::lenient::{json_path, Lenient};
+use crate::output::{
+    output_path, Column, Compression, ExistingFiles, GroupBy, HtmlReport, Layout, OutputFormat, OutputOptions,
+    FileStems, ParquetWriter, SplitBy, SqliteWriter, TempFile, XlsxWorkbook, ALL_RECORDS, DATABASE_FILE,
+    FILENAME_MAP_FILE, REPORT_FILE, WORKBOOK_FILE,
+};
+use crate::player_summary::PLAYER_SUMMARY_FILE;
+use crate::player_totals::{PLAYER_TOTALS_FILE, SUMMARY_WORKBOOK_FILE};
+use crate::profiles::{Settings, User};
+use crate::progress::{GameProgress, PROGRESS_FILE};
+use crate::run_summary::RunSummary;
+use crate::sort::sort_records;
+use crate::stats::{SongStats, STATS_FILE};
+use crate::timings::TIMINGS_FILE;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct SaveData {
+    /// Entries that can't be read are kept as errors so that they only drop
+    /// themselves, not the whole save.
     #[serde(rename = "gameRecord")]
-    game_record: HashMap<String, Vec<Option<ScoreRecord>>>,
+    game_record: HashMap<String, Vec<Option<Lenient<ScoreRecord>>>>,
     #[serde(rename = "saveInfo")]
     save_info: SaveInfo,
+    /// Only read for --progress-out; a malformed section doesn't fail the save.
+    #[serde(rename = "gameProgress", default, skip_serializing_if = "Option::is_none")]
+    game_progress: Option<Lenient<GameProgress>>,
+    /// Only read for --profiles-out, like gameProgress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<Lenient<User>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    settings: Option<Lenient<Settings>>,
+    /// What --lenient-json had to change for the save to parse.
+    #[serde(skip)]
+    lenient_fixes: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct SaveInfo {
     #[serde(rename = "summary")]
     summary: Summary,
+    #[serde(default)]
+    nickname: Option<String>,
+    /// When the cloud save was last updated, if the save records it.
+    #[serde(rename = "updatedAt", default)]
+    updated_at: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct Summary {
-    #[serde(rename = "rankingScore")]
-    ranking_score: f64,
-    #[serde(rename = "gameVersion")]
-    game_version: i32,
+    /// Missing from some very old saves.
+    #[serde(rename = "rankingScore", default)]
+    ranking_score: Option<f64>,
+    #[serde(rename = "gameVersion", default)]
+    game_version: Option<i32>,
+    /// Per-difficulty counts reported by the game, EZ to AT;
+    /// older saves don't have them.
+    #[serde(default)]
+    cleared: Option<Vec<u32>>,
+    #[serde(rename = "fullCombo", default)]
+    full_combo: Option<Vec<u32>>,
+    #[serde(default)]
+    phi: Option<Vec<u32>>,
+    /// Challenge mode rating, e.g. `438` for gold 38.
+    #[serde(rename = "challengeModeRank", default)]
+    challenge_mode_rank: Option<u32>,
+    #[serde(default)]
+    avatar: Option<String>,
+    #[serde(rename = "saveVersion", default)]
+    save_version: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ScoreRecord {
+    #[serde(deserialize_with = "lenient::score")]
     score: i32,
+    #[serde(deserialize_with = "lenient::acc")]
     acc: f64,
+    #[serde(deserialize_with = "lenient::fc")]
     fc: bool,
 }
 
-#[derive(Debug, Serialize)]
-struct ProcessedRecord {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub(crate) enum Difficulty {
+    #[serde(rename = "EZ")]
+    #[value(name = "EZ")]
+    Ez,
+    #[serde(rename = "HD")]
+    #[value(name = "HD")]
+    Hd,
+    #[serde(rename = "IN")]
+    #[value(name = "IN")]
+    In,
+    #[serde(rename = "AT")]
+    #[value(name = "AT")]
+    At,
+    #[serde(rename = "Legacy")]
+    #[value(name = "Legacy")]
+    Legacy,
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 5] = [
+        Difficulty::Ez,
+        Difficulty::Hd,
+        Difficulty::In,
+        Difficulty::At,
+        Difficulty::Legacy,
+    ];
+
+    /// Position of a difficulty label in the save's score array, for ordering.
+    fn rank(name: &str) -> usize {
+        Difficulty::ALL
+            .iter()
+            .position(|d| d.as_str() == name)
+            .unwrap_or(Difficulty::ALL.len())
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Difficulty::Ez => "EZ",
+            Difficulty::Hd => "HD",
+            Difficulty::In => "IN",
+            Difficulty::At => "AT",
+            Difficulty::Legacy => "Legacy",
+        }
+    }
+
+    /// Label for position `index` of a song's score array; slots beyond the
+    /// known difficulties are kept as `Unknown(<index>)`.
+    fn label(index: usize) -> String {
+        Difficulty::ALL
+            .get(index)
+            .map_or_else(|| format!("Unknown({})", index), |d| d.as_str().to_string())
+    }
+}
+
+/// The in-game grade for a score; a full combo in the V range is shown as
+/// `V (FC)`, which the game draws as a blue V.
+fn grade(score: i32, fc: bool) -> &'static str {
+    match score {
+        1_000_000.. => "φ",
+        960_000.. if fc => "V (FC)",
+        960_000.. => "V",
+        920_000.. => "S",
+        880_000.. => "A",
+        820_000.. => "B",
+        700_000.. => "C",
+        _ => "F",
+    }
+}
+
+/// Whether a play was an All Perfect. acc is compared exactly: an acc such as
+/// 99.99999999 is not rounded up to 100.
+fn is_all_perfect(score: i32, acc: f64, fc: bool) -> bool {
+    score >= 1_000_000 || (fc && acc == 100.0)
+}
+
+// Deserialized when --append reads an earlier csv back; columns that were not
+// exported are left at their defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct ProcessedRecord {
+    #[serde(skip)]
+    song_id: String,
+    /// Name of the player directory the record was read from.
+    player_id: String,
     song_name: String,
+    /// Artist part of the song id, empty when the id has none.
+    artist: String,
+    /// Numeric chart revision suffix of the song id, empty when it has none.
+    chart_suffix: String,
     difficulty: String,
     score: i32,
+    /// Points short of the 1,000,000 maximum; negative for the out-of-range
+    /// scores kept with --keep-invalid.
+    score_deficit: i32,
     acc: f64,
     fc: bool,
+    /// All Perfect: a full combo with every note Perfect.
+    ap: bool,
+    grade: String,
     ranking_score: f64,
     game_version: String,
+    /// Avatar id from the save summary, empty when the save has none.
+    avatar: String,
+    /// RFC 3339 UTC time the save was captured, empty when unknown.
+    captured_at: String,
+    /// Whether score or acc is out of range; only kept with --keep-invalid.
+    invalid: bool,
+    /// File name of the save without extension, e.g. `save` or `save_20240101`.
+    snapshot: String,
+    /// From --constants, empty when the chart isn't listed there.
+    chart_constant: Option<f64>,
+    /// The rks this play is worth, given its chart constant.
+    play_rks: Option<f64>,
+    /// From --with-percentiles, empty for charts with too few players.
+    acc_percentile: Option<f64>,
+}
+
+/// Save files larger than this are parsed as they are read, rather than read
+/// into memory whole and then parsed.
+#[cfg(not(test))]
+const STREAM_THRESHOLD: u64 = 16 * 1024 * 1024;
+/// Low enough for a test fixture to be streamed.
+#[cfg(test)]
+const STREAM_THRESHOLD: u64 = 64 * 1024;
+
+/// Reads a save.json, or a binary cloud save which is decrypted first.
+fn read_save_file(save_file: &SaveFile, config: &Config) -> Result<SaveData> {
+    if let SaveLocation::File(path) = &save_file.location {
+        // Cleaning up a save for --lenient-json needs all of its text.
+        if !config.lenient_json && fs::metadata(path).is_ok_and(|metadata| metadata.len() > STREAM_THRESHOLD) {
+            return stream_save_file(save_file, path);
+        }
+    }
+    let bytes = save_file.read()?;
+    if decode::is_binary_save(&bytes) {
+        return decode::decode_save(&bytes)
+            .with_context(|| Phase::Decode.error(format!("Failed to decode {}", save_file.location)));
+    }
+    let content = encoding::decode_text(bytes)
+        .with_context(|| Phase::Parse.error(format!("Failed to read {} as text", save_file.location)))?;
+    if !config.lenient_json {
+        return parse_save_json(save_file, &mut serde_json::Deserializer::from_str(&content));
+    }
+    let (content, fixes) = lenient_json::clean(&content);
+    let mut save_data = parse_save_json(save_file, &mut serde_json::Deserializer::from_str(&content))?;
+    save_data.lenient_fixes = fixes.describe();
+    if !save_data.lenient_fixes.is_empty() {
+        debug!("{}: cleaned up for --lenient-json: {}", save_file.location, save_data.lenient_fixes.join(", "));
+    }
+    Ok(save_data)
+}
+
+/// Parses a large save.json straight from a buffered reader, so the text is
+/// never held in memory next to the records parsed from it.
+fn stream_save_file(save_file: &SaveFile, path: &Path) -> Result<SaveData> {
+    let file = File::open(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let start = reader.fill_buf().with_context(|| format!("Failed to read file: {}", path.display()))?;
+    if decode::is_binary_save(start) {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).with_context(|| format!("Failed to read file: {}", path.display()))?;
+        return decode::decode_save(&bytes)
+            .with_context(|| Phase::Decode.error(format!("Failed to decode {}", save_file.location)));
+    }
+    match encoding::Encoding::sniff(start) {
+        encoding::Encoding::Utf8 => {}
+        encoding::Encoding::Utf8Bom => reader.consume(encoding::Encoding::Utf8Bom.bom_len()),
+        // serde_json only reads UTF-8, so UTF-16 is converted whole first.
+        encoding::Encoding::Utf16Le | encoding::Encoding::Utf16Be => {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).with_context(|| format!("Failed to read file: {}", path.display()))?;
+            let content = encoding::decode_text(bytes)
+                .with_context(|| Phase::Parse.error(format!("Failed to read {} as text", save_file.location)))?;
+            return parse_save_json(save_file, &mut serde_json::Deserializer::from_str(&content));
+        }
+    }
+    parse_save_json(save_file, &mut serde_json::Deserializer::from_reader(reader))
+}
+
+fn parse_save_json<'de, R: serde_json::de::Read<'de>>(
+    save_file: &SaveFile,
+    deserializer: &mut serde_json::Deserializer<R>,
+) -> Result<SaveData> {
+    serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        Phase::Parse
+            .error(format!(
+                "Failed to parse {} at {}: {}",
+                save_file.location,
+                json_path(String::new(), err.path()),
+                err.inner()
+            ))
+            .into()
+    })
+}
+
+/// Splits a song id like `SongName.ArtistName.0` into its name, artist and
+/// numeric chart suffix. The suffix is taken off first; an id without a
+/// further `.` is all name and has no artist. Name and artist are NFC
+/// normalized, so ids that differ only in Unicode composition share a file.
+fn split_song_id(song_id: &str) -> (String, String, String) {
+    let (base, suffix) = split_revision(song_id);
+    let (name, artist) = base.rsplit_once('.').unwrap_or((base, ""));
+    (name.nfc().collect(), artist.nfc().collect(), suffix.to_string())
+}
+
+/// Splits off the numeric chart suffix, e.g. `Song.Artist.1` into
+/// `Song.Artist` and `1`; the suffix is empty if there is none.
+fn split_revision(song_id: &str) -> (&str, &str) {
+    match song_id.rsplit_once('.') {
+        Some((base, suffix)) if suffix.chars().all(|c| c.is_ascii_digit()) => (base, suffix),
+        _ => (song_id, ""),
+    }
+}
+
+/// How song ids that differ only in their chart suffix are exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RevisionPolicy {
+    /// As one song, interleaving the records of every revision.
+    Merge,
+    /// Only each save's highest revision of a song.
+    Latest,
+    /// As separate songs, with the suffix appended to the song name (except
+    /// for revision 0).
+    Separate,
+}
+
+/// The song ids in `song_ids` that are the highest revision of their song.
+fn latest_revisions<'a>(song_ids: impl Iterator<Item = &'a String>) -> HashSet<String> {
+    let mut latest: HashMap<&str, (Option<u64>, &str)> = HashMap::new();
+    for song_id in song_ids {
+        let (base, suffix) = split_revision(song_id);
+        // An id without a suffix sorts before every numbered revision.
+        let revision = (suffix.parse().ok(), song_id.as_str());
+        let entry = latest.entry(base).or_insert(revision);
+        if revision > *entry {
+            *entry = revision;
+        }
+    }
+    latest.into_values().map(|(_, song_id)| song_id.to_string()).collect()
+}
+
+/// When a save was captured: the time recorded in the save itself if there is
+/// one, otherwise the modification time of the save file, or empty if neither
+/// is available.
+fn captured_at(save_file: &SaveFile, save_info: &SaveInfo) -> String {
+    let in_save = save_info
+        .updated_at
+        .as_deref()
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&Utc));
+    let modified = || save_file.modified().map(DateTime::<Utc>::from);
+    in_save
+        .or_else(modified)
+        .map_or_else(String::new, |time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// The score of a perfect play.
+const MAX_SCORE: i32 = 1_000_000;
+
+/// Why a score entry's values can't be real: acc must be within 0-100 and
+/// score within 0-1,000,000, both inclusive.
+fn validate(score: i32, acc: f64) -> Option<String> {
+    if !acc.is_finite() || !(0.0..=100.0).contains(&acc) {
+        Some(format!("acc {} is outside 0-100", acc))
+    } else if !(0..=MAX_SCORE).contains(&score) {
+        Some(format!("score {} is outside 0-{}", score, MAX_SCORE))
+    } else {
+        None
+    }
+}
+
+/// The records of one save, plus a description of each score entry that
+/// could not be read and was left out, each entry with out-of-range values,
+/// and the summary fields that were missing and filled with placeholders,
+/// and what --lenient-json changed. The rest of the save is kept for the
+/// reports, so no save is read more than once.
+struct ProcessedSave {
+    records: Vec<ProcessedRecord>,
+    malformed: Vec<String>,
+    invalid: Vec<String>,
+    missing_summary: Vec<&'static str>,
+    lenient_fixes: Vec<String>,
+    save_info: SaveInfo,
+    game_progress: Option<Lenient<GameProgress>>,
+    user: Option<Lenient<User>>,
+    settings: Option<Lenient<Settings>>,
 }
 
-fn process_save_file(save_file_path: &Path) -> Result<Vec<ProcessedRecord>> {
-    let content = fs::read_to_string(save_file_path)
-        .with_context(|| format!("Failed to read file: {}", save_file_path.display()))?;
-    let save_data: SaveData = serde_json::from_str(&content)
-        .with_context(|| "Failed to parse JSON")?;
+/// Reads a save into records. Entries with out-of-range values are dropped
+/// unless `--keep-invalid` is set, in which case they are marked `invalid`.
+fn process_save_file(save_file: &SaveFile, config: &Config) -> Result<ProcessedSave> {
+    let save_data = read_save_file(save_file, config)?;
     let mut scores_and_rks = Vec::new();
-    let ranking_score = save_data.save_info.summary.ranking_score;
-    let game_version = save_data.save_info.summary.game_version.to_string();
-    let difficulties = ["EZ", "HD", "IN", "AT"];
-
-    for (song_id, song_scores) in save_data.game_record {
-        let song_name = song_id.rsplit_once('.').map_or(song_id.clone(), |(base, suffix)| {
-            if suffix.chars().all(|c| c.is_digit(10)) {
-                base.to_string()
-            } else {
-                song_id.clone()
+    let mut malformed = Vec::new();
+    let mut invalid = Vec::new();
+    let summary = &save_data.save_info.summary;
+    let missing_summary: Vec<_> = [
+        ("rankingScore", summary.ranking_score.is_none()),
+        ("gameVersion", summary.game_version.is_none()),
+    ]
+    .into_iter()
+    .filter_map(|(field, missing)| missing.then_some(field))
+    .collect();
+    if config.require_summary && !missing_summary.is_empty() {
+        return Err(Phase::Check.error(format!("saveInfo.summary is missing {}", missing_summary.join(", "))).into());
+    }
+    let ranking_score = summary.ranking_score.unwrap_or(0.0);
+    let game_version = summary.game_version.map_or_else(|| "unknown".to_string(), |version| version.to_string());
+    let player_id = save_file.player_id.clone();
+    let snapshot = save_file.snapshot.clone();
+    let avatar = save_data.save_info.summary.avatar.clone().unwrap_or_default();
+    let captured_at = captured_at(save_file, &save_data.save_info);
+    let latest = (config.revision_policy == RevisionPolicy::Latest)
+        .then(|| latest_revisions(save_data.game_record.keys()));
+
+    // The save's order is lost in the map; go by song id so every run lists
+    // records the same way.
+    let mut game_record: Vec<_> = save_data.game_record.into_iter().collect();
+    game_record.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (song_id, song_scores) in game_record {
+        if latest.as_ref().is_some_and(|latest| !latest.contains(&song_id)) {
+            continue;
+        }
+        let (mut song_name, artist, chart_suffix) = split_song_id(&song_id);
+        if config.revision_policy == RevisionPolicy::Separate && !chart_suffix.is_empty() && chart_suffix != "0" {
+            song_name = format!("{}.{}", song_name, chart_suffix);
+        }
+
+        for (index, score_record) in song_scores.iter().enumerate() {
+            let record = match score_record {
+                None => continue,
+                Some(Lenient(Ok(record))) => record,
+                Some(Lenient(Err(invalid))) => {
+                    let path = json_path(format!("gameRecord[{:?}][{}]", song_id, index), &invalid.path);
+                    malformed.push(format!("{}: {}", path, invalid.message));
+                    continue;
+                }
+            };
+            let problem = validate(record.score, record.acc);
+            if let Some(problem) = &problem {
+                invalid.push(format!("{} {}: {}", song_id, Difficulty::label(index), problem));
+                if !config.keep_invalid {
+                    continue;
+                }
             }
+            let difficulty = Difficulty::label(index);
+            let chart_constant = config.chart_constants.get(&song_id, &difficulty);
+            scores_and_rks.push(ProcessedRecord {
+                song_id: song_id.clone(),
+                player_id: player_id.clone(),
+                song_name: song_name.clone(),
+                artist: artist.clone(),
+                chart_suffix: chart_suffix.clone(),
+                difficulty,
+                score: record.score,
+                score_deficit: MAX_SCORE.saturating_sub(record.score),
+                acc: record.acc,
+                fc: record.fc,
+                ap: is_all_perfect(record.score, record.acc, record.fc),
+                grade: grade(record.score, record.fc).to_string(),
+                ranking_score,
+                game_version: game_version.clone(),
+                avatar: avatar.clone(),
+                captured_at: captured_at.clone(),
+                invalid: problem.is_some(),
+                snapshot: snapshot.clone(),
+                chart_constant,
+                play_rks: chart_constant.map(|constant| play_rks(constant, record.acc)),
+                acc_percentile: None,
+            });
+        }
+    }
+
+    Ok(ProcessedSave {
+        records: scores_and_rks,
+        malformed,
+        invalid,
+        missing_summary,
+        lenient_fixes: save_data.lenient_fixes,
+        save_info: save_data.save_info,
+        game_progress: save_data.game_progress,
+        user: save_data.user,
+        settings: save_data.settings,
+    })
+}
+
+/// Reads every save on rayon's threads, taking the saves that haven't changed
+/// from the cache, and hands each to `consume` with the time it took to
+/// read. `consume` runs on a thread of its own while later saves are still
+/// being read, and gets the saves in the order of `save_files` whatever order
+/// they finish in. If it fails, the saves being read are finished before its
+/// error is returned. Returns how long reading took.
+fn process_save_files<'a>(
+    save_files: &'a [SaveFile],
+    config: &Config,
+    cache: &Cache,
+    mut consume: impl FnMut(&'a SaveFile, Result<ProcessedSave>, Duration) -> Result<()> + Send,
+) -> Result<Duration> {
+    let started = Instant::now();
+    let read = |save_file: &SaveFile| {
+        let started = Instant::now();
+        let save = match cache.get(save_file, config) {
+            Some(save) => {
+                debug!("{}: unchanged since the last run", save_file.location);
+                Ok(save)
+            }
+            None => process_save_file(save_file, config),
+        };
+        if let Ok(save) = &save {
+            cache.insert(save_file, save);
+        }
+        (save, started.elapsed())
+    };
+    // With --jobs 1 everything stays on the one thread, one save after another.
+    if rayon::current_num_threads() == 1 {
+        for save_file in save_files {
+            let (save, elapsed) = read(save_file);
+            consume(save_file, save, elapsed)?;
+        }
+        return Ok(started.elapsed());
+    }
+    let (sender, receiver) = mpsc::channel();
+    // Set once `consume` has failed, after which the saves left aren't read.
+    let stopped = &AtomicBool::new(false);
+    thread::scope(|scope| {
+        let consumer = scope.spawn(move || -> Result<()> {
+            let mut waiting = BTreeMap::new();
+            let mut next = 0;
+            for (index, save, elapsed) in receiver {
+                waiting.insert(index, (save, elapsed));
+                while let Some((save, elapsed)) = waiting.remove(&next) {
+                    if let Err(err) = consume(&save_files[next], save, elapsed) {
+                        stopped.store(true, Ordering::Relaxed);
+                        return Err(err);
+                    }
+                    next += 1;
+                }
+            }
+            Ok(())
         });
+        save_files.par_iter().enumerate().filter(|_| !stopped.load(Ordering::Relaxed)).for_each_with(
+            sender,
+            |sender, (index, save_file)| {
+                let (save, elapsed) = read(save_file);
+                // Only fails once `consume` has failed, and its error is returned instead.
+                let _ = sender.send((index, save, elapsed));
+            },
+        );
+        let elapsed = started.elapsed();
+        consumer.join().unwrap_or_else(|panic| panic::resume_unwind(panic))?;
+        Ok(elapsed)
+    })
+}
+
+/// Warns about score entries past the known difficulties, once per song, and
+/// counts them in the run summary; with `--strict` they fail the run instead.
+fn check_unknown_difficulties(
+    save_file: &SaveFile,
+    records: &[ProcessedRecord],
+    strict: bool,
+    summary: &mut RunSummary,
+) -> Result<()> {
+    let mut unknown: BTreeMap<&str, usize> = BTreeMap::new();
+    for record in records {
+        if Difficulty::rank(&record.difficulty) >= Difficulty::ALL.len() {
+            *unknown.entry(&record.song_id).or_default() += 1;
+        }
+    }
+    for (song_id, count) in unknown {
+        if strict {
+            bail!(
+                "{}: {} has {} score entries beyond the known difficulties",
+                save_file.player_id,
+                song_id,
+                count
+            );
+        }
+        warn!(
+            "{}: {} has {} score entries beyond the known difficulties, exported as Unknown(n)",
+            save_file.player_id,
+            song_id,
+            count
+        );
+        summary.unknown_difficulty_entries += count;
+    }
+    Ok(())
+}
+
+/// For `--merge-case-insensitive`: maps each song name that differs from
+/// another only in case to the casing seen on the most records (the
+/// alphabetically first on a tie), which is used for the file and the
+/// song_name column.
+fn merge_case_variants(casings: &HashMap<String, usize>) -> HashMap<String, String> {
+    let mut groups: BTreeMap<String, Vec<(&str, usize)>> = BTreeMap::new();
+    for (name, count) in casings {
+        groups.entry(name.to_lowercase()).or_default().push((name, *count));
+    }
+    let mut aliases = HashMap::new();
+    for variants in groups.values_mut().filter(|variants| variants.len() > 1) {
+        variants.sort_by_key(|&(name, count)| (Reverse(count), name));
+        let canonical = variants[0].0;
+        let merged: Vec<_> = variants[1..].iter().map(|(name, _)| format!("`{}`", name)).collect();
+        info!("Merging {} into `{}`", merged.join(", "), canonical);
+        for (name, _) in &variants[1..] {
+            aliases.insert(name.to_string(), canonical.to_string());
+        }
+    }
+    aliases
+}
+
+/// Renames records to the canonical song names chosen by `merge_case_variants`.
+fn apply_song_aliases(records: &mut [ProcessedRecord], aliases: &HashMap<String, String>) {
+    for record in records {
+        if let Some(canonical) = aliases.get(&record.song_name) {
+            record.song_name = canonical.clone();
+        }
+    }
+}
+
+/// What is reported across all saves once they have been read: song ids
+/// that share a file, normalized names, charts without a constant and unused
+/// `--exclude-songs` entries.
+#[derive(Default)]
+struct SaveChecks {
+    // Records per exact song name, to pick the casing that case variants merge into.
+    casings: HashMap<String, usize>,
+    seen: HashSet<String>,
+    // Song ids by their id without the chart suffix, to spot revisions that
+    // end up in the same per-song file.
+    revisions: BTreeMap<String, BTreeSet<String>>,
+    // Raw song ids by normalized song name, for --report-normalization.
+    ids_by_name: BTreeMap<String, BTreeSet<String>>,
+    // Charts played but not listed in --constants.
+    missing_constants: BTreeSet<(String, String)>,
+}
+
+impl SaveChecks {
+    /// Reports what was wrong with one save, and returns it; `None` if it
+    /// could not be read.
+    fn add(
+        &mut self,
+        save_file: &SaveFile,
+        processed: Result<ProcessedSave>,
+        filter: &RecordFilter,
+        config: &Config,
+        summary: &mut RunSummary,
+    ) -> Result<Option<ProcessedSave>> {
+        let save = match processed {
+            Ok(save) => save,
+            Err(err) => {
+                let err = config.error_policy.handle(err, summary)?;
+                error!("{}: {:#}", save_file.player_id, err);
+                summary.failed_saves.push(SaveFailure::new(save_file, &err));
+                return Ok(None);
+            }
+        };
+        let ProcessedSave { records, malformed, invalid, missing_summary, lenient_fixes, .. } = &save;
+        info!("{}: {} records", save_file.player_id, records.len());
+        summary.timings.records += records.len();
+        if !lenient_fixes.is_empty() {
+            warn!(
+                "{}: only parsed with --lenient-json ({}), {} needs cleaning up",
+                save_file.player_id,
+                lenient_fixes.join(", "),
+                save_file.location
+            );
+        }
+        if !missing_summary.is_empty() {
+            warn!(
+                "{}: saveInfo.summary is missing {}, exported as placeholders (see --require-summary)",
+                save_file.player_id,
+                missing_summary.join(", ")
+            );
+        }
+        for entry in malformed {
+            config.error_policy.handle(anyhow!("{}: malformed score entry {}", save_file.player_id, entry), summary)?;
+            warn!("{}: skipped malformed score entry {}", save_file.player_id, entry);
+        }
+        summary.malformed_entries += malformed.len();
+        for entry in invalid {
+            if config.strict {
+                bail!("{}: {}", save_file.player_id, entry);
+            }
+            config.error_policy.handle(anyhow!("{}: {}", save_file.player_id, entry), summary)?;
+            if config.keep_invalid {
+                warn!("{}: kept invalid record {}", save_file.player_id, entry);
+            } else {
+                warn!("{}: dropped invalid record {}", save_file.player_id, entry);
+            }
+        }
+        if !invalid.is_empty() {
+            summary.invalid_records.push((save_file.player_id.clone(), invalid.len()));
+        }
+        check_unknown_difficulties(save_file, records, config.strict, summary)?;
+        for record in records {
+            if !record.chart_suffix.is_empty() {
+                let base = &record.song_id[..record.song_id.len() - record.chart_suffix.len() - 1];
+                self.revisions.entry(base.to_string()).or_default().insert(record.song_id.clone());
+            }
+            if config.report_normalization {
+                self.ids_by_name.entry(record.song_name.clone()).or_default().insert(record.song_id.clone());
+            }
+            if config.merge_case_insensitive {
+                *self.casings.entry(record.song_name.clone()).or_default() += 1;
+            }
+            self.seen.insert(record.song_id.clone());
+            self.seen.insert(record.song_name.clone());
+            if filter.matches(record) && config.constants.is_some() && record.chart_constant.is_none() {
+                summary.missing_constant_records += 1;
+                self.missing_constants.insert((record.song_id.clone(), record.difficulty.clone()));
+            }
+        }
+        Ok(Some(save))
+    }
 
-        for (i, score_record) in song_scores.iter().enumerate().take(4) {
-            if let Some(record) = score_record {
-                scores_and_rks.push(ProcessedRecord {
-                    song_name: song_name.clone(),
-                    difficulty: difficulties[i].to_string(),
-                    score: record.score,
-                    acc: record.acc,
-                    fc: record.fc,
-                    ranking_score,
-                    game_version: game_version.clone(),
-                });
+    /// Reports what spans several saves, and returns the renames that merge
+    /// case variants with `--merge-case-insensitive`.
+    fn finish(self, filter: &RecordFilter, config: &Config, summary: &mut RunSummary) -> HashMap<String, String> {
+        if config.with_chart_id && config.revision_policy != RevisionPolicy::Separate {
+            for ids in self.revisions.values().filter(|ids| ids.len() > 1) {
+                let ids: Vec<_> = ids.iter().map(String::as_str).collect();
+                warn!("Song ids differ only by chart suffix and share one file: {}", ids.join(", "));
             }
         }
+        for (name, ids) in &self.ids_by_name {
+            if ids.iter().any(|id| !is_nfc(id)) {
+                let ids: Vec<_> = ids.iter().map(|id| format!("{:?}", id)).collect();
+                info!("Normalized to `{}`: {}", name, ids.join(", "));
+            }
+        }
+        for (song_id, difficulty) in &self.missing_constants {
+            debug!("No chart constant for {} {}", song_id, difficulty);
+        }
+        summary.missing_constant_charts = self.missing_constants.len();
+        for entry in filter.unmatched_excluded_songs(&self.seen) {
+            warn!("--exclude-songs entry `{}` matched no song", entry);
+        }
+        merge_case_variants(&self.casings)
     }
+}
+
+/// The saves that could be read, each with the file it was read from.
+type Saves<'a> = Vec<(&'a SaveFile, ProcessedSave)>;
 
-    Ok(scores_and_rks)
+/// Reads the saves, reporting what was wrong with each as soon as it and the
+/// saves before it are read, and collects the saves that could be read, in
+/// the order of the saves. Their selected records are grouped by song while
+/// later saves are still being read; with `--merge-case-insensitive` also
+/// returns the renames that merge case variants, already applied to the
+/// groups.
+fn collect_saves<'a>(
+    save_files: &'a [SaveFile],
+    config: &Config,
+    cache: &Cache,
+    filter: &RecordFilter,
+    summary: &mut RunSummary,
+) -> Result<(Saves<'a>, Songs, HashMap<String, String>)> {
+    let mut checks = SaveChecks::default();
+    let mut saves = Vec::new();
+    let mut songs = Songs::new();
+    let mut checking = Duration::ZERO;
+    let reading = process_save_files(save_files, config, cache, |save_file, processed, elapsed| {
+        let started = Instant::now();
+        summary.timings.add_save(elapsed);
+        if let Some(save) = checks.add(save_file, processed, filter, config, summary)? {
+            add_to_songs(&mut songs, save.records.iter().cloned(), filter);
+            saves.push((save_file, save));
+        }
+        checking += started.elapsed();
+        Ok(())
+    })?;
+    summary.timings.parse += reading;
+    summary.timings.group += checking;
+    let aliases = checks.finish(filter, config, summary);
+    // Which names merge is only known once every save is read.
+    if !aliases.is_empty() {
+        let started = Instant::now();
+        songs = group_by_song(&saves, filter, &aliases);
+        summary.timings.group += started.elapsed();
+    }
+    Ok((saves, songs, aliases))
 }
 
-fn get_all_song_names(save_data_dir: &Path) -> Result<Vec<String>> {
-    let mut song_names: HashSet<String> = HashSet::new();
-    for entry in WalkDir::new(save_data_dir)
-        .min_depth(1)
-        .max_depth(1)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_dir() {
-            let save_file_path = entry.path().join("save.json");
-            if let Ok(content) = fs::read_to_string(&save_file_path) {
-                if let Ok(save_data) = serde_json::from_str::<SaveData>(&content) {
-                    for (song_id, _) in save_data.game_record {
-                        let song_name = song_id.rsplit_once('.').map_or(song_id.clone(), |(base, suffix)| {
-                            if suffix.chars().all(|c| c.is_digit(10)) {
-                                base.to_string()
-                            } else {
-                                song_id.clone()
-                            }
-                        });
-                        song_names.insert(song_name);
-                    }
+/// The selected records of the run by song name.
+type Songs = HashMap<String, Vec<ProcessedRecord>>;
+
+/// Appends the selected `records` to their songs, keeping their order.
+fn add_to_songs(songs: &mut Songs, records: impl Iterator<Item = ProcessedRecord>, filter: &RecordFilter) {
+    for record in records {
+        if filter.matches(&record) {
+            trace!("{}: {:?}", record.player_id, record);
+            songs.entry(record.song_name.clone()).or_default().push(record);
+        }
+    }
+}
+
+/// Groups the selected records of the saves by song, after merging names
+/// with `aliases`. Within a song the records stay in the order of the saves.
+fn group_by_song(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+) -> Songs {
+    let mut records: Vec<_> = saves.iter().flat_map(|(_, save)| save.records.iter().cloned()).collect();
+    apply_song_aliases(&mut records, aliases);
+    let mut songs = Songs::new();
+    add_to_songs(&mut songs, records.into_iter(), filter);
+    songs
+}
+
+/// Each player's selected records across all of their saves, after merging
+/// names with `aliases`. Keyed by [`input::player_key`], so a player found
+/// under several inputs, or with several snapshots, is one player.
+fn records_by_player(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+) -> BTreeMap<String, Vec<ProcessedRecord>> {
+    let mut players: BTreeMap<String, Vec<ProcessedRecord>> = BTreeMap::new();
+    for (save_file, save) in saves {
+        let mut records = save.records.clone();
+        apply_song_aliases(&mut records, aliases);
+        players.entry(save_file.player().to_string()).or_default().extend(
+            records.into_iter().filter(|record| filter.matches(record) && filter.matches_song(&record.song_name)),
+        );
+    }
+    players
+}
+
+/// The file name for a player's records; nested player ids from --recursive
+/// are flattened, e.g. `player/date` becomes `player_date`.
+fn player_file_stem(player_id: &str) -> String {
+    output::sanitize_filename(player_id)
+}
+
+/// The per-song files of one song (or one of its difficulties with
+/// --split-by), written once every song has been collected.
+struct PendingFiles {
+    song_name: String,
+    records: Vec<ProcessedRecord>,
+    outputs: Vec<(OutputFormat, PathBuf)>,
+}
+
+/// Writes the per-song files in parallel, on the --jobs threads. Each file gets
+/// its own writer and temporary file, which is removed if writing fails. With
+/// `--error-policy fail-fast` no more files are started once one has failed.
+/// Returns the indices in `pending` of the songs a file failed for.
+fn write_song_files(
+    pending: &[PendingFiles],
+    config: &Config,
+    options: &OutputOptions,
+    dedupe_on: &[Column],
+    summary: &mut RunSummary,
+) -> Result<BTreeSet<usize>> {
+    let jobs: Vec<_> = pending
+        .iter()
+        .enumerate()
+        .flat_map(|(index, files)| files.outputs.iter().map(move |(format, path)| (index, files, *format, path)))
+        .collect();
+    let stopped = AtomicBool::new(false);
+    let written: Vec<_> = jobs
+        .par_iter()
+        .filter(|_| !stopped.load(Ordering::Relaxed))
+        .map(|&(index, files, format, path)| {
+            let started = Instant::now();
+            let written = if config.append && format == OutputFormat::Csv {
+                output::merge_existing_csv(path, &files.records, options, dedupe_on).and_then(|mut merged| {
+                    sort_records(&mut merged, &config.sort_by);
+                    format.write(&merged, path, options)
+                })
+            } else {
+                format.write(&files.records, path, options)
+            };
+            if written.is_err() && config.error_policy == ErrorPolicy::FailFast {
+                stopped.store(true, Ordering::Relaxed);
+            }
+            (index, format, started.elapsed(), written.err())
+        })
+        .collect();
+    let mut failed = BTreeSet::new();
+    for (index, format, elapsed, failure) in written {
+        summary.timings.add_write(format.extension(), elapsed);
+        if let Some(err) = failure {
+            let err = config.error_policy.handle(err, summary)?;
+            error!("{}: {:#}", pending[index].song_name, err);
+            summary.files_failed += 1;
+            failed.insert(index);
+        }
+    }
+    Ok(failed)
+}
+
+/// `--group-by player`: one file per player directory with all of that
+/// player's records across every selected song.
+fn write_player_files(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+    config: &Config,
+    options: &OutputOptions,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<()> {
+    let output_dir = config.output_dir();
+    if let Some(format) = config.formats.iter().find(|format| !format.is_per_song()) {
+        bail!("{} output is not supported with --group-by player", format.extension());
+    }
+    if options.jsonl_single || options.xlsx_single_workbook || options.parquet_combined || options.split_by.is_some() {
+        bail!("--group-by player cannot be combined with single-file or split outputs");
+    }
+
+    let mut players = records_by_player(saves, filter, aliases);
+    players.retain(|_, records| !records.is_empty());
+    if config.with_percentiles {
+        percentile::annotate(players.values_mut().flatten().collect());
+    }
+
+    let mut dry_run_plan = Vec::new();
+    for (player, records) in &mut players {
+        sort_records(records, &config.sort_by);
+        let mut paths = Vec::new();
+        for &format in &config.formats {
+            let path = output_path(output_dir, &player_file_stem(player), format, options);
+            if !claim_output_file(&path, config.existing, summary)? {
+                continue;
+            }
+            if !dry_run {
+                if let Err(err) = format.write(records, &path, options) {
+                    let err = config.error_policy.handle(err, summary)?;
+                    error!("{}: {:#}", player, err);
+                    summary.files_failed += 1;
+                    continue;
                 }
             }
+            paths.push(path);
+        }
+        debug!("{}: {} records", player, records.len());
+        if dry_run {
+            dry_run_plan.push((player.as_str(), records.len(), paths));
+        } else {
+            summary.players_written += 1;
+        }
+    }
+    if dry_run {
+        print_dry_run_plan(&dry_run_plan);
+    }
+    Ok(())
+}
+
+/// The reports the run writes, for refusing existing files before anything
+/// is written. Reports with a file per player or per song are planned by
+/// their writers as with --dry-run, so only the files they would write are
+/// listed.
+fn planned_report_paths(
+    saves: &[(&SaveFile, ProcessedSave)],
+    songs: &Songs,
+    song_names: &[String],
+    stems: &mut FileStems,
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+    config: &Config,
+) -> Result<Vec<PathBuf>> {
+    let output_dir = config.output_dir();
+    let by_song = config.group_by != GroupBy::Player;
+    let reports = [
+        (config.timings, TIMINGS_FILE),
+        (config.player_summary, PLAYER_SUMMARY_FILE),
+        (config.fc_ap_summary, FC_AP_SUMMARY_FILE),
+        (config.player_summary_out, PLAYER_TOTALS_FILE),
+        (config.player_summary_out && config.formats.contains(&OutputFormat::Xlsx), SUMMARY_WORKBOOK_FILE),
+        (config.songlist.is_some(), COMPLETION_FILE),
+        (config.songlist.is_some(), UNPLAYED_CHARTS_FILE),
+        (config.rks_drift, RKS_DRIFT_FILE),
+        (config.progress_out, PROGRESS_FILE),
+        (config.stats && by_song, STATS_FILE),
+    ];
+    let mut planned: Vec<_> =
+        reports.into_iter().filter(|&(written, _)| written).map(|(_, file)| output_dir.join(file)).collect();
+    planned.extend(config.profiles_out.clone());
+
+    // Existing files are listed rather than refused by the writers.
+    let mut config = config.clone();
+    config.existing = ExistingFiles::Overwrite;
+    let config = &config;
+    let summary = &mut RunSummary::default();
+    if config.global_leaderboard {
+        planned.extend(leaderboard::write_global_leaderboard(saves, config, true, summary)?);
+    }
+    if config.rks_timeline {
+        planned.extend(timeline::write_timelines(saves, config, true, summary)?);
+    }
+    if config.history {
+        planned.extend(history::write_histories(saves, filter, aliases, config, true, summary)?);
+    }
+    if let Some(n) = config.best_n {
+        planned.extend(best::write_best_files(saves, filter, aliases, config, n, true, summary)?);
+    }
+    if config.push_acc {
+        planned.extend(push_acc::write_push_acc(saves, filter, aliases, config, true, summary)?);
+    }
+    if config.suggestions {
+        planned.extend(suggestions::write_suggestions(saves, filter, aliases, config, true, summary)?);
+    }
+    if !by_song {
+        return Ok(planned);
+    }
+    for song_name in song_names {
+        let Some(records) = songs.get(song_name).filter(|records| !records.is_empty()) else {
+            continue;
+        };
+        let stem = stems.stem(song_name);
+        if config.histograms {
+            planned.extend(histogram::write_song_histograms(stem, records, config, true, summary)?);
+        }
+        #[cfg(feature = "plots")]
+        if config.plots {
+            planned.extend(plots::write_song_plots(stem, records, config, true, summary)?);
+        }
+        if config.leaderboards {
+            planned.extend(leaderboard::write_song_leaderboards(stem, records, config, true, summary)?);
         }
     }
-    let mut names: Vec<_> = song_names.into_iter().collect();
-    names.sort();
-    Ok(names)
+    Ok(planned)
 }
 
-fn write_to_csv(records: &[ProcessedRecord], output_path: &Path) -> Result<()> {
-    let mut writer = csv::Writer::from_path(output_path)?;
-    records.iter().for_each(|record| {
-        writer.serialize(record).unwrap();
-    });
-    writer.flush()?;
+/// Fails listing the files of `planned` that already exist, if any.
+fn refuse_existing(planned: Vec<PathBuf>) -> Result<()> {
+    let conflicts: Vec<_> = planned.into_iter().filter(|path| path.exists()).collect();
+    if !conflicts.is_empty() {
+        bail!(
+            "Refusing to overwrite {} existing files (use --force or --skip-existing):\n  {}",
+            conflicts.len(),
+            conflicts.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join("\n  ")
+        );
+    }
     Ok(())
 }
 
-fn write_to_excel(records: &[ProcessedRecord], output_path: &Path) -> Result<()> {
-    let workbook = xlsxwriter::Workbook::new(output_path.to_str().unwrap())?;
-    let mut sheet = workbook.add_worksheet(None)?;
+/// Whether a run-wide file such as `player_summary.csv` should be written,
+/// honoring --force / --skip-existing.
+fn claim_output_file(path: &Path, existing: ExistingFiles, summary: &mut RunSummary) -> Result<bool> {
+    let exists = path.exists();
+    if exists && existing == ExistingFiles::Error {
+        bail!("Refusing to overwrite {} (use --force or --skip-existing)", path.display());
+    }
+    let skip = exists && existing == ExistingFiles::Skip;
+    if skip {
+        summary.files_skipped += 1;
+    }
+    Ok(!skip)
+}
+
+fn print_dry_run_plan(plan: &[(&str, usize, Vec<PathBuf>)]) {
+    let name_width = plan.iter().map(|(name, _, _)| name.chars().count()).max().unwrap_or(0).max(4);
+    println!("{:<name_width$}  {:>7}  outputs", "song", "records");
+    for (song_name, count, paths) in plan {
+        let paths: Vec<_> = paths.iter().map(|path| path.display().to_string()).collect();
+        println!("{:<name_width$}  {:>7}  {}", song_name, count, paths.join(", "));
+    }
+}
 
-    let headers = ["song_name", "difficulty", "score", "acc", "fc", "ranking_score", "game_version"];
-    for (i, header) in headers.iter().enumerate() {
-        sheet.write_string(0, i as u16, header, None)?;
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    logging::init(cli.verbose, cli.quiet);
+    match configure_and_run(&cli) {
+        Ok(()) => Exit::Success.into(),
+        Err(err) => {
+            // What returning the error from main would print.
+            eprintln!("Error: {:?}", err);
+            Exit::of(&err).into()
+        }
     }
+}
 
-    records.iter().enumerate().for_each(|(row, record)| {
-        let row = row + 1;
-        sheet.write_string(row as u32, 0, &record.song_name, None).unwrap();
-        sheet.write_string(row as u32, 1, &record.difficulty, None).unwrap();
-        sheet.write_number(row as u32, 2, record.score as f64, None).unwrap();
-        sheet.write_number(row as u32, 3, record.acc, None).unwrap();
-        sheet.write_boolean(row as u32, 4, record.fc, None).unwrap();
-        sheet.write_number(row as u32, 5, record.ranking_score, None).unwrap();
-        sheet.write_string(row as u32, 6, &record.game_version, None).unwrap();
-    });
+fn configure_and_run(cli: &Cli) -> Result<()> {
+    let mut config = Config::load(cli.config.as_deref())?;
+    config.apply_cli(cli);
 
-    workbook.close()?;
+    if cli.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+    if config.jobs == Some(0) {
+        bail!("--jobs must be at least 1");
+    }
+    // A pool of our own rather than rayon's global one; with one thread the
+    // saves are read and the files written one after another, in order.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.jobs.unwrap_or(0))
+        .build()
+        .context("Failed to start the worker threads")?;
+    debug!("Running {} jobs", pool.current_num_threads());
+    pool.install(|| run(cli, config))
+}
+
+/// Fails on options that cannot be honored, such as reports that need
+/// chart constants when --constants is not given.
+fn check_options(config: &Config) -> Result<()> {
+    if config.best_n.is_some() && config.constants.is_none() {
+        bail!("--best-n ranks plays by their rks, which needs chart constants from --constants");
+    }
+    if !(config.histogram_bin_width > 0.0 && config.histogram_bin_width.is_finite()) {
+        bail!("--histogram-bin-width must be a positive number, got {}", config.histogram_bin_width);
+    }
+    if config.plots && !cfg!(feature = "plots") {
+        bail!("--plots requires building with `--features plots`");
+    }
+    if config.rks_timeline && !cfg!(feature = "plots") {
+        warn!("Built without the plots feature, --rks-timeline writes only the csv files");
+    }
+    if config.rks_drift && config.constants.is_none() {
+        bail!("--rks-drift recomputes rks from the records, which needs chart constants from --constants");
+    }
+    if config.push_acc && config.constants.is_none() {
+        bail!("--push-acc works out rks from the records, which needs chart constants from --constants");
+    }
+    if config.suggestions && config.constants.is_none() {
+        bail!("--suggestions works out rks from the records, which needs chart constants from --constants");
+    }
+    if !(config.suggestion_step > 0.0 && config.suggestion_step.is_finite()) {
+        bail!("--suggestion-step must be a positive number, got {}", config.suggestion_step);
+    }
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let save_data_dir = PathBuf::from("saveData");
-    let output_dir = PathBuf::from("rks_data_output");
+fn run(cli: &Cli, mut config: Config) -> Result<()> {
+    if let Some(path) = &config.constants {
+        config.chart_constants = ChartConstants::load(path)?;
+    }
+    if let Some(Command::Diff { old, new, out }) = &cli.command {
+        return diff::run(old, new, out.as_deref(), &config);
+    }
+    check_options(&config)?;
 
-    fs::create_dir_all(&output_dir)?;
+    let save_data_dirs = &config.input;
+    let output_dir = config.output_dir();
+    let to_stdout = config.writes_to_stdout();
 
-    let song_names = get_all_song_names(&save_data_dir)?;
+    if to_stdout {
+        if config.layout == Layout::Wide {
+            bail!("--layout wide cannot be written to stdout");
+        }
+        if config.group_by == GroupBy::Player {
+            bail!("--group-by player cannot be written to stdout");
+        }
+        if let Some(format) = config.formats.iter().find(|&&format| format != OutputFormat::Csv) {
+            bail!(
+                "{} output cannot be written to stdout, use --format csv",
+                format.extension()
+            );
+        }
+    }
 
-    for song_name in &song_names {
-        let mut all_song_data = Vec::new();
-        for entry in WalkDir::new(&save_data_dir)
-            .min_depth(1)
-            .max_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
+    let tokens = config.session_tokens()?;
+    if !tokens.is_empty() {
+        if !cfg!(feature = "cloud") {
+            bail!("fetching cloud saves requires building with `--features cloud`");
+        }
+        let Some(save_data_dir) = save_data_dirs.first().filter(|dir| !input::is_zip(dir)) else {
+            bail!("Cloud saves are written into the first --input, which must be a directory");
+        };
+        fs::create_dir_all(save_data_dir)?;
+        #[cfg(feature = "cloud")]
         {
-            if entry.file_type().is_dir() {
-                let save_file_path = entry.path().join("save.json");
-                if let Ok(scores_and_rks) = process_save_file(&save_file_path) {
-                    let song_data: Vec<_> = scores_and_rks
-                        .into_iter()
-                        .filter(|entry| entry.song_name == *song_name)
-                        .collect();
-                    all_song_data.extend(song_data);
+            let fetched = cloud::fetch_saves(&tokens, save_data_dir);
+            info!("Fetched {} of {} cloud saves into {}", fetched, tokens.len(), save_data_dir.display());
+            if cli.fetch_only && fetched < tokens.len() && !config.allow_errors {
+                return Err(SavesFailed { failed: tokens.len() - fetched, found: tokens.len() }.into());
+            }
+        }
+        if cli.fetch_only {
+            return Ok(());
+        }
+    }
+
+    for save_data_dir in save_data_dirs {
+        if !save_data_dir.is_dir() && !input::is_zip(save_data_dir) {
+            let absolute = std::path::absolute(save_data_dir).unwrap_or_else(|_| save_data_dir.clone());
+            bail!(
+                "Input directory does not exist: {} (from the {}); pass the directory with the player folders \
+                 with --input",
+                absolute.display(),
+                config.input_source
+            );
+        }
+    }
+    if !to_stdout && !config.allow_nested_output {
+        input::check_output_dir(save_data_dirs, output_dir)?;
+    }
+    let scanned_output_dir = (!to_stdout).then_some(output_dir);
+    if let Some(Command::EstimateConstants { out, min_samples }) = &cli.command {
+        let player_filter = PlayerFilter::from_config(&config)?;
+        let save_files = find_save_files(
+            save_data_dirs,
+            scanned_output_dir,
+            &player_filter,
+            &config.save_pattern,
+            config.recursive,
+            &mut RunSummary::default(),
+        )?;
+        let path = out.clone().unwrap_or_else(|| output_dir.join(ESTIMATED_CONSTANTS_FILE));
+        return estimate::run(&save_files, &config, *min_samples, &path);
+    }
+
+    if !cli.dry_run && !to_stdout {
+        fs::create_dir_all(output_dir)?;
+    }
+
+    let mut summary = RunSummary::default();
+    let filter = RecordFilter::from_config(&config)?;
+    let output_options = OutputOptions::from_config(&config);
+    let file_options = output_options.for_split_files(config.keep_difficulty_column);
+    let player_filter = PlayerFilter::from_config(&config)?;
+    let jsonl_single = output_options.jsonl_single && config.formats.contains(&OutputFormat::Jsonl);
+    let xlsx_single_workbook =
+        output_options.xlsx_single_workbook && config.formats.contains(&OutputFormat::Xlsx);
+    let combined = config.combined || config.combined_only;
+    let combined_formats: Vec<_> = [OutputFormat::Csv, OutputFormat::Xlsx, OutputFormat::Msgpack]
+        .into_iter()
+        .filter(|format| combined && !to_stdout && config.formats.contains(format))
+        .collect();
+    // Formats that would be per-song but were folded into one file for the run.
+    let single_file = |format| {
+        config.combined_only
+            || (jsonl_single && format == OutputFormat::Jsonl)
+            || (xlsx_single_workbook && format == OutputFormat::Xlsx)
+    };
+    let parquet_combined = output_options.parquet_combined && config.formats.contains(&OutputFormat::Parquet);
+    let dedupe_on = if config.dedupe_on.is_empty() {
+        file_options.columns.clone()
+    } else {
+        config.dedupe_on.clone()
+    };
+    if config.append {
+        if config.layout == Layout::Wide || output_options.compression != Compression::None {
+            bail!("--append only works with plain long-layout csv");
+        }
+        if let Some(column) = dedupe_on.iter().find(|column| !file_options.columns.contains(column)) {
+            bail!("--dedupe-on column `{}` is not among the exported columns", column.name());
+        }
+    }
+    if config.formats.contains(&OutputFormat::Parquet) && !cfg!(feature = "parquet") {
+        bail!("parquet output requires building with `--features parquet`");
+    }
+    if config.formats.contains(&OutputFormat::Msgpack) && !cfg!(feature = "msgpack") {
+        bail!("msgpack output requires building with `--features msgpack`");
+    }
+    if config.low_memory {
+        low_memory::check_options(&config, to_stdout)?;
+    }
+    let scan_started = Instant::now();
+    let save_files = find_save_files(
+        save_data_dirs,
+        scanned_output_dir,
+        &player_filter,
+        &config.save_pattern,
+        config.recursive,
+        &mut summary,
+    )?;
+    summary.timings.scan = scan_started.elapsed();
+    if save_files.is_empty() {
+        return Err(NoSaves {
+            input: save_data_dirs.clone(),
+            save_pattern: config.save_pattern.clone(),
+            dirs_scanned: summary.dirs_scanned,
+            players_skipped: summary.players_not_allowed + summary.players_denied,
+        }
+        .into());
+    }
+    let errors_path = output_dir.join(ERRORS_FILE);
+    let write_errors =
+        config.errors_json && !to_stdout && claim_output_file(&errors_path, config.existing, &mut summary)?;
+    let errors_path = write_errors.then_some(errors_path.as_path());
+    if config.low_memory {
+        let written =
+            low_memory::write_song_files(&save_files, &filter, &config, &file_options, cli.dry_run, &mut summary)?;
+        if cli.dry_run {
+            let plan: Vec<_> =
+                written.iter().map(|(song_name, rows, path)| (song_name.as_str(), *rows, vec![path.clone()])).collect();
+            print_dry_run_plan(&plan);
+        }
+        return finish_run(&summary, &config, None, errors_path, cli.dry_run);
+    }
+    let mut cache = if config.cache && !to_stdout {
+        Cache::load(output_dir, &config, cli.rebuild_cache)
+    } else {
+        Cache::disabled()
+    };
+    // Each save is read once; from here on everything works from what it was read into.
+    let (saves, mut songs, song_aliases) = collect_saves(&save_files, &config, &cache, &filter, &mut summary)?;
+    let mut song_names = get_all_song_names(&songs);
+
+    let unmatched = filter.unmatched_songs(&song_names);
+    if !unmatched.is_empty() {
+        bail!(
+            "No song matches {} ({} songs known)",
+            unmatched.iter().map(|p| format!("`{}`", p)).collect::<Vec<_>>().join(", "),
+            song_names.len()
+        );
+    }
+    song_names.retain(|name| filter.matches_song(name));
+
+    let mut stems = FileStems::new(&song_names);
+    // Every file the run writes is checked before the first one is, so that
+    // an existing file never fails the run halfway.
+    if config.existing == ExistingFiles::Error && !to_stdout {
+        let output_options = &output_options;
+        let mut planned =
+            planned_report_paths(&saves, &songs, &song_names, &mut stems, &filter, &song_aliases, &config)?;
+        if !stems.renamed().is_empty() && config.group_by != GroupBy::Player {
+            planned.push(output_dir.join(FILENAME_MAP_FILE));
+        }
+        if config.group_by == GroupBy::Player {
+            for (player, records) in records_by_player(&saves, &filter, &song_aliases) {
+                if !records.is_empty() {
+                    let stem = player_file_stem(&player);
+                    planned.extend(
+                        config.formats.iter().map(|&format| output_path(output_dir, &stem, format, output_options)),
+                    );
                 }
             }
+        } else {
+            let per_song_formats: Vec<_> = config
+                .formats
+                .iter()
+                .copied()
+                .filter(|&format| format.is_per_song() && !single_file(format))
+                .filter(|&format| !(config.append && format == OutputFormat::Csv))
+                .collect();
+            let stems: Vec<_> = song_names
+                .iter()
+                .flat_map(|song_name| {
+                    let stem = stems.stem(song_name).to_string();
+                    match output_options.split_by {
+                        None => vec![stem],
+                        Some(SplitBy::Difficulty) => {
+                            let records = songs.get(song_name).map_or(&[][..], Vec::as_slice);
+                            output::split_by_difficulty(&stem, records).into_iter().map(|(stem, _)| stem).collect()
+                        }
+                    }
+                })
+                .collect();
+            planned.extend(stems.iter().flat_map(|stem| {
+                per_song_formats.iter().map(move |&format| output_path(output_dir, stem, format, output_options))
+            }));
+            if jsonl_single {
+                planned.push(output_path(output_dir, ALL_RECORDS, OutputFormat::Jsonl, output_options));
+            }
+            if parquet_combined {
+                planned.push(output_path(output_dir, ALL_RECORDS, OutputFormat::Parquet, output_options));
+            }
+            if xlsx_single_workbook {
+                planned.push(output_dir.join(WORKBOOK_FILE));
+            }
+            for &format in &combined_formats {
+                planned.push(output_path(output_dir, ALL_RECORDS, format, output_options));
+            }
+            if config.formats.contains(&OutputFormat::Html) {
+                planned.push(output_dir.join(REPORT_FILE));
+            }
         }
+        refuse_existing(planned)?;
+    }
+    let renamed = stems.renamed();
+    let filename_map_path = output_dir.join(FILENAME_MAP_FILE);
+    let write_filename_map = !renamed.is_empty()
+        && !to_stdout
+        && config.group_by != GroupBy::Player
+        && claim_output_file(&filename_map_path, config.existing, &mut summary)?;
+    if write_filename_map && !cli.dry_run {
+        output::write_filename_map(&renamed, &filename_map_path)?;
+    }
+    let renamed_songs = renamed.len();
+
+    let timings_path = output_dir.join(TIMINGS_FILE);
+    let write_timings =
+        config.timings && !to_stdout && claim_output_file(&timings_path, config.existing, &mut summary)?;
+    let timings_path = write_timings.then_some(timings_path.as_path());
+
+    let report_plan = write_reports(&saves, &filter, &song_aliases, &config, cli.dry_run, &mut summary)?;
+
+    if config.group_by == GroupBy::Player {
+        write_player_files(&saves, &filter, &song_aliases, &config, &output_options, cli.dry_run, &mut summary)?;
+        if !cli.dry_run {
+            cache.write();
+        }
+        return finish_run(&summary, &config, timings_path, errors_path, cli.dry_run);
+    }
+
+    let mut stdout_writer = if to_stdout && !cli.dry_run {
+        Some(output::stdout_csv_writer(&output_options)?)
+    } else {
+        None
+    };
+
+    let mut run_files = RunFiles::create(&config, &output_options, song_names.len(), cli.dry_run, &mut summary)?;
+    let mut combined_records = Vec::new();
+
+    let mut dry_run_plan = Vec::new();
+    let mut pending = Vec::new();
+    let mut written_songs = Vec::new();
+    // Part of each song's hash, so changing the options rewrites every song.
+    let config_toml = config.to_toml()?;
+    for song_name in &song_names {
+        let mut all_song_data = songs.remove(song_name).unwrap_or_default();
+        let stem = stems.stem(song_name).to_string();
+        debug!("{}: {} records", song_name, all_song_data.len());
 
         if !all_song_data.is_empty() {
-            let csv_path = output_dir.join(format!("{}.csv", song_name));
-            let xlsx_path = output_dir.join(format!("{}.xlsx", song_name));
+            if config.with_percentiles {
+                percentile::annotate(all_song_data.iter_mut().collect());
+            }
+            sort_records(&mut all_song_data, &config.sort_by);
+            let record_count = all_song_data.len();
+            let mut paths = Vec::new();
+            if to_stdout {
+                if let Some(writer) = &mut stdout_writer {
+                    output::write_csv_rows(writer, &all_song_data, &output_options)?;
+                }
+                paths.push(PathBuf::from("-"));
+            } else {
+                run_files.add_song(song_name, &all_song_data, &output_options)?;
+                if config.histograms {
+                    paths.extend(histogram::write_song_histograms(
+                        &stem,
+                        &all_song_data,
+                        &config,
+                        cli.dry_run,
+                        &mut summary,
+                    )?);
+                }
+                #[cfg(feature = "plots")]
+                if config.plots {
+                    paths.extend(plots::write_song_plots(
+                        &stem,
+                        &all_song_data,
+                        &config,
+                        cli.dry_run,
+                        &mut summary,
+                    )?);
+                }
+                if config.leaderboards {
+                    paths.extend(leaderboard::write_song_leaderboards(
+                        &stem,
+                        &all_song_data,
+                        &config,
+                        cli.dry_run,
+                        &mut summary,
+                    )?);
+                }
+                if !combined_formats.is_empty() {
+                    combined_records.extend(all_song_data.iter().cloned());
+                }
+                let files = match output_options.split_by {
+                    None => vec![(stem.clone(), all_song_data)],
+                    Some(SplitBy::Difficulty) => output::split_by_difficulty(&stem, &all_song_data),
+                };
+                for (stem, records) in files {
+                    let hash = cache::song_hash(&records, &config_toml);
+                    let mut outputs = Vec::new();
+                    for &format in &config.formats {
+                        if !format.is_per_song() || single_file(format) {
+                            continue;
+                        }
+                        let path = output_path(output_dir, &stem, format, &file_options);
+                        let append = config.append && format == OutputFormat::Csv;
+                        if !append && !claim_output_file(&path, config.existing, &mut summary)? {
+                            continue;
+                        }
+                        outputs.push((format, path.clone()));
+                        paths.push(path);
+                    }
+                    if cache.song_unchanged(&stem, hash) && outputs.iter().all(|(_, path)| path.exists()) {
+                        summary.files_unchanged += outputs.len();
+                    } else if !cli.dry_run && !outputs.is_empty() {
+                        written_songs.push((stem, hash));
+                        pending.push(PendingFiles { song_name: song_name.clone(), records, outputs });
+                    }
+                }
+            }
+            if cli.dry_run {
+                dry_run_plan.push((song_name.as_str(), record_count, paths));
+            } else {
+                summary.songs_written += 1;
+            }
+        }
+    }
+
+    let failed_songs = write_song_files(&pending, &config, &file_options, &dedupe_on, &mut summary)?;
+    // A song whose file failed is written again next run.
+    for (index, (stem, hash)) in written_songs.iter().enumerate() {
+        if !failed_songs.contains(&index) {
+            cache.song_written(stem, *hash);
+        }
+    }
+    if let Some(writer) = stdout_writer {
+        output::finish_csv(writer)?;
+    }
+    if !combined_formats.is_empty() {
+        let (options, dry_run) = (&output_options, cli.dry_run);
+        let paths = write_combined(&mut combined_records, &combined_formats, &config, options, dry_run, &mut summary)?;
+        dry_run_plan.push((ALL_RECORDS, combined_records.len(), paths));
+    }
+    dry_run_plan.extend(run_files.finish()?);
+    dry_run_plan.extend(report_plan);
+    if let Some(path) = timings_path {
+        dry_run_plan.push((TIMINGS_FILE, save_files.len(), vec![path.to_path_buf()]));
+    }
+    if write_filename_map {
+        dry_run_plan.push((FILENAME_MAP_FILE, renamed_songs, vec![filename_map_path.clone()]));
+    }
+    if let Some(path) = errors_path {
+        dry_run_plan.push((ERRORS_FILE, summary.failed_saves.len(), vec![path.to_path_buf()]));
+    }
+
+    if cli.dry_run {
+        print_dry_run_plan(&dry_run_plan);
+    } else {
+        cache.write();
+    }
+    finish_run(&summary, &config, timings_path, errors_path, cli.dry_run)
+}
+
+/// `path` if `wanted` and it is to be written: nothing is written to the
+/// output directory with the records on stdout, and [`claim_output_file`]
+/// decides about an existing file.
+fn claim_run_file(path: PathBuf, wanted: bool, config: &Config, summary: &mut RunSummary) -> Result<Option<PathBuf>> {
+    let write = wanted && !config.writes_to_stdout() && claim_output_file(&path, config.existing, summary)?;
+    Ok(write.then_some(path))
+}
+
+/// Writes the reports about the whole run the options ask for, such as
+/// `player_summary.csv` or the --best-n files, and returns the files of each
+/// for the --dry-run plan.
+fn write_reports(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<(&'static str, usize, Vec<PathBuf>)>> {
+    let output_dir = config.output_dir();
+    let to_stdout = config.writes_to_stdout();
+    let saves_found = summary.saves_found;
+    let mut plan = Vec::new();
+
+    let path = output_dir.join(PLAYER_SUMMARY_FILE);
+    if let Some(path) = claim_run_file(path, config.player_summary, config, summary)? {
+        if !dry_run {
+            player_summary::write_player_summary(saves, config, &path)?;
+        }
+        plan.push((PLAYER_SUMMARY_FILE, saves_found, vec![path]));
+    }
+    let path = output_dir.join(FC_AP_SUMMARY_FILE);
+    if let Some(path) = claim_run_file(path, config.fc_ap_summary, config, summary)? {
+        if !dry_run {
+            fc_ap::write_fc_ap_summary(saves, &path)?;
+        }
+        plan.push((FC_AP_SUMMARY_FILE, saves_found, vec![path]));
+    }
+    let path = output_dir.join(PLAYER_TOTALS_FILE);
+    if let Some(path) = claim_run_file(path, config.player_summary_out, config, summary)? {
+        let workbook_path = output_dir.join(SUMMARY_WORKBOOK_FILE);
+        let workbook_path =
+            claim_run_file(workbook_path, config.formats.contains(&OutputFormat::Xlsx), config, summary)?;
+        if !dry_run {
+            player_totals::write_player_totals(saves, filter, config, &path, workbook_path.as_deref())?;
+        }
+        plan.push((PLAYER_TOTALS_FILE, saves_found, [Some(path), workbook_path].into_iter().flatten().collect()));
+    }
+    if let Some(songlist) = config.songlist.as_deref().map(completion::load_songlist).transpose()? {
+        let completion_path = claim_run_file(output_dir.join(COMPLETION_FILE), true, config, summary)?;
+        let unplayed_path = claim_run_file(output_dir.join(UNPLAYED_CHARTS_FILE), true, config, summary)?;
+        if !dry_run {
+            completion::write_completion(saves, &songlist, completion_path.as_deref(), unplayed_path.as_deref())?;
+        }
+        plan.push((COMPLETION_FILE, saves_found, completion_path.into_iter().collect()));
+        plan.push((UNPLAYED_CHARTS_FILE, songlist.len(), unplayed_path.into_iter().collect()));
+    }
+    if config.global_leaderboard && !to_stdout {
+        let paths = leaderboard::write_global_leaderboard(saves, config, dry_run, summary)?;
+        plan.push((GLOBAL_LEADERBOARD_FILE, saves_found, paths));
+    }
+    if config.rks_timeline && !to_stdout {
+        let paths = timeline::write_timelines(saves, config, dry_run, summary)?;
+        plan.push((timeline::TIMELINE_DIR, paths.len(), paths));
+    }
+    if config.history && !to_stdout {
+        let paths = history::write_histories(saves, filter, aliases, config, dry_run, summary)?;
+        plan.push((history::HISTORY_DIR, paths.len(), paths));
+    }
+    let path = output_dir.join(RKS_DRIFT_FILE);
+    if let Some(path) = claim_run_file(path, config.rks_drift, config, summary)? {
+        if !dry_run {
+            drift::write_rks_drift(saves, config, &path)?;
+        }
+        plan.push((RKS_DRIFT_FILE, saves_found, vec![path]));
+    }
+    let path = output_dir.join(PROGRESS_FILE);
+    if let Some(path) = claim_run_file(path, config.progress_out, config, summary)? {
+        if !dry_run {
+            progress::write_progress(saves, &path)?;
+        }
+        plan.push((PROGRESS_FILE, saves_found, vec![path]));
+    }
+    // Written wherever --profiles-out points, even with the records on stdout.
+    if let Some(path) = &config.profiles_out {
+        if claim_output_file(path, config.existing, summary)? {
+            if !dry_run {
+                profiles::write_profiles(saves, path)?;
+            }
+            plan.push(("profiles", saves_found, vec![path.clone()]));
+        }
+    }
+    if let Some(n) = config.best_n.filter(|_| !to_stdout) {
+        let paths = best::write_best_files(saves, filter, aliases, config, n, dry_run, summary)?;
+        plan.push((best::BEST_DIR, paths.len(), paths));
+    }
+    if config.push_acc && !to_stdout {
+        let paths = push_acc::write_push_acc(saves, filter, aliases, config, dry_run, summary)?;
+        plan.push((push_acc::PUSH_ACC_DIR, paths.len(), paths));
+    }
+    if config.suggestions && !to_stdout {
+        let paths = suggestions::write_suggestions(saves, filter, aliases, config, dry_run, summary)?;
+        plan.push((suggestions::SUGGESTIONS_DIR, paths.len(), paths));
+    }
+    plan.retain(|(_, _, paths)| !paths.is_empty());
+    Ok(plan)
+}
+
+/// The files every song of the run is added to: the single jsonl, parquet
+/// and xlsx files, the html report, the database and `stats.csv`. A path is
+/// `None` unless the options ask for the file and it is to be written; the
+/// writers are `None` with --dry-run as well.
+struct RunFiles {
+    jsonl: Option<PathBuf>,
+    jsonl_writer: Option<(BufWriter<File>, TempFile)>,
+    parquet: Option<PathBuf>,
+    parquet_writer: Option<ParquetWriter>,
+    workbook: Option<PathBuf>,
+    workbook_writer: Option<XlsxWorkbook>,
+    report: Option<PathBuf>,
+    report_writer: Option<HtmlReport>,
+    database: Option<PathBuf>,
+    database_writer: Option<SqliteWriter>,
+    stats: Option<PathBuf>,
+    song_stats: Option<SongStats>,
+    records: usize,
+}
+
+impl RunFiles {
+    fn create(
+        config: &Config,
+        options: &OutputOptions,
+        songs: usize,
+        dry_run: bool,
+        summary: &mut RunSummary,
+    ) -> Result<RunFiles> {
+        let output_dir = config.output_dir();
+        let formats = &config.formats;
+
+        let path = output_path(output_dir, ALL_RECORDS, OutputFormat::Jsonl, options);
+        let wanted = options.jsonl_single && formats.contains(&OutputFormat::Jsonl);
+        let jsonl = claim_run_file(path, wanted, config, summary)?;
+        let jsonl_writer = match jsonl.as_ref().filter(|_| !dry_run) {
+            Some(path) => {
+                let temp = TempFile::new(path);
+                let file =
+                    File::create(temp.path()).with_context(|| format!("Failed to create file: {}", path.display()))?;
+                Some((BufWriter::new(file), temp))
+            }
+            None => None,
+        };
+        let path = output_path(output_dir, ALL_RECORDS, OutputFormat::Parquet, options);
+        let wanted = options.parquet_combined && formats.contains(&OutputFormat::Parquet);
+        let parquet = claim_run_file(path, wanted, config, summary)?;
+        let parquet_writer =
+            parquet.as_ref().filter(|_| !dry_run).map(|path| ParquetWriter::create(path, options)).transpose()?;
+        let wanted = options.xlsx_single_workbook && formats.contains(&OutputFormat::Xlsx);
+        let workbook = claim_run_file(output_dir.join(WORKBOOK_FILE), wanted, config, summary)?;
+        let workbook_writer = workbook.as_deref().filter(|_| !dry_run).map(XlsxWorkbook::create).transpose()?;
+        let wanted = formats.contains(&OutputFormat::Html);
+        let report = claim_run_file(output_dir.join(REPORT_FILE), wanted, config, summary)?;
+        let report_writer = report
+            .as_deref()
+            .filter(|_| !dry_run)
+            .map(|path| HtmlReport::create(path, summary.saves_found, songs))
+            .transpose()?;
+        // The database is updated in place rather than overwritten.
+        let database = (formats.contains(&OutputFormat::Sqlite) && !config.writes_to_stdout())
+            .then(|| output_dir.join(DATABASE_FILE));
+        let database_writer = database.as_deref().filter(|_| !dry_run).map(SqliteWriter::open).transpose()?;
+        let stats = claim_run_file(output_dir.join(STATS_FILE), config.stats, config, summary)?;
+        let song_stats = stats.as_ref().filter(|_| !dry_run).map(|_| SongStats::default());
+        Ok(RunFiles {
+            jsonl,
+            jsonl_writer,
+            parquet,
+            parquet_writer,
+            workbook,
+            workbook_writer,
+            report,
+            report_writer,
+            database,
+            database_writer,
+            stats,
+            song_stats,
+            records: 0,
+        })
+    }
+
+    fn add_song(&mut self, song_name: &str, records: &[ProcessedRecord], options: &OutputOptions) -> Result<()> {
+        if let Some((writer, _)) = &mut self.jsonl_writer {
+            output::write_jsonl_rows(writer, records, options)?;
+        }
+        if let Some(writer) = &mut self.parquet_writer {
+            writer.write(records)?;
+        }
+        if let Some(workbook) = &mut self.workbook_writer {
+            workbook.add_song(song_name, records, options)?;
+        }
+        if let Some(report) = &mut self.report_writer {
+            report.add_song(song_name, records, options)?;
+        }
+        if let Some(database) = &mut self.database_writer {
+            database.insert(records)?;
+        }
+        if let Some(stats) = &mut self.song_stats {
+            stats.add_song(song_name, records);
+        }
+        self.records += records.len();
+        Ok(())
+    }
 
-            write_to_csv(&all_song_data, &csv_path)?;
-            write_to_excel(&all_song_data, &xlsx_path)?;
+    /// Finishes the files, returning them for the --dry-run plan.
+    fn finish(self) -> Result<Vec<(&'static str, usize, Vec<PathBuf>)>> {
+        if let Some((mut writer, temp)) = self.jsonl_writer {
+            writer.flush()?;
+            drop(writer);
+            temp.persist()?;
         }
+        if let Some(writer) = self.parquet_writer {
+            writer.finish()?;
+        }
+        if let Some(workbook) = self.workbook_writer {
+            workbook.finish()?;
+        }
+        if let Some(report) = self.report_writer {
+            report.finish()?;
+        }
+        if let Some(database) = self.database_writer {
+            database.finish()?;
+        }
+        if let (Some(stats), Some(path)) = (&self.song_stats, &self.stats) {
+            stats.write(path)?;
+        }
+        let files = [
+            (DATABASE_FILE, self.database),
+            (ALL_RECORDS, self.jsonl),
+            (ALL_RECORDS, self.parquet),
+            (WORKBOOK_FILE, self.workbook),
+            (REPORT_FILE, self.report),
+            (STATS_FILE, self.stats),
+        ];
+        Ok(files.into_iter().filter_map(|(name, path)| Some((name, self.records, vec![path?]))).collect())
     }
+}
 
+/// Writes every record of the run into one `all_records` file per format of
+/// --combined, returning the files written.
+fn write_combined(
+    records: &mut [ProcessedRecord],
+    formats: &[OutputFormat],
+    config: &Config,
+    options: &OutputOptions,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    sort_records(records, &config.sort_by);
+    let mut paths = Vec::new();
+    for &format in formats {
+        let path = output_path(config.output_dir(), ALL_RECORDS, format, options);
+        if !claim_output_file(&path, config.existing, summary)? {
+            continue;
+        }
+        if !dry_run {
+            let started = Instant::now();
+            if let Err(err) = format.write(records, &path, options) {
+                let err = config.error_policy.handle(err, summary)?;
+                error!("{:#}", err);
+                summary.files_failed += 1;
+                continue;
+            }
+            summary.timings.add_write(format.extension(), started.elapsed());
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Reports the run once everything is written, and fails if any output file
+/// could not be written, or any save could not be read unless --allow-errors
+/// is set. With --timings the time spent is
+/// printed too, and written to `timings_path` if given; the saves that failed
+/// are written to `errors_path` if given.
+fn finish_run(
+    summary: &RunSummary,
+    config: &Config,
+    timings_path: Option<&Path>,
+    errors_path: Option<&Path>,
+    dry_run: bool,
+) -> Result<()> {
+    if config.timings {
+        summary.timings.print();
+        if let (Some(path), false) = (timings_path, dry_run) {
+            summary.timings.write(path)?;
+        }
+    }
+    if let (Some(path), false) = (errors_path, dry_run) {
+        failures::write(&summary.failed_saves, path)?;
+    }
+    if dry_run {
+        if !summary.failed_saves.is_empty() {
+            failures::print(&summary.failed_saves);
+        }
+    } else {
+        summary.print();
+    }
+    if summary.files_failed > 0 {
+        bail!("Failed to write {} files", summary.files_failed);
+    }
+    if !summary.failed_saves.is_empty() && !config.allow_errors {
+        return Err(SavesFailed { failed: summary.failed_saves.len(), found: summary.saves_found }.into());
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own under the system temp dir, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir = std::env::temp_dir().join(format!("phi-save-data-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Scratch(dir)
+        }
+
+        fn input(&self) -> PathBuf {
+            self.0.join("saveData")
+        }
+
+        fn output(&self) -> PathBuf {
+            self.0.join("out")
+        }
+
+        /// Writes `saveData/{player}/save.json`.
+        fn save(&self, player: &str, content: impl AsRef<[u8]>) {
+            let dir = self.input().join(player);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("save.json"), content).unwrap();
+        }
+
+        /// Runs on the saves written so far, as the command line would with `args`.
+        fn run(&self, args: &[&str]) -> Result<()> {
+            let (input, output) = (self.input(), self.output());
+            let mut argv = vec!["phi-save-data", "--input", input.to_str().unwrap()];
+            argv.extend(["--output", output.to_str().unwrap(), "--no-cache", "--format", "csv"]);
+            argv.extend(args);
+            configure_and_run(&Cli::parse_from(argv))
+        }
+
+        fn read(&self, name: &str) -> String {
+            fs::read_to_string(self.output().join(name)).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn songs_with_the_same_file_name_get_files_of_their_own() {
+        let scratch = Scratch::new("file-stems");
+        scratch.save(
+            "p1",
+            r#"{"gameRecord":{
+                "AB?C.Artist.0":[{"score":990000,"acc":99.1,"fc":true}],
+                "AB:C.Artist.0":[{"score":880000,"acc":95.2,"fc":false}]
+            },"saveInfo":{"summary":{"rankingScore":14.5,"gameVersion":90}}}"#,
+        );
+        scratch.run(&[]).unwrap();
+
+        let first = scratch.read("AB_C.csv");
+        assert!(first.contains("AB:C") && first.contains("880000"), "{}", first);
+        assert!(!first.contains("AB?C"), "{}", first);
+        let second = scratch.read("AB_C_2.csv");
+        assert!(second.contains("AB?C") && second.contains("990000"), "{}", second);
+        assert!(!second.contains("AB:C"), "{}", second);
+
+        let map = scratch.read(FILENAME_MAP_FILE);
+        assert_eq!(map, "file_stem,song_name\nAB_C,AB:C\nAB_C_2,AB?C\n");
+    }
+
+    #[test]
+    fn reads_saves_in_each_text_encoding() {
+        let scratch = Scratch::new("encodings");
+        scratch.save("utf8", SAVE);
+        scratch.save("utf8_bom", [b"\xEF\xBB\xBF", SAVE.as_bytes()].concat());
+        let utf16 = |bom: [u8; 2], to_bytes: fn(u16) -> [u8; 2]| {
+            bom.into_iter().chain(SAVE.encode_utf16().flat_map(to_bytes)).collect::<Vec<_>>()
+        };
+        scratch.save("utf16le", utf16([0xFF, 0xFE], u16::to_le_bytes));
+        scratch.save("utf16be", utf16([0xFE, 0xFF], u16::to_be_bytes));
+        scratch.run(&[]).unwrap();
+
+        let song = scratch.read("Rrhar'il.csv");
+        for player in ["utf8", "utf8_bom", "utf16le", "utf16be"] {
+            assert!(song.lines().any(|line| line.starts_with(&format!("{},", player))), "{}", song);
+        }
+    }
+
+    #[test]
+    fn undecodable_saves_fail_with_the_encoding_error() {
+        let scratch = Scratch::new("not-text");
+        scratch.save("p1", SAVE);
+        scratch.save("p2", b"\x89PNG\r\n\x1a\n\xff\xd8");
+        scratch.save("p3", b"\xFF\xFE{\x00}");
+        let err = scratch.run(&["--errors-json"]).unwrap_err();
+        assert_eq!(Exit::of(&err), Exit::SavesFailed);
+        let errors = fs::read_to_string(scratch.output().join(ERRORS_FILE)).unwrap();
+        assert!(errors.contains("Not valid UTF-8 (no byte order mark found)"), "{}", errors);
+        let odd = "Not valid UTF-16LE (found from its byte order mark): odd number of bytes";
+        assert!(errors.contains(odd), "{}", errors);
+    }
+
+    #[test]
+    fn grade_thresholds() {
+        for (score, expected) in [
+            (0, "F"),
+            (699_999, "F"),
+            (700_000, "C"),
+            (819_999, "C"),
+            (820_000, "B"),
+            (880_000, "A"),
+            (919_999, "A"),
+            (920_000, "S"),
+            (959_999, "S"),
+            (960_000, "V"),
+            (999_999, "V"),
+            (1_000_000, "φ"),
+        ] {
+            assert_eq!(grade(score, false), expected, "{}", score);
+        }
+    }
+
+    #[test]
+    fn full_combo_only_changes_the_v_grade() {
+        assert_eq!(grade(960_000, true), "V (FC)");
+        assert_eq!(grade(999_999, true), "V (FC)");
+        assert_eq!(grade(959_999, true), "S");
+        assert_eq!(grade(1_000_000, true), "φ");
+    }
+
+    #[test]
+    fn split_song_id_normalizes_composition() {
+        let composed = split_song_id("Caf\u{e9}.Ren\u{e9}.0");
+        let decomposed = split_song_id("Cafe\u{301}.Rene\u{301}.0");
+        assert_eq!(composed, ("Caf\u{e9}".to_string(), "Ren\u{e9}".to_string(), "0".to_string()));
+        assert_eq!(decomposed, composed);
+    }
+
+    #[test]
+    fn split_song_id_without_artist_or_suffix() {
+        assert_eq!(split_song_id("Song.Artist"), ("Song".to_string(), "Artist".to_string(), String::new()));
+        assert_eq!(split_song_id("Song.3"), ("Song".to_string(), String::new(), "3".to_string()));
+        assert_eq!(split_song_id("Song.Art.ist.12").0, "Song.Art");
+    }
+
+    #[test]
+    fn songs_differing_in_composition_share_a_file() {
+        let scratch = Scratch::new("nfc");
+        scratch.save(
+            "p1",
+            "{\"gameRecord\":{\"Caf\u{e9}.A.0\":[{\"score\":990000,\"acc\":99.1,\"fc\":true}]},\
+             \"saveInfo\":{\"summary\":{\"rankingScore\":14.5,\"gameVersion\":90}}}",
+        );
+        scratch.save(
+            "p2",
+            "{\"gameRecord\":{\"Cafe\u{301}.A.0\":[{\"score\":880000,\"acc\":95.2,\"fc\":false}]},\
+             \"saveInfo\":{\"summary\":{\"rankingScore\":14.5,\"gameVersion\":90}}}",
+        );
+        scratch.run(&[]).unwrap();
+        let files: Vec<_> = fs::read_dir(scratch.output()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, ["Caf\u{e9}.csv"]);
+        let song = scratch.read("Caf\u{e9}.csv");
+        assert!(song.contains("990000") && song.contains("880000"), "{}", song);
+    }
+
+    fn ids(song_ids: &[&str]) -> Vec<String> {
+        song_ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn latest(song_ids: &[&str]) -> Vec<String> {
+        let song_ids = ids(song_ids);
+        let mut latest: Vec<_> = latest_revisions(song_ids.iter()).into_iter().collect();
+        latest.sort();
+        latest
+    }
+
+    #[test]
+    fn latest_revision_of_each_chart_is_kept() {
+        let song_ids = ["Song.Artist.0", "Song.Artist.1", "Other.Artist.0"];
+        assert_eq!(latest(&song_ids), ids(&["Other.Artist.0", "Song.Artist.1"]));
+        assert_eq!(latest(&["Song.Artist.1", "Song.Artist.0"]), ids(&["Song.Artist.1"]));
+    }
+
+    #[test]
+    fn revisions_compare_as_numbers() {
+        assert_eq!(latest(&["Song.Artist.9", "Song.Artist.10"]), ids(&["Song.Artist.10"]));
+    }
+
+    #[test]
+    fn id_without_suffix_is_older_than_any_revision() {
+        assert_eq!(latest(&["Song.Artist", "Song.Artist.0"]), ids(&["Song.Artist.0"]));
+    }
+
+    #[test]
+    fn latest_revision_policy_drops_the_older_records() {
+        let scratch = Scratch::new("revisions");
+        scratch.save(
+            "p1",
+            r#"{"gameRecord":{
+                "Song.Artist.0":[{"score":880000,"acc":95.2,"fc":false}],
+                "Song.Artist.1":[{"score":990000,"acc":99.1,"fc":true}]
+            },"saveInfo":{"summary":{"rankingScore":14.5,"gameVersion":90}}}"#,
+        );
+        scratch.run(&["--revision-policy", "latest"]).unwrap();
+        let song = scratch.read("Song.csv");
+        assert!(song.contains("990000") && !song.contains("880000"), "{}", song);
+        scratch.run(&["--revision-policy", "merge", "--force"]).unwrap();
+        let song = scratch.read("Song.csv");
+        assert!(song.contains("990000") && song.contains("880000"), "{}", song);
+    }
+
+    #[test]
+    fn all_perfect_needs_max_score_or_a_full_combo_at_100() {
+        assert!(is_all_perfect(1_000_000, 100.0, true));
+        assert!(is_all_perfect(1_000_000, 100.0, false));
+        assert!(is_all_perfect(999_999, 100.0, true));
+        assert!(!is_all_perfect(999_999, 100.0, false));
+        assert!(!is_all_perfect(999_999, 99.99999999, true));
+    }
+
+    #[test]
+    fn validate_accepts_the_boundaries() {
+        for (score, acc) in [(0, 0.0), (MAX_SCORE, 100.0), (500_000, 50.5)] {
+            assert_eq!(validate(score, acc), None, "{} {}", score, acc);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_acc_that_cant_be_real() {
+        assert_eq!(validate(900_000, f64::NAN).unwrap(), "acc NaN is outside 0-100");
+        assert_eq!(validate(900_000, f64::INFINITY).unwrap(), "acc inf is outside 0-100");
+        assert_eq!(validate(900_000, f64::NEG_INFINITY).unwrap(), "acc -inf is outside 0-100");
+        assert_eq!(validate(900_000, -0.01).unwrap(), "acc -0.01 is outside 0-100");
+        assert_eq!(validate(900_000, 100.0001).unwrap(), "acc 100.0001 is outside 0-100");
+        assert_eq!(validate(900_000, 1.7e308).unwrap(), format!("acc {} is outside 0-100", 1.7e308));
+    }
+
+    const SAVE: &str = r#"{"gameRecord":{"Rrhar'il.Team Grimoire.0":[{"score":990000,"acc":99.1,"fc":true}]},
+        "saveInfo":{"summary":{"rankingScore":14.5,"gameVersion":90}}}"#;
+
+    fn save_file(path: PathBuf) -> SaveFile {
+        SaveFile { location: SaveLocation::File(path), player_id: "p1".to_string(), snapshot: "save".to_string() }
+    }
+
+    /// Maps come out sorted, so saves can be compared whatever their hash order.
+    fn as_value(save_data: &SaveData) -> serde_json::Value {
+        serde_json::to_value(save_data).unwrap()
+    }
+
+    /// Writes `content` to a file of its own in the temp directory.
+    fn temp_save(name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("phi-save-data-{}-{}.json", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn streamed_save_matches_the_one_read_whole() {
+        let utf16 = [0xFF, 0xFE].into_iter().chain(SAVE.encode_utf16().flat_map(u16::to_le_bytes)).collect();
+        for (name, content) in [
+            ("utf8", SAVE.as_bytes().to_vec()),
+            ("utf8_bom", [b"\xEF\xBB\xBF", SAVE.as_bytes()].concat()),
+            ("utf16le", utf16),
+        ] {
+            let path = temp_save(&format!("stream-{}", name), content);
+            let save = save_file(path.clone());
+            let streamed = stream_save_file(&save, &path).unwrap();
+            let read = read_save_file(&save, &Config::default()).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(as_value(&streamed), as_value(&read), "{}", name);
+        }
+    }
+
+    #[test]
+    fn save_over_the_threshold_is_streamed_with_the_same_result() {
+        let mut content = String::from(r#"{"gameRecord":{"#);
+        let mut index = 0;
+        while content.len() as u64 <= STREAM_THRESHOLD {
+            if index > 0 {
+                content.push(',');
+            }
+            content.push_str(&format!(r#""Song{}.A.0":[{{"score":{},"acc":98.5,"fc":false}},null]"#, index, index));
+            index += 1;
+        }
+        content.push_str(r#"},"saveInfo":{"summary":{"rankingScore":14.5,"gameVersion":90}}}"#);
+        let path = temp_save("stream-large", &content);
+        let save = save_file(path.clone());
+
+        let streamed = read_save_file(&save, &Config::default()).unwrap();
+        let whole = parse_save_json(&save, &mut serde_json::Deserializer::from_str(&content)).unwrap();
+        assert_eq!(streamed.game_record.len(), index);
+        assert_eq!(as_value(&streamed), as_value(&whole));
+        let broken = parse_save_json(&save, &mut serde_json::Deserializer::from_str(&content[..content.len() - 1]));
+        fs::write(&path, &content[..content.len() - 1]).unwrap();
+        let err = read_save_file(&save, &Config::default()).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.to_string(), broken.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn lenient_json_nan_acc_is_rejected_as_invalid() {
+        let scratch = Scratch::new("lenient-nan");
+        scratch.save(
+            "p1",
+            r#"{"gameRecord":{"Rrhar'il.Team Grimoire.0":[
+                // hand-edited
+                {"score":990000,"acc":99.1,"fc":true},
+                {"score":880000,"acc":NaN,"fc":false},
+            ]},"saveInfo":{"summary":{"rankingScore":14.5,"gameVersion":90}}}"#,
+        );
+        assert!(scratch.run(&[]).is_err());
+        scratch.run(&["--lenient-json"]).unwrap();
+        let song = scratch.read("Rrhar'il.csv");
+        assert!(song.contains("990000"), "{}", song);
+        assert!(!song.contains("880000"), "{}", song);
+
+        scratch.run(&["--lenient-json", "--keep-invalid", "--force"]).unwrap();
+        let song = scratch.read("Rrhar'il.csv");
+        assert!(song.contains(",880000,120000,NaN,false,false,A,14.5,90,true"), "{}", song);
+    }
+
+    #[test]
+    fn validate_rejects_score_out_of_range() {
+        assert_eq!(validate(-1, 90.0).unwrap(), "score -1 is outside 0-1000000");
+        assert_eq!(validate(MAX_SCORE + 1, 90.0).unwrap(), "score 1000001 is outside 0-1000000");
+    }
+
+    #[test]
+    fn acc_is_checked_before_score() {
+        assert_eq!(validate(-1, f64::NAN).unwrap(), "acc NaN is outside 0-100");
+    }
+
+    #[test]
+    fn profiles_have_the_sections_each_save_has() {
+        let scratch = Scratch::new("profiles");
+        scratch.save(
+            "full",
+            r#"{"gameRecord":{},"saveInfo":{"nickname":"Full","summary":{"rankingScore":15.1,"gameVersion":90,
+                "challengeModeRank":438,"avatar":"Old"}},
+                "user":{"showPlayerId":true,"selfIntro":"hi","avatar":"New","background":"Bg"},
+                "settings":{"chordSupport":true,"fcAPIndicator":false,"deviceName":"phone","musicVolume":0.8}}"#,
+        );
+        scratch.save("bare", r#"{"gameRecord":{},"saveInfo":{"summary":{}}}"#);
+        scratch.save(
+            "broken",
+            r#"{"gameRecord":{},"saveInfo":{"summary":{"rankingScore":12.0,"avatar":"Summary"}},
+                "user":{"showPlayerId":"yes"},"settings":[]}"#,
+        );
+        let profiles = scratch.output().join("profiles.json");
+        scratch.run(&["--profiles-out", profiles.to_str().unwrap()]).unwrap();
+
+        let profiles: Vec<serde_json::Value> = serde_json::from_str(&fs::read_to_string(&profiles).unwrap()).unwrap();
+        let by_player = |player: &str| profiles.iter().find(|profile| profile["player_id"] == player).unwrap();
+        let full = by_player("full");
+        assert_eq!(full["nickname"], "Full");
+        assert_eq!(full["ranking_score"], 15.1);
+        assert_eq!(full["challenge_mode_rank"], 438);
+        assert_eq!(full["avatar"], "New");
+        assert_eq!(full["show_player_id"], true);
+        assert_eq!(full["self_intro"], "hi");
+        assert_eq!(full["fc_ap_indicator"], false);
+        assert_eq!(full["device_name"], "phone");
+        assert_eq!(full["music_volume"], 0.8);
+        assert!(full["note_scale"].is_null());
+
+        let bare = by_player("bare");
+        for field in ["nickname", "ranking_score", "avatar", "show_player_id", "chord_support", "music_volume"] {
+            assert!(bare[field].is_null(), "{}: {}", field, bare);
+        }
+
+        // Sections that don't parse are left out, not the save.
+        let broken = by_player("broken");
+        assert_eq!(broken["ranking_score"], 12.0);
+        assert_eq!(broken["avatar"], "Summary");
+        assert!(broken["show_player_id"].is_null() && broken["chord_support"].is_null());
+    }
+
+    #[test]
+    fn profiles_csv_leaves_missing_fields_empty() {
+        let scratch = Scratch::new("profiles-csv");
+        scratch.save("bare", r#"{"gameRecord":{},"saveInfo":{"summary":{"rankingScore":12.5}}}"#);
+        let profiles = scratch.output().join("profiles.csv");
+        scratch.run(&["--profiles-out", profiles.to_str().unwrap()]).unwrap();
+        let csv = fs::read_to_string(&profiles).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("player_id,nickname,ranking_score,game_version,"));
+        assert_eq!(lines.next().unwrap(), format!("bare,,12.5{}", ",".repeat(17)));
+        assert_eq!(lines.next(), None);
+    }
+
+    /// Makes `dir` read-only, and as root may still write there, also puts a
+    /// directory where the temporary file of `blocked` would go. Returns a
+    /// guard making `dir` writable again, so that it can be removed.
+    fn make_unwritable(dir: &Path, blocked: &str) -> impl Drop {
+        struct Restore(PathBuf);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let mut permissions = fs::metadata(&self.0).unwrap().permissions();
+                #[allow(clippy::permissions_set_readonly_false)]
+                permissions.set_readonly(false);
+                fs::set_permissions(&self.0, permissions).unwrap();
+            }
+        }
+        fs::create_dir_all(dir.join(format!("{}.tmp", blocked))).unwrap();
+        let mut permissions = fs::metadata(dir).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(dir, permissions).unwrap();
+        Restore(dir.to_path_buf())
+    }
+
+    #[test]
+    fn write_error_names_the_file() {
+        let scratch = Scratch::new("unwritable-file");
+        let _restore = make_unwritable(&scratch.0, "Song.csv");
+        let path = scratch.0.join("Song.csv");
+        let records = [ProcessedRecord { song_name: "Song".to_string(), ..ProcessedRecord::default() }];
+        let options = OutputOptions::from_config(&Config::default());
+        let err = OutputFormat::Csv.write(&records, &path, &options).unwrap_err();
+        assert_eq!(err.to_string(), format!("Failed to write {}", path.display()));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unwritable_output_fails_the_run_without_a_panic() {
+        let scratch = Scratch::new("unwritable-run");
+        scratch.save(
+            "p1",
+            r#"{"gameRecord":{
+                "Song.Artist.0":[{"score":990000,"acc":99.1,"fc":true}],
+                "Other.Artist.0":[{"score":880000,"acc":95.2,"fc":false}]
+            },"saveInfo":{"summary":{"rankingScore":14.5,"gameVersion":90}}}"#,
+        );
+        // Only Song.csv can't be written, for any user.
+        fs::create_dir_all(scratch.output().join("Song.csv.tmp")).unwrap();
+        let err = scratch.run(&[]).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to write 1 files");
+        assert_eq!(Exit::of(&err), Exit::Fatal);
+        assert!(!scratch.output().join("Song.csv").exists());
+        assert!(scratch.read("Other.csv").contains("880000"));
+
+        // Stopping at the first error fails the run with that error.
+        let err = scratch.run(&["--error-policy", "fail-fast", "--force"]).unwrap_err();
+        assert_eq!(err.to_string(), format!("Failed to write {}", scratch.output().join("Song.csv").display()));
+        assert_eq!(Exit::of(&err), Exit::Fatal);
+    }
+
+    #[test]
+    fn stopped_low_memory_run_keeps_the_files_of_the_last_run() {
+        let scratch = Scratch::new("low-memory-stop");
+        scratch.save("p1", SAVE);
+        scratch.run(&["--low-memory"]).unwrap();
+        let before = scratch.read("Rrhar'il.csv");
+
+        scratch.save("p1", SAVE.replace("990000", "995000"));
+        scratch.save("p2", "{");
+        let args = ["--low-memory", "--force", "--error-policy", "fail-fast"];
+        assert!(scratch.run(&args).is_err());
+        assert_eq!(scratch.read("Rrhar'il.csv"), before);
+        assert!(!scratch.output().join("Rrhar'il.csv.tmp").exists());
+
+        fs::remove_dir_all(scratch.input().join("p2")).unwrap();
+        scratch.run(&["--low-memory", "--force"]).unwrap();
+        assert!(scratch.read("Rrhar'il.csv").contains("995000"));
+        assert!(!scratch.output().join("Rrhar'il.csv.tmp").exists());
+    }
+
+    /// Built by `tests/fixtures/make_fixtures.py`.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    #[test]
+    fn reads_the_encoding_fixtures_whole_and_streamed() {
+        for name in ["save_utf8_bom.json", "save_utf16le.json", "save_utf16be.json"] {
+            let path = fixture(name);
+            let save = save_file(path.clone());
+            let processed = process_save_file(&save, &Config::default()).unwrap();
+            let records: Vec<_> = processed
+                .records
+                .iter()
+                .map(|record| (record.song_name.as_str(), record.difficulty.as_str(), record.score, record.ap))
+                .collect();
+            assert_eq!(records.len(), 2, "{}", name);
+            assert!(records.contains(&("Rrhar'il", "IN", 990000, false)), "{}: {:?}", name, records);
+            assert!(records.contains(&("Glaciaxion", "EZ", 1_000_000, true)), "{}: {:?}", name, records);
+            assert_eq!(processed.save_info.summary.ranking_score, Some(14.5), "{}", name);
+
+            let streamed = stream_save_file(&save, &path).unwrap();
+            assert_eq!(as_value(&streamed), as_value(&read_save_file(&save, &Config::default()).unwrap()), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! The `msgpack` output format.
+//!
+//! A file is a [`Header`] followed by `header.records` records, each encoded
+//! as a MessagePack map from column name to value. Only the columns selected
+//! with `--columns` are present, so use [`Record`] (whose fields are all
+//! optional) or your own struct with matching field names.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const MAGIC: &str = "phisavesong";
+
+/// Bumped whenever the layout of the file changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub magic: String,
+    pub version: u32,
+    /// Number of records following the header.
+    pub records: u64,
+}
+
+/// One exported record; columns left out of the export are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Record {
+    pub player_id: Option<String>,
+    pub song_name: Option<String>,
+    pub artist: Option<String>,
+    pub difficulty: Option<String>,
+    pub score: Option<i32>,
+    pub score_deficit: Option<i32>,
+    pub acc: Option<f64>,
+    pub fc: Option<bool>,
+    pub ap: Option<bool>,
+    pub grade: Option<String>,
+    pub ranking_score: Option<f64>,
+    pub game_version: Option<String>,
+    pub avatar: Option<String>,
+    pub captured_at: Option<String>,
+    pub chart_suffix: Option<String>,
+    pub invalid: Option<bool>,
+    pub snapshot: Option<String>,
+    pub chart_constant: Option<f64>,
+    pub play_rks: Option<f64>,
+    pub acc_percentile: Option<f64>,
+}
+
+/// Writes the header and then every record.
+pub fn write_msgpack<W: Write, T: Serialize>(writer: &mut W, records: &[T]) -> Result<()> {
+    let header = Header {
+        magic: MAGIC.to_string(),
+        version: FORMAT_VERSION,
+        records: records.len() as u64,
+    };
+    rmp_serde::encode::write_named(writer, &header)?;
+    for record in records {
+        rmp_serde::encode::write_named(writer, record)?;
+    }
+    Ok(())
+}
+
+/// Reads a file written by [`write_msgpack`], e.g.
+/// `read_msgpack::<_, Record>(File::open("song.msgpack")?)`.
+pub fn read_msgpack<R: Read, T: DeserializeOwned>(mut reader: R) -> Result<(Header, Vec<T>)> {
+    let header: Header = rmp_serde::from_read(&mut reader).context("Failed to read msgpack header")?;
+    if header.magic != MAGIC {
+        bail!("not a phisavesong msgpack file");
+    }
+    if header.version != FORMAT_VERSION {
+        bail!("unsupported msgpack format version {}, expected {}", header.version, FORMAT_VERSION);
+    }
+    let records = (0..header.records)
+        .map(|i| rmp_serde::from_read(&mut reader).with_context(|| format!("Failed to read record {}", i)))
+        .collect::<Result<_>>()?;
+    Ok((header, records))
+}
@@ -0,0 +1,767 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::config::{Config, DEFAULT_SAVE_PATTERN};
+use crate::{Difficulty, ProcessedRecord};
+
+mod append;
+mod atomic;
+mod compress;
+mod filename;
+mod html;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod sqlite;
+mod wide;
+mod xlsx;
+
+pub use append::merge_existing_csv;
+pub use atomic::{write_atomically, TempFile};
+pub use compress::{CompressedWriter, Compression};
+pub use filename::{sanitize_filename, write_filename_map, FileStems, FILENAME_MAP_FILE};
+pub use html::{HtmlReport, REPORT_FILE};
+#[cfg(feature = "parquet")]
+pub use self::parquet::ParquetWriter;
+#[cfg(not(feature = "parquet"))]
+pub use self::no_parquet::ParquetWriter;
+pub use sqlite::{SqliteWriter, DATABASE_FILE};
+pub use xlsx::{create_workbook, AccFormat, XlsxWorkbook, WORKBOOK_FILE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Csv,
+    Xlsx,
+    Json,
+    Jsonl,
+    Sqlite,
+    Parquet,
+    Markdown,
+    Html,
+    Ods,
+    Msgpack,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Sqlite => "db",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Html => "html",
+            OutputFormat::Ods => "ods",
+            OutputFormat::Msgpack => "msgpack",
+        }
+    }
+
+    /// Formats that produce one file per song, as opposed to one for the whole run.
+    pub fn is_per_song(self) -> bool {
+        !matches!(self, OutputFormat::Sqlite | OutputFormat::Html)
+    }
+
+    /// Writes `records` to `output_path`, replacing it only once the whole file
+    /// has been written.
+    pub fn write(self, records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+        atomic::write_atomically(output_path, |temp_path| self.write_to(records, temp_path, options))
+            .with_context(|| format!("Failed to write {}", output_path.display()))
+    }
+
+    fn write_to(self, records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+        match self {
+            OutputFormat::Csv if options.layout == Layout::Wide => wide::write_to_csv(records, output_path, options),
+            OutputFormat::Xlsx if options.layout == Layout::Wide => wide::write_to_excel(records, output_path),
+            OutputFormat::Csv => write_to_csv(records, output_path, options),
+            OutputFormat::Xlsx => xlsx::write_to_excel(records, output_path, options),
+            OutputFormat::Ods => write_to_ods(records, output_path, options),
+            OutputFormat::Json => write_to_json(records, output_path, options),
+            OutputFormat::Jsonl => write_to_jsonl(records, output_path, options),
+            OutputFormat::Sqlite | OutputFormat::Html => {
+                bail!("{} output is written once per run, not per song", self.extension())
+            }
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => parquet::write_to_parquet(records, output_path, options),
+            #[cfg(not(feature = "parquet"))]
+            OutputFormat::Parquet => bail!("parquet output requires building with `--features parquet`"),
+            OutputFormat::Markdown => write_to_markdown(records, output_path, options),
+            #[cfg(feature = "msgpack")]
+            OutputFormat::Msgpack => write_to_msgpack(records, output_path, options),
+            #[cfg(not(feature = "msgpack"))]
+            OutputFormat::Msgpack => bail!("msgpack output requires building with `--features msgpack`"),
+        }
+    }
+}
+
+/// File stem used when every song goes into one file.
+pub const ALL_RECORDS: &str = "all_records";
+
+pub fn output_path(output_dir: &Path, name: &str, format: OutputFormat, options: &OutputOptions) -> PathBuf {
+    output_dir.join(format!("{}.{}", name, options.extension(format)))
+}
+
+/// Field separator for csv output, given as `,`, `;`, `\t` or any other single
+/// ASCII character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Delimiter(u8);
+
+impl Delimiter {
+    pub const COMMA: Delimiter = Delimiter(b',');
+    pub const TAB: Delimiter = Delimiter(b'\t');
+}
+
+impl FromStr for Delimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "\\t" | "\t" | "tab" => Ok(Delimiter::TAB),
+            _ => match s.as_bytes() {
+                [byte] if byte.is_ascii() && !matches!(byte, b'"' | b'\n' | b'\r') => Ok(Delimiter(*byte)),
+                _ => Err(format!("invalid delimiter `{}`, expected a single ASCII character or `\\t`", s)),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for Delimiter {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Delimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Delimiter::TAB {
+            f.write_str("\\t")
+        } else {
+            write!(f, "{}", self.0 as char)
+        }
+    }
+}
+
+impl From<Delimiter> for String {
+    fn from(delimiter: Delimiter) -> String {
+        delimiter.to_string()
+    }
+}
+
+/// Shape of the csv and xlsx tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// One row per record.
+    Long,
+    /// One row per player with score and acc columns per difficulty.
+    Wide,
+}
+
+/// What each output file holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    /// One file per song with every player's records.
+    Song,
+    /// One file per player directory with all of its songs.
+    Player,
+}
+
+/// Splits each song's per-song files further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitBy {
+    /// One `{song}_{difficulty}` file per difficulty that has records.
+    Difficulty,
+}
+
+/// The records of one song grouped into `{stem}_{difficulty}` files, `stem`
+/// being the song's file stem, in difficulty order and keeping the sorted
+/// order within each file.
+pub fn split_by_difficulty(stem: &str, records: &[ProcessedRecord]) -> Vec<(String, Vec<ProcessedRecord>)> {
+    let mut groups: BTreeMap<(usize, &str), Vec<ProcessedRecord>> = BTreeMap::new();
+    for record in records {
+        groups
+            .entry((Difficulty::rank(&record.difficulty), &record.difficulty))
+            .or_default()
+            .push(record.clone());
+    }
+    groups
+        .into_iter()
+        .map(|((_, difficulty), matching)| (format!("{}_{}", stem, difficulty), matching))
+        .collect()
+}
+
+/// What to do when an output file is already present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingFiles {
+    Error,
+    Overwrite,
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Column {
+    PlayerId,
+    SongName,
+    Artist,
+    Difficulty,
+    Score,
+    Acc,
+    Fc,
+    Ap,
+    Grade,
+    RankingScore,
+    GameVersion,
+    Avatar,
+    CapturedAt,
+    ChartSuffix,
+    Invalid,
+    Snapshot,
+    ChartConstant,
+    PlayRks,
+    AccPercentile,
+    ScoreDeficit,
+}
+
+impl Column {
+    /// The columns exported by default; `avatar`, `captured_at`,
+    /// `chart_suffix` and `invalid` are added with `--with-avatar`,
+    /// `--with-timestamp`, `--with-chart-id` and `--keep-invalid`, `snapshot`
+    /// with a `--save-pattern` other than `save.json`, `chart_constant` and
+    /// `play_rks` with `--constants`, and `acc_percentile` with
+    /// `--with-percentiles`.
+    pub const ALL: [Column; 12] = [
+        Column::PlayerId,
+        Column::SongName,
+        Column::Artist,
+        Column::Difficulty,
+        Column::Score,
+        Column::ScoreDeficit,
+        Column::Acc,
+        Column::Fc,
+        Column::Ap,
+        Column::Grade,
+        Column::RankingScore,
+        Column::GameVersion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::PlayerId => "player_id",
+            Column::SongName => "song_name",
+            Column::Artist => "artist",
+            Column::Difficulty => "difficulty",
+            Column::Score => "score",
+            Column::Acc => "acc",
+            Column::Fc => "fc",
+            Column::Ap => "ap",
+            Column::Grade => "grade",
+            Column::RankingScore => "ranking_score",
+            Column::GameVersion => "game_version",
+            Column::Avatar => "avatar",
+            Column::CapturedAt => "captured_at",
+            Column::ChartSuffix => "chart_suffix",
+            Column::Invalid => "invalid",
+            Column::Snapshot => "snapshot",
+            Column::ChartConstant => "chart_constant",
+            Column::PlayRks => "play_rks",
+            Column::AccPercentile => "acc_percentile",
+            Column::ScoreDeficit => "score_deficit",
+        }
+    }
+
+    pub fn cell(self, record: &ProcessedRecord) -> Cell<'_> {
+        match self {
+            Column::PlayerId => Cell::Str(&record.player_id),
+            Column::SongName => Cell::Str(&record.song_name),
+            Column::Artist => Cell::Str(&record.artist),
+            Column::Difficulty => Cell::Str(&record.difficulty),
+            Column::Score => Cell::Int(record.score as i64),
+            Column::Acc => Cell::Float(record.acc),
+            Column::Fc => Cell::Bool(record.fc),
+            Column::Ap => Cell::Bool(record.ap),
+            Column::Grade => Cell::Str(&record.grade),
+            Column::RankingScore => Cell::Float(record.ranking_score),
+            Column::GameVersion => Cell::Str(&record.game_version),
+            Column::Avatar => Cell::Str(&record.avatar),
+            Column::CapturedAt => Cell::Str(&record.captured_at),
+            Column::ChartSuffix => Cell::Str(&record.chart_suffix),
+            Column::Invalid => Cell::Bool(record.invalid),
+            Column::Snapshot => Cell::Str(&record.snapshot),
+            Column::ChartConstant => record.chart_constant.map_or(Cell::Empty, Cell::Float),
+            Column::PlayRks => record.play_rks.map_or(Cell::Empty, Cell::Float),
+            Column::AccPercentile => record.acc_percentile.map_or(Cell::Empty, Cell::Float),
+            Column::ScoreDeficit => Cell::Int(record.score_deficit as i64),
+        }
+    }
+}
+
+/// A single typed value, so that every writer renders a column the same way.
+pub enum Cell<'a> {
+    Str(&'a str),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// A value the record doesn't have.
+    Empty,
+}
+
+impl fmt::Display for Cell<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Cell::Str(value) => f.write_str(value),
+            Cell::Int(value) => write!(f, "{}", value),
+            Cell::Float(value) => write!(f, "{}", value),
+            Cell::Bool(value) => write!(f, "{}", value),
+            Cell::Empty => Ok(()),
+        }
+    }
+}
+
+impl Serialize for Cell<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Cell::Str(value) => serializer.serialize_str(value),
+            Cell::Int(value) => serializer.serialize_i64(value),
+            Cell::Float(value) => serializer.serialize_f64(value),
+            Cell::Bool(value) => serializer.serialize_bool(value),
+            Cell::Empty => serializer.serialize_none(),
+        }
+    }
+}
+
+/// One record as a map of the selected column names to cells.
+struct Row<'a> {
+    columns: &'a [Column],
+    record: &'a ProcessedRecord,
+}
+
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for column in self.columns {
+            map.serialize_entry(column.name(), &column.cell(self.record))?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Clone)]
+pub struct OutputOptions {
+    pub columns: Vec<Column>,
+    pub delimiter: Delimiter,
+    pub json_compact: bool,
+    pub jsonl_single: bool,
+    pub parquet_combined: bool,
+    pub top: Option<usize>,
+    pub xlsx_single_workbook: bool,
+    pub xlsx_acc_format: AccFormat,
+    pub xlsx_colors: bool,
+    pub compression: Compression,
+    pub compress_level: u32,
+    pub split_by: Option<SplitBy>,
+    pub layout: Layout,
+}
+
+impl OutputOptions {
+    pub fn from_config(config: &Config) -> OutputOptions {
+        let mut columns = if config.columns.is_empty() {
+            Column::ALL
+                .into_iter()
+                .filter(|&column| config.player_id || column != Column::PlayerId)
+                .collect()
+        } else {
+            config.columns.clone()
+        };
+        let optional = [
+            (config.with_avatar, Column::Avatar),
+            (config.with_timestamp, Column::CapturedAt),
+            (config.with_chart_id, Column::ChartSuffix),
+            (config.keep_invalid, Column::Invalid),
+            (config.save_pattern != DEFAULT_SAVE_PATTERN, Column::Snapshot),
+            (config.constants.is_some(), Column::ChartConstant),
+            (config.constants.is_some(), Column::PlayRks),
+            (config.with_percentiles, Column::AccPercentile),
+        ];
+        for (enabled, column) in optional {
+            if enabled && !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+        OutputOptions {
+            columns,
+            delimiter: config.delimiter,
+            json_compact: config.json_compact,
+            jsonl_single: config.jsonl_single,
+            parquet_combined: config.parquet_combined,
+            top: config.top,
+            xlsx_single_workbook: config.xlsx_single_workbook,
+            xlsx_acc_format: config.xlsx_acc_format,
+            xlsx_colors: config.xlsx_colors,
+            compression: config.compress,
+            compress_level: config.compress_level,
+            split_by: config.split_by,
+            layout: config.layout,
+        }
+    }
+
+    /// Options for split files, which leave out the difficulty column as it is
+    /// already part of the file name unless `keep_difficulty_column` is set.
+    pub fn for_split_files(&self, keep_difficulty_column: bool) -> OutputOptions {
+        let mut options = self.clone();
+        if !keep_difficulty_column && options.split_by == Some(SplitBy::Difficulty) {
+            options.columns.retain(|&column| column != Column::Difficulty);
+        }
+        options
+    }
+
+    /// File extension for `format`; tab-separated csv is written as `.tsv`, and
+    /// compressed csv gets a trailing `.gz`.
+    pub fn extension(&self, format: OutputFormat) -> &'static str {
+        if format != OutputFormat::Csv {
+            return format.extension();
+        }
+        match (self.delimiter == Delimiter::TAB, self.compression) {
+            (false, Compression::None) => "csv",
+            (true, Compression::None) => "tsv",
+            (false, Compression::Gzip) => "csv.gz",
+            (true, Compression::Gzip) => "tsv.gz",
+        }
+    }
+
+    pub fn csv_writer(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder.delimiter(self.delimiter.0);
+        builder
+    }
+}
+
+fn write_to_csv(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let file = BufWriter::new(File::create(output_path)?);
+    let mut writer = options.csv_writer().from_writer(CompressedWriter::new(
+        file,
+        options.compression,
+        options.compress_level,
+    ));
+    writer.write_record(options.columns.iter().map(|column| column.name()))?;
+    write_csv_rows(&mut writer, records, options)?;
+    finish_csv(writer)
+}
+
+/// A CSV writer on stdout with the header already written; songs are appended
+/// with [`write_csv_rows`] and the stream is completed with [`finish_csv`].
+pub fn stdout_csv_writer(options: &OutputOptions) -> Result<csv::Writer<CompressedWriter<io::Stdout>>> {
+    let stdout = CompressedWriter::new(io::stdout(), options.compression, options.compress_level);
+    let mut writer = options.csv_writer().from_writer(stdout);
+    writer.write_record(options.columns.iter().map(|column| column.name()))?;
+    Ok(writer)
+}
+
+/// Flushes `writer` and finishes its compression, if any.
+pub fn finish_csv<W: Write>(writer: csv::Writer<CompressedWriter<W>>) -> Result<()> {
+    writer.into_inner().map_err(|err| err.into_error())?.finish()?;
+    Ok(())
+}
+
+pub fn write_csv_rows<W: Write>(
+    writer: &mut csv::Writer<W>,
+    records: &[ProcessedRecord],
+    options: &OutputOptions,
+) -> Result<()> {
+    for (index, record) in records.iter().enumerate() {
+        let row: Vec<_> = options.columns.iter().map(|column| column.cell(record)).collect();
+        writer.serialize(row).with_context(|| describe_row(index, record))?;
+    }
+    Ok(())
+}
+
+/// Names a record by its position among the written rows (the header being
+/// row 0) and what it is a record of, for errors while writing it.
+fn describe_row(index: usize, record: &ProcessedRecord) -> String {
+    format!(
+        "Failed to write row {} ({} {} of {})",
+        index + 1,
+        record.song_name,
+        record.difficulty,
+        record.player_id
+    )
+}
+
+/// The cell writes shared by the spreadsheet formats, so that xlsx and ods lay
+/// out a sheet the same way.
+trait SheetCells {
+    fn write_str(&mut self, row: u32, col: u16, value: &str) -> Result<()>;
+    fn write_number(&mut self, row: u32, col: u16, value: f64) -> Result<()>;
+    fn write_bool(&mut self, row: u32, col: u16, value: bool) -> Result<()>;
+
+    /// Called before the cells of `record` are written.
+    fn start_row(&mut self, _record: &ProcessedRecord) {}
+}
+
+/// Writes the header row followed by one row per record.
+fn fill_sheet(sheet: &mut impl SheetCells, records: &[ProcessedRecord], options: &OutputOptions) -> Result<()> {
+    for (col, column) in options.columns.iter().enumerate() {
+        sheet.write_str(0, col as u16, column.name())?;
+    }
+    for (index, record) in records.iter().enumerate() {
+        fill_row(sheet, index as u32 + 1, record, options).with_context(|| describe_row(index, record))?;
+    }
+    Ok(())
+}
+
+fn fill_row(sheet: &mut impl SheetCells, row: u32, record: &ProcessedRecord, options: &OutputOptions) -> Result<()> {
+    sheet.start_row(record);
+    for (col, column) in options.columns.iter().enumerate() {
+        let col = col as u16;
+        match column.cell(record) {
+            Cell::Str(value) => sheet.write_str(row, col, value)?,
+            Cell::Int(value) => sheet.write_number(row, col, value as f64)?,
+            Cell::Float(value) => sheet.write_number(row, col, value)?,
+            Cell::Bool(value) => sheet.write_bool(row, col, value)?,
+            // Still written so the cell gets the row's style.
+            Cell::Empty => sheet.write_str(row, col, "")?,
+        }
+    }
+    Ok(())
+}
+
+impl SheetCells for spreadsheet_ods::Sheet {
+    fn write_str(&mut self, row: u32, col: u16, value: &str) -> Result<()> {
+        self.set_value(row, col as u32, value);
+        Ok(())
+    }
+
+    fn write_number(&mut self, row: u32, col: u16, value: f64) -> Result<()> {
+        self.set_value(row, col as u32, value);
+        Ok(())
+    }
+
+    fn write_bool(&mut self, row: u32, col: u16, value: bool) -> Result<()> {
+        self.set_value(row, col as u32, value);
+        Ok(())
+    }
+}
+
+fn write_to_ods(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let mut workbook = spreadsheet_ods::WorkBook::new_empty();
+    let mut sheet = spreadsheet_ods::Sheet::new("Sheet1");
+    fill_sheet(&mut sheet, records, options)?;
+    workbook.push_sheet(sheet);
+    spreadsheet_ods::write_ods(&mut workbook, output_path)?;
+    Ok(())
+}
+
+fn write_to_json(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    let rows: Vec<_> = records
+        .iter()
+        .map(|record| Row {
+            columns: &options.columns,
+            record,
+        })
+        .collect();
+    if options.json_compact {
+        serde_json::to_writer(&mut writer, &rows)?;
+    } else {
+        serde_json::to_writer_pretty(&mut writer, &rows)?;
+    }
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_to_jsonl(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    write_jsonl_rows(&mut writer, records, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serializes and writes one line per record, so nothing larger than a single
+/// record is buffered.
+pub fn write_jsonl_rows<W: Write>(writer: &mut W, records: &[ProcessedRecord], options: &OutputOptions) -> Result<()> {
+    for record in records {
+        serde_json::to_writer(
+            &mut *writer,
+            &Row {
+                columns: &options.columns,
+                record,
+            },
+        )?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "msgpack")]
+fn write_to_msgpack(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    let rows: Vec<_> = records
+        .iter()
+        .map(|record| Row {
+            columns: &options.columns,
+            record,
+        })
+        .collect();
+    phi_save_data::msgpack::write_msgpack(&mut writer, &rows)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_to_markdown(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let mut records: Vec<_> = records.iter().collect();
+    if let Some(top) = options.top {
+        // Stable, so ties keep the configured sort order.
+        records.sort_by_key(|record| Reverse(record.score));
+        records.truncate(top);
+    }
+
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    let header: Vec<_> = options.columns.iter().map(|column| column.name()).collect();
+    writeln!(writer, "| {} |", header.join(" | "))?;
+    writeln!(writer, "|{}", " --- |".repeat(header.len()))?;
+    for record in records {
+        let row: Vec<_> = options
+            .columns
+            .iter()
+            .map(|&column| match column.cell(record) {
+                Cell::Str(value) => escape_markdown(value),
+                Cell::Int(value) => value.to_string(),
+                Cell::Float(value) if column == Column::Acc => format!("{:.2}", value),
+                Cell::Float(value) => value.to_string(),
+                Cell::Bool(value) => if value { "✓" } else { "✗" }.to_string(),
+                Cell::Empty => String::new(),
+            })
+            .collect();
+        writeln!(writer, "| {} |", row.join(" | "))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Escapes characters that would otherwise break out of a table cell.
+pub fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '|' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(not(feature = "parquet"))]
+mod no_parquet {
+    use std::path::Path;
+
+    use anyhow::{bail, Result};
+
+    use super::OutputOptions;
+    use crate::ProcessedRecord;
+
+    /// Stand-in so callers compile without the `parquet` feature.
+    pub enum ParquetWriter {}
+
+    impl ParquetWriter {
+        pub fn create(_path: &Path, _options: &OutputOptions) -> Result<ParquetWriter> {
+            bail!("parquet output requires building with `--features parquet`")
+        }
+
+        pub fn write(&mut self, _records: &[ProcessedRecord]) -> Result<()> {
+            match *self {}
+        }
+
+        pub fn finish(self) -> Result<()> {
+            match self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn record(song_name: &str, score: i32) -> ProcessedRecord {
+        ProcessedRecord { song_name: song_name.to_string(), score, ..ProcessedRecord::default() }
+    }
+
+    fn options_with(delimiter: Delimiter) -> OutputOptions {
+        let mut config = Config::default();
+        config.delimiter = delimiter;
+        OutputOptions::from_config(&config)
+    }
+
+    /// `records` as csv with `delimiter`, with only the song name and score
+    /// columns, written in a directory of its own named after `name`.
+    fn csv_with(name: &str, delimiter: &str, records: &[ProcessedRecord]) -> String {
+        let mut options = options_with(delimiter.parse().unwrap());
+        options.columns = vec![Column::SongName, Column::Score];
+        let dir = std::env::temp_dir().join(format!("phi-save-data-delimiter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("song.{}", options.extension(OutputFormat::Csv)));
+        OutputFormat::Csv.write(records, &path, &options).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        written
+    }
+
+    #[test]
+    fn parses_delimiters() {
+        assert_eq!("\\t".parse::<Delimiter>().unwrap(), Delimiter::TAB);
+        assert_eq!("tab".parse::<Delimiter>().unwrap(), Delimiter::TAB);
+        assert_eq!(";".parse::<Delimiter>().unwrap(), Delimiter(b';'));
+        for invalid in ["", ";;", "\"", "\n", "é"] {
+            assert!(invalid.parse::<Delimiter>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn tab_delimited_csv_is_written_as_tsv() {
+        let extension = |delimiter| options_with(delimiter).extension(OutputFormat::Csv);
+        assert_eq!(extension(Delimiter::TAB), "tsv");
+        assert_eq!(extension(Delimiter::COMMA), "csv");
+        assert_eq!(extension(Delimiter(b';')), "csv");
+    }
+
+    #[test]
+    fn fields_holding_the_delimiter_are_quoted() {
+        let records = [record("Hello, World", 1), record("Plain", 2)];
+        assert_eq!(csv_with("comma", ",", &records), "song_name,score\n\"Hello, World\",1\nPlain,2\n");
+        let records = [record("A;B", 1), record("A,B", 2)];
+        assert_eq!(csv_with("semicolon", ";", &records), "song_name;score\n\"A;B\";1\nA,B;2\n");
+        let records = [record("A\tB", 1)];
+        assert_eq!(csv_with("tab", "\\t", &records), "song_name\tscore\n\"A\tB\"\t1\n");
+    }
+
+    #[test]
+    fn quotes_and_newlines_are_quoted() {
+        let records = [record("Say \"Hi\"", 1), record("Two\nLines", 2)];
+        assert_eq!(csv_with("quotes", ";", &records), "song_name;score\n\"Say \"\"Hi\"\"\";1\n\"Two\nLines\";2\n");
+    }
+}
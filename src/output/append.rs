@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use super::{Column, OutputOptions};
+use crate::ProcessedRecord;
+
+/// Reads an existing csv back so new records can be merged into it. The file
+/// must have exactly the selected columns; anything else aborts instead of
+/// mixing layouts.
+fn read_existing_csv(path: &Path, options: &OutputOptions) -> Result<Vec<ProcessedRecord>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter.0)
+        .from_path(path)
+        .with_context(|| format!("Failed to open {} for --append", path.display()))?;
+    let header: Vec<_> = reader.headers()?.iter().map(str::to_string).collect();
+    let expected: Vec<_> = options.columns.iter().map(|column| column.name()).collect();
+    if header != expected {
+        bail!(
+            "{} has columns `{}`, expected `{}`; rerun without --append or with matching --columns",
+            path.display(),
+            header.join(","),
+            expected.join(",")
+        );
+    }
+    reader
+        .deserialize()
+        .enumerate()
+        .map(|(i, row)| row.with_context(|| format!("{}: invalid row {}", path.display(), i + 2)))
+        .collect()
+}
+
+/// The rows of `path`, if it exists, followed by `records`, keeping only the
+/// best score for each distinct value of the `dedupe_on` columns.
+pub fn merge_existing_csv(
+    path: &Path,
+    records: &[ProcessedRecord],
+    options: &OutputOptions,
+    dedupe_on: &[Column],
+) -> Result<Vec<ProcessedRecord>> {
+    let mut merged = if path.exists() {
+        read_existing_csv(path, options)?
+    } else {
+        Vec::new()
+    };
+    merged.extend(records.iter().cloned());
+
+    let mut best: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut kept: Vec<ProcessedRecord> = Vec::new();
+    for record in merged {
+        let key = dedupe_on.iter().map(|column| column.cell(&record).to_string()).collect();
+        match best.get(&key) {
+            Some(&i) => {
+                if (record.score, record.acc) > (kept[i].score, kept[i].acc) {
+                    kept[i] = record;
+                }
+            }
+            None => {
+                best.insert(key, kept.len());
+                kept.push(record);
+            }
+        }
+    }
+    Ok(kept)
+}
@@ -0,0 +1,155 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// `{name}.tmp` next to `path`, so the final rename stays on one filesystem.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Runs `write` against a temporary file and moves it over `path` only if it
+/// succeeds, so an interrupted or failed write never leaves a truncated file
+/// that looks complete.
+pub fn write_atomically(path: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let temp = TempFile::new(path);
+    write(temp.path())?;
+    temp.persist()
+}
+
+/// A file written under a temporary name, for writers that keep it open over
+/// the whole run. [`TempFile::persist`] moves it over the final path once it
+/// is complete; dropped before then, e.g. when the run stops with an error,
+/// the temporary file is removed.
+pub struct TempFile {
+    temp: PathBuf,
+    target: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    pub fn new(path: &Path) -> TempFile {
+        TempFile { temp: temp_path(path), target: path.to_path_buf(), persisted: false }
+    }
+
+    /// With the `.tmp` before the extension, `{stem}.tmp.{extension}`, for
+    /// writers that pick the format by the extension of the file.
+    #[cfg_attr(not(feature = "plots"), allow(dead_code))]
+    pub fn keeping_extension(path: &Path) -> TempFile {
+        let mut name = path.file_stem().map(OsString::from).unwrap_or_default();
+        name.push(".tmp");
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        TempFile { temp: path.with_file_name(name), target: path.to_path_buf(), persisted: false }
+    }
+
+    /// Where to write.
+    pub fn path(&self) -> &Path {
+        &self.temp
+    }
+
+    pub fn persist(mut self) -> Result<()> {
+        replace(&self.temp, &self.target)
+            .with_context(|| format!("Failed to move {} into place", self.temp.display()))?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        // Renaming over an existing file can fail on Windows, e.g. when it is
+        // marked read-only or another program still has it open.
+        Err(_) if cfg!(windows) && to.exists() => {
+            fs::remove_file(to)?;
+            fs::rename(from, to)
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("phi-save-data-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn moves_the_finished_file_into_place() {
+        let dir = scratch_dir("atomic-ok");
+        let path = dir.join("report.csv");
+        fs::write(&path, "old").unwrap();
+        write_atomically(&path, |temp| {
+            assert_ne!(temp, path);
+            fs::write(temp, "new")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temp_path(&path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_leaves_no_files_behind() {
+        let dir = scratch_dir("atomic-fail");
+        let path = dir.join("report.csv");
+        let result = write_atomically(&path, |temp| {
+            fs::write(temp, "half a ")?;
+            bail!("writer failed")
+        });
+        assert_eq!(result.unwrap_err().to_string(), "writer failed");
+        assert!(!path.exists());
+        assert!(!temp_path(&path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_keeps_the_existing_file() {
+        let dir = scratch_dir("atomic-keep");
+        let path = dir.join("report.csv");
+        fs::write(&path, "complete").unwrap();
+        assert!(write_atomically(&path, |temp| {
+            fs::write(temp, "trunc")?;
+            bail!("writer failed")
+        })
+        .is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "complete");
+        assert!(!temp_path(&path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dropped_temp_file_is_removed() {
+        let dir = scratch_dir("atomic-drop");
+        let path = dir.join("plot.png");
+        let temp = TempFile::keeping_extension(&path);
+        assert_eq!(temp.path(), dir.join("plot.tmp.png"));
+        fs::write(temp.path(), "partial").unwrap();
+        let written = temp.path().to_path_buf();
+        drop(temp);
+        assert!(!written.exists());
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+}
+
+/// A writer that is either passed through or gzip-compressed. Call
+/// [`CompressedWriter::finish`] so the gzip trailer is written.
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(inner: W, compression: Compression, level: u32) -> CompressedWriter<W> {
+        match compression {
+            Compression::None => CompressedWriter::Plain(inner),
+            Compression::Gzip => CompressedWriter::Gzip(GzEncoder::new(inner, flate2::Compression::new(level))),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CompressedWriter::Plain(mut inner) => {
+                inner.flush()?;
+                Ok(inner)
+            }
+            CompressedWriter::Gzip(encoder) => {
+                let mut inner = encoder.finish()?;
+                inner.flush()?;
+                Ok(inner)
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(inner) => inner.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(inner) => inner.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
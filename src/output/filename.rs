@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+
+use super::write_atomically;
+
+/// Lists the song names that were changed to make file names of them.
+pub const FILENAME_MAP_FILE: &str = "filename_map.csv";
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes a song name usable as a file stem on any platform: path separators,
+/// the characters Windows doesn't allow and control characters become `_`,
+/// and trailing dots and spaces, which Windows drops, are trimmed. Names
+/// that are fine already are returned as they are.
+pub fn sanitize_filename(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = replaced.trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        return "_".to_string();
+    }
+    let device = trimmed.split('.').next().unwrap_or_default();
+    if RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(device)) {
+        return format!("{}_{}", device, &trimmed[device.len()..]);
+    }
+    trimmed.to_string()
+}
+
+/// The file stem of each song: its sanitized name, with `_2`, `_3` and so
+/// on appended when songs come out the same, the first song keeping the plain
+/// stem.
+#[derive(Default)]
+pub struct FileStems {
+    by_song: BTreeMap<String, String>,
+    /// The song each stem was given to.
+    taken: HashMap<String, String>,
+}
+
+impl FileStems {
+    /// The stems of `song_names`, handed out in the order given.
+    pub fn new(song_names: &[String]) -> Self {
+        let mut stems = FileStems::default();
+        for song_name in song_names {
+            stems.stem(song_name);
+        }
+        stems
+    }
+
+    /// The stem of `song_name`, given it now if it has none yet.
+    pub fn stem(&mut self, song_name: &str) -> &str {
+        if !self.by_song.contains_key(song_name) {
+            let base = sanitize_filename(song_name);
+            let mut stem = base.clone();
+            let mut n = 1;
+            while self.taken.contains_key(&stem) {
+                n += 1;
+                stem = format!("{}_{}", base, n);
+            }
+            if n > 1 {
+                warn!(
+                    "`{}` and `{}` both make the file name {}; writing `{}` as {}",
+                    self.taken[&base], song_name, base, song_name, stem
+                );
+            }
+            self.taken.insert(stem.clone(), song_name.to_string());
+            self.by_song.insert(song_name.to_string(), stem);
+        }
+        &self.by_song[song_name]
+    }
+
+    /// Stem and song name of the songs whose stem isn't their name.
+    pub fn renamed(&self) -> Vec<(&str, &str)> {
+        self.by_song
+            .iter()
+            .filter(|(song_name, stem)| song_name != stem)
+            .map(|(song_name, stem)| (stem.as_str(), song_name.as_str()))
+            .collect()
+    }
+}
+
+/// Writes each file stem and the song name it was made from, so the files of
+/// renamed songs can be traced back.
+pub fn write_filename_map(renamed: &[(&str, &str)], path: &Path) -> Result<()> {
+    write_atomically(path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        writer.write_record(["file_stem", "song_name"])?;
+        for &(stem, song_name) in renamed {
+            writer.write_record([stem, song_name])?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
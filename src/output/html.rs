@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::{Cell, Column, OutputOptions, TempFile};
+use crate::ProcessedRecord;
+
+pub const REPORT_FILE: &str = "report.html";
+
+const HEAD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Phigros score report</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin: 0.5em 0 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.6em; text-align: left; }
+th { background: #eee; cursor: pointer; user-select: none; }
+td.num { text-align: right; }
+summary { cursor: pointer; font-weight: bold; }
+</style>
+</head>
+<body>
+"#;
+
+// Clicking a header sorts its table by that column, numerically when the
+// cells carry a data-value, and toggles the direction on repeated clicks.
+// Numeric cells without a value have an empty data-value and go last either
+// way.
+const TAIL: &str = r#"<script>
+document.querySelectorAll("table").forEach(function (table) {
+  table.querySelectorAll("th").forEach(function (th, index) {
+    th.addEventListener("click", function () {
+      var body = table.tBodies[0];
+      var rows = Array.prototype.slice.call(body.rows);
+      var descending = th.dataset.order !== "desc";
+      th.dataset.order = descending ? "desc" : "asc";
+      rows.sort(function (a, b) {
+        var x = a.cells[index].dataset.value, y = b.cells[index].dataset.value;
+        var result;
+        if (x === undefined || y === undefined) {
+          result = a.cells[index].textContent.localeCompare(b.cells[index].textContent);
+        } else if (x === "" || y === "") {
+          return (x === "") - (y === "");
+        } else {
+          result = parseFloat(x) - parseFloat(y);
+        }
+        return descending ? -result : result;
+      });
+      rows.forEach(function (row) { body.appendChild(row); });
+    });
+  });
+});
+</script>
+</body>
+</html>
+"#;
+
+/// A single HTML page with one collapsible, sortable table per song.
+pub struct HtmlReport {
+    writer: BufWriter<File>,
+    temp: TempFile,
+}
+
+impl HtmlReport {
+    pub fn create(path: &Path, saves: usize, songs: usize) -> Result<HtmlReport> {
+        let temp = TempFile::new(path);
+        let file = File::create(temp.path())
+            .with_context(|| format!("Failed to create file: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(HEAD.as_bytes())?;
+        writeln!(writer, "<h1>Phigros score report</h1>")?;
+        writeln!(
+            writer,
+            "<p>Generated {} from {} saves, {} songs.</p>",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            saves,
+            songs
+        )?;
+        Ok(HtmlReport { writer, temp })
+    }
+
+    pub fn add_song(&mut self, song_name: &str, records: &[ProcessedRecord], options: &OutputOptions) -> Result<()> {
+        let writer = &mut self.writer;
+        writeln!(
+            writer,
+            "<details>\n<summary>{} ({})</summary>\n<table>\n<thead><tr>",
+            escape_html(song_name),
+            records.len()
+        )?;
+        for column in &options.columns {
+            write!(writer, "<th>{}</th>", column.name())?;
+        }
+        writeln!(writer, "</tr></thead>\n<tbody>")?;
+        for record in records {
+            write!(writer, "<tr>")?;
+            for &column in &options.columns {
+                match column.cell(record) {
+                    Cell::Str(value) => write!(writer, "<td>{}</td>", escape_html(value))?,
+                    Cell::Int(value) => write!(writer, r#"<td class="num" data-value="{0}">{0}</td>"#, value)?,
+                    Cell::Float(value) if column == Column::Acc => {
+                        write!(writer, r#"<td class="num" data-value="{0}">{0:.2}%</td>"#, value)?
+                    }
+                    Cell::Float(value) => write!(writer, r#"<td class="num" data-value="{0}">{0:.4}</td>"#, value)?,
+                    Cell::Bool(value) => write!(
+                        writer,
+                        r#"<td data-value="{}">{}</td>"#,
+                        value as u8,
+                        if value { "✓" } else { "✗" }
+                    )?,
+                    Cell::Empty => write!(writer, r#"<td class="num" data-value=""></td>"#)?,
+                }
+            }
+            writeln!(writer, "</tr>")?;
+        }
+        writeln!(writer, "</tbody>\n</table>\n</details>")?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.write_all(TAIL.as_bytes())?;
+        self.writer.flush()?;
+        self.temp.persist()
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn numeric_cells_without_a_value_sort_as_empty() {
+        let dir = std::env::temp_dir().join(format!("phi-save-data-html-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(REPORT_FILE);
+        let mut options = OutputOptions::from_config(&Config::default());
+        options.columns = vec![Column::SongName, Column::PlayRks];
+        let records = [
+            ProcessedRecord { song_name: "A".to_string(), play_rks: Some(12.5), ..ProcessedRecord::default() },
+            ProcessedRecord { song_name: "A".to_string(), ..ProcessedRecord::default() },
+        ];
+        let mut report = HtmlReport::create(&path, 1, 1).unwrap();
+        report.add_song("A", &records, &options).unwrap();
+        report.finish().unwrap();
+        let html = std::fs::read_to_string(&path).unwrap();
+        assert!(html.contains(r#"<tr><td>A</td><td class="num" data-value="12.5">12.5000</td></tr>"#), "{}", html);
+        assert!(html.contains(r#"<tr><td>A</td><td class="num" data-value=""></td></tr>"#), "{}", html);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
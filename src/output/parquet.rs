@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int32Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+
+use super::{Cell, Column, OutputOptions, TempFile};
+use crate::ProcessedRecord;
+
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+fn data_type(column: Column) -> DataType {
+    match column {
+        Column::PlayerId
+        | Column::SongName
+        | Column::Artist
+        | Column::Grade
+        | Column::Difficulty
+        | Column::GameVersion
+        | Column::Avatar
+        | Column::CapturedAt
+        | Column::ChartSuffix
+        | Column::Snapshot => DataType::Utf8,
+        Column::Score | Column::ScoreDeficit => DataType::Int32,
+        Column::Acc | Column::RankingScore | Column::ChartConstant | Column::PlayRks | Column::AccPercentile => {
+            DataType::Float64
+        }
+        Column::Fc | Column::Ap | Column::Invalid => DataType::Boolean,
+    }
+}
+
+fn column_array(column: Column, records: &[ProcessedRecord]) -> ArrayRef {
+    let cells = records.iter().map(|record| column.cell(record));
+    match data_type(column) {
+        DataType::Int32 => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Cell::Int(value) => i32::try_from(value).ok(),
+                    _ => None,
+                })
+                .collect::<Int32Array>(),
+        ),
+        DataType::Float64 => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Cell::Float(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<Float64Array>(),
+        ),
+        DataType::Boolean => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Cell::Bool(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<BooleanArray>(),
+        ),
+        _ => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Cell::Str(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<StringArray>(),
+        ),
+    }
+}
+
+/// A parquet file that songs are appended to one record batch at a time.
+pub struct ParquetWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    temp: TempFile,
+}
+
+impl ParquetWriter {
+    pub fn create(path: &Path, options: &OutputOptions) -> Result<ParquetWriter> {
+        let schema = Arc::new(Schema::new(
+            options
+                .columns
+                .iter()
+                .map(|&column| Field::new(column.name(), data_type(column), true))
+                .collect(),
+        ));
+        let temp = TempFile::new(path);
+        let file = File::create(temp.path())
+            .with_context(|| format!("Failed to create file: {}", path.display()))?;
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+        Ok(ParquetWriter {
+            writer,
+            schema,
+            columns: options.columns.clone(),
+            temp,
+        })
+    }
+
+    pub fn write(&mut self, records: &[ProcessedRecord]) -> Result<()> {
+        let arrays = self
+            .columns
+            .iter()
+            .map(|&column| column_array(column, records))
+            .collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.writer.write(&batch)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.writer.close()?;
+        self.temp.persist()
+    }
+}
+
+pub fn write_to_parquet(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let mut writer = ParquetWriter::create(output_path, options)?;
+    writer.write(records)?;
+    writer.finish()
+}
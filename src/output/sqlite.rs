@@ -0,0 +1,167 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::ProcessedRecord;
+
+pub const DATABASE_FILE: &str = "records.db";
+
+const SCHEMA_VERSION: &str = "5";
+
+// One row per player, snapshot, song and difficulty, so re-running over the
+// same saves replaces rows instead of duplicating them, while each snapshot of
+// a player keeps its own rows.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS records (
+    player_id TEXT NOT NULL,
+    song_id TEXT NOT NULL,
+    snapshot TEXT NOT NULL,
+    song_name TEXT NOT NULL,
+    artist TEXT NOT NULL,
+    chart_suffix TEXT NOT NULL,
+    difficulty TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    score_deficit INTEGER NOT NULL,
+    acc REAL NOT NULL,
+    fc INTEGER NOT NULL,
+    ap INTEGER NOT NULL,
+    grade TEXT NOT NULL,
+    ranking_score REAL NOT NULL,
+    game_version TEXT NOT NULL,
+    avatar TEXT NOT NULL,
+    captured_at TEXT NOT NULL,
+    invalid INTEGER NOT NULL,
+    chart_constant REAL,
+    play_rks REAL,
+    acc_percentile REAL,
+    UNIQUE (player_id, snapshot, song_id, difficulty)
+);
+CREATE INDEX IF NOT EXISTS records_song_difficulty ON records (song_name, difficulty);
+CREATE INDEX IF NOT EXISTS records_score ON records (score);
+";
+
+/// Writes every record of a run into one database inside a single transaction.
+pub struct SqliteWriter {
+    conn: Connection,
+}
+
+impl SqliteWriter {
+    pub fn open(path: &Path) -> Result<SqliteWriter> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open database: {}", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        let version: Option<String> = conn
+            .query_row("SELECT value FROM meta WHERE key = 'schema_version'", [], |row| row.get(0))
+            .optional()?;
+        match version.as_deref() {
+            None => {
+                conn.execute(
+                    "INSERT INTO meta (key, value) VALUES ('schema_version', ?1)",
+                    params![SCHEMA_VERSION],
+                )?;
+            }
+            Some(SCHEMA_VERSION) => {}
+            Some(other) => bail!(
+                "{} has schema version {}, expected {}",
+                path.display(),
+                other,
+                SCHEMA_VERSION
+            ),
+        }
+        conn.execute_batch("BEGIN")?;
+        Ok(SqliteWriter { conn })
+    }
+
+    pub fn insert(&mut self, records: &[ProcessedRecord]) -> Result<()> {
+        let mut statement = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO records
+                (player_id, song_id, snapshot, song_name, artist, chart_suffix, difficulty, score, score_deficit,
+                 acc, fc, ap, grade, ranking_score, game_version, avatar, captured_at, invalid, chart_constant,
+                 play_rks, acc_percentile)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)",
+        )?;
+        for record in records {
+            statement.execute(params![
+                record.player_id,
+                record.song_id,
+                record.snapshot,
+                record.song_name,
+                record.artist,
+                record.chart_suffix,
+                record.difficulty,
+                record.score,
+                record.score_deficit,
+                record.acc,
+                record.fc,
+                record.ap,
+                record.grade,
+                record.ranking_score,
+                record.game_version,
+                record.avatar,
+                record.captured_at,
+                record.invalid,
+                record.chart_constant,
+                record.play_rks,
+                record.acc_percentile,
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(snapshot: &str, score: i32) -> ProcessedRecord {
+        ProcessedRecord {
+            player_id: "p1".to_string(),
+            song_id: "Song.Artist".to_string(),
+            song_name: "Song".to_string(),
+            difficulty: "IN".to_string(),
+            snapshot: snapshot.to_string(),
+            score,
+            play_rks: Some(12.5),
+            ..ProcessedRecord::default()
+        }
+    }
+
+    #[test]
+    fn each_snapshot_keeps_its_rows_and_reruns_replace_them() {
+        let dir = std::env::temp_dir().join(format!("phi-save-data-sqlite-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(DATABASE_FILE);
+        for _ in 0..2 {
+            let mut writer = SqliteWriter::open(&path).unwrap();
+            writer.insert(&[record("save_1", 900_000), record("save_2", 950_000)]).unwrap();
+            writer.finish().unwrap();
+        }
+        let conn = Connection::open(&path).unwrap();
+        let mut statement = conn.prepare("SELECT snapshot, score, play_rks, acc_percentile FROM records").unwrap();
+        let mut rows: Vec<(String, i32, Option<f64>, Option<f64>)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            rows,
+            [
+                ("save_1".to_string(), 900_000, Some(12.5), None),
+                ("save_2".to_string(), 950_000, Some(12.5), None)
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
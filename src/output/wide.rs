@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::Result;
+use xlsxwriter::Format;
+
+use super::{create_workbook, finish_csv, CompressedWriter, OutputOptions};
+use crate::input::player_key;
+use crate::{Difficulty, ProcessedRecord};
+
+/// A player's best (score, acc) for each difficulty of one song.
+type WideRow = [Option<(i32, f64)>; Difficulty::ALL.len()];
+
+/// One row per player, keeping the best score per difficulty when a player
+/// appears in several saves or snapshots.
+fn pivot(records: &[ProcessedRecord]) -> BTreeMap<&str, WideRow> {
+    let mut rows: BTreeMap<&str, WideRow> = BTreeMap::new();
+    for record in records {
+        let row = rows.entry(player_key(&record.player_id)).or_default();
+        // Unknown difficulties have no column.
+        let Some(cell) = row.get_mut(Difficulty::rank(&record.difficulty)) else {
+            continue;
+        };
+        if cell.is_none_or(|(score, acc)| (record.score, record.acc) > (score, acc)) {
+            *cell = Some((record.score, record.acc));
+        }
+    }
+    rows
+}
+
+fn header() -> Vec<String> {
+    let mut header = vec!["player".to_string()];
+    for difficulty in Difficulty::ALL {
+        header.push(format!("{}_score", difficulty.as_str()));
+        header.push(format!("{}_acc", difficulty.as_str()));
+    }
+    header
+}
+
+pub fn write_to_csv(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let file = BufWriter::new(File::create(output_path)?);
+    let mut writer = options.csv_writer().from_writer(CompressedWriter::new(
+        file,
+        options.compression,
+        options.compress_level,
+    ));
+    writer.write_record(header())?;
+    for (player, cells) in pivot(records) {
+        let mut row = vec![player.to_string()];
+        for cell in cells {
+            match cell {
+                Some((score, acc)) => {
+                    row.push(score.to_string());
+                    row.push(acc.to_string());
+                }
+                None => row.extend([String::new(), String::new()]),
+            }
+        }
+        writer.write_record(&row)?;
+    }
+    finish_csv(writer)
+}
+
+pub fn write_to_excel(records: &[ProcessedRecord], output_path: &Path) -> Result<()> {
+    let workbook = create_workbook(output_path)?;
+    let mut sheet = workbook.add_worksheet(None)?;
+    let mut bold = Format::new();
+    bold.set_bold();
+    let mut score = Format::new();
+    score.set_num_format("#,##0");
+    let mut acc = Format::new();
+    acc.set_num_format("0.00");
+
+    for (col, name) in header().iter().enumerate() {
+        sheet.write_string(0, col as u16, name, Some(&bold))?;
+    }
+    for (row, (player, cells)) in pivot(records).into_iter().enumerate() {
+        let row = row as u32 + 1;
+        sheet.write_string(row, 0, player, None)?;
+        for (i, cell) in cells.into_iter().enumerate() {
+            // Blank cells are simply left unwritten.
+            if let Some((value, accuracy)) = cell {
+                let col = 1 + 2 * i as u16;
+                sheet.write_number(row, col, value as f64, Some(&score))?;
+                sheet.write_number(row, col + 1, accuracy, Some(&acc))?;
+            }
+        }
+    }
+    sheet.set_column(0, 0, 24.0, None)?;
+    sheet.freeze_panes(1, 1);
+    workbook.close()?;
+    Ok(())
+}
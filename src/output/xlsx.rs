@@ -0,0 +1,392 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use xlsxwriter::{Format, FormatColor, Workbook, Worksheet};
+
+use super::{fill_sheet, Column, OutputOptions, SheetCells, TempFile};
+use crate::{Difficulty, ProcessedRecord};
+
+/// File written instead of per-song xlsx files with `--xlsx-single-workbook`.
+pub const WORKBOOK_FILE: &str = "all_songs.xlsx";
+
+const MAX_SHEET_NAME: usize = 31;
+
+/// Rows per worksheet, including the header.
+const MAX_ROWS: usize = 1_048_576;
+
+/// How `acc` is displayed; the stored value is always the 0-100 number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AccFormat {
+    /// `98.76`
+    Number,
+    /// `98.76%`
+    Percent,
+}
+
+const FC_FILL: FormatColor = FormatColor::Custom(0xDDEBF7);
+const AP_FILL: FormatColor = FormatColor::Custom(0xFFD966);
+
+/// Rows are highlighted by how well the record was played.
+#[derive(Clone, Copy)]
+enum RowStyle {
+    Plain,
+    FullCombo,
+    AllPerfect,
+}
+
+impl RowStyle {
+    const ALL: [RowStyle; 3] = [RowStyle::Plain, RowStyle::FullCombo, RowStyle::AllPerfect];
+
+    fn of(record: &ProcessedRecord) -> RowStyle {
+        if record.ap {
+            RowStyle::AllPerfect
+        } else if record.fc {
+            RowStyle::FullCombo
+        } else {
+            RowStyle::Plain
+        }
+    }
+
+    fn fill(self) -> Option<FormatColor> {
+        match self {
+            RowStyle::Plain => None,
+            RowStyle::FullCombo => Some(FC_FILL),
+            RowStyle::AllPerfect => Some(AP_FILL),
+        }
+    }
+}
+
+/// The bold header plus, for every row style, the format of each column.
+struct SheetStyles {
+    header: Format,
+    rows: [Vec<Format>; 3],
+    colors: bool,
+}
+
+impl SheetStyles {
+    fn new(options: &OutputOptions) -> SheetStyles {
+        let mut header = Format::new();
+        header.set_bold();
+        SheetStyles {
+            header,
+            rows: RowStyle::ALL.map(|style| Self::column_formats(options, style)),
+            colors: options.xlsx_colors,
+        }
+    }
+
+    fn column_formats(options: &OutputOptions, style: RowStyle) -> Vec<Format> {
+        options
+            .columns
+            .iter()
+            .map(|&column| {
+                let mut format = Format::new();
+                if let Some(fill) = style.fill() {
+                    format.set_bg_color(fill);
+                }
+                match column {
+                    Column::Score | Column::ScoreDeficit => {
+                        format.set_num_format("#,##0");
+                    }
+                    Column::Acc => {
+                        format.set_num_format(match options.xlsx_acc_format {
+                            AccFormat::Number => "0.00",
+                            // The value is already 0-100, so a real percent format would scale it again.
+                            AccFormat::Percent => "0.00\"%\"",
+                        });
+                    }
+                    Column::RankingScore | Column::PlayRks => {
+                        format.set_num_format("0.0000");
+                    }
+                    Column::ChartConstant | Column::AccPercentile => {
+                        format.set_num_format("0.0");
+                    }
+                    _ => {}
+                }
+                format
+            })
+            .collect()
+    }
+
+    fn format(&self, row: u32, col: u16, style: RowStyle) -> Option<&Format> {
+        if row == 0 {
+            return Some(&self.header);
+        }
+        let style = if self.colors { style } else { RowStyle::Plain };
+        self.rows[style as usize].get(col as usize)
+    }
+}
+
+fn column_width(column: Column) -> f64 {
+    match column {
+        Column::SongName => 40.0,
+        Column::Difficulty | Column::Fc | Column::Ap | Column::Grade | Column::Invalid => 10.0,
+        Column::GameVersion => 14.0,
+        Column::PlayerId | Column::Artist | Column::CapturedAt | Column::Snapshot => 20.0,
+        _ => 12.0,
+    }
+}
+
+/// A worksheet that writes every cell with its column's and row's style.
+struct StyledSheet<'s, 'w> {
+    sheet: &'s mut Worksheet<'w>,
+    styles: &'s SheetStyles,
+    row_style: RowStyle,
+}
+
+impl SheetCells for StyledSheet<'_, '_> {
+    fn write_str(&mut self, row: u32, col: u16, value: &str) -> Result<()> {
+        self.sheet.write_string(row, col, value, self.styles.format(row, col, self.row_style))?;
+        Ok(())
+    }
+
+    fn write_number(&mut self, row: u32, col: u16, value: f64) -> Result<()> {
+        self.sheet.write_number(row, col, value, self.styles.format(row, col, self.row_style))?;
+        Ok(())
+    }
+
+    fn write_bool(&mut self, row: u32, col: u16, value: bool) -> Result<()> {
+        self.sheet.write_boolean(row, col, value, self.styles.format(row, col, self.row_style))?;
+        Ok(())
+    }
+
+    fn start_row(&mut self, record: &ProcessedRecord) {
+        self.row_style = RowStyle::of(record);
+    }
+}
+
+/// Fills `sheet` and makes it readable without manual fiddling: frozen bold
+/// header, an autofilter over the data, per-column widths and number formats,
+/// and FC/AP rows highlighted unless `--no-xlsx-colors` is given.
+fn write_sheet(sheet: &mut Worksheet<'_>, records: &[ProcessedRecord], options: &OutputOptions) -> Result<()> {
+    if records.len() >= MAX_ROWS {
+        bail!(
+            "{} records do not fit in an xlsx sheet (at most {} rows), use --format csv instead",
+            records.len(),
+            MAX_ROWS - 1
+        );
+    }
+    let styles = SheetStyles::new(options);
+    let mut styled = StyledSheet {
+        sheet,
+        styles: &styles,
+        row_style: RowStyle::Plain,
+    };
+    fill_sheet(&mut styled, records, options)?;
+
+    for (col, &column) in options.columns.iter().enumerate() {
+        sheet.set_column(col as u16, col as u16, column_width(column), None)?;
+    }
+    sheet.freeze_panes(1, 0);
+    if !options.columns.is_empty() {
+        sheet.autofilter(0, 0, records.len() as u32, options.columns.len() as u16 - 1)?;
+    }
+    Ok(())
+}
+
+/// Per-difficulty totals for the first sheet of a song's workbook. Every
+/// difficulty gets a row, even without records, so the layout is the same for
+/// every song.
+fn write_summary_sheet(sheet: &mut Worksheet<'_>, records: &[ProcessedRecord]) -> Result<()> {
+    let mut header = Format::new();
+    header.set_bold();
+    let mut acc = Format::new();
+    acc.set_num_format("0.00");
+    let mut score = Format::new();
+    score.set_num_format("#,##0");
+
+    for (col, name) in ["difficulty", "records", "avg_acc", "max_score", "fc_count"].into_iter().enumerate() {
+        sheet.write_string(0, col as u16, name, Some(&header))?;
+    }
+    for (row, difficulty) in Difficulty::ALL.into_iter().enumerate() {
+        let row = row as u32 + 1;
+        let matching: Vec<_> = records.iter().filter(|r| r.difficulty == difficulty.as_str()).collect();
+        let count = matching.len();
+        let avg_acc = if count == 0 {
+            0.0
+        } else {
+            matching.iter().map(|r| r.acc).sum::<f64>() / count as f64
+        };
+        let max_score = matching.iter().map(|r| r.score).max().unwrap_or(0);
+        let fc_count = matching.iter().filter(|r| r.fc).count();
+
+        sheet.write_string(row, 0, difficulty.as_str(), None)?;
+        sheet.write_number(row, 1, count as f64, None)?;
+        sheet.write_number(row, 2, avg_acc, Some(&acc))?;
+        sheet.write_number(row, 3, max_score as f64, Some(&score))?;
+        sheet.write_number(row, 4, fc_count as f64, None)?;
+    }
+    sheet.set_column(0, 4, 12.0, None)?;
+    sheet.freeze_panes(1, 0);
+    Ok(())
+}
+
+/// Creates an xlsx file, which xlsxwriter can only do for UTF-8 paths.
+pub fn create_workbook(path: &Path) -> Result<Workbook> {
+    let Some(name) = path.to_str() else {
+        bail!("Can't write xlsx to {}, which is not valid UTF-8", path.display());
+    };
+    Workbook::new(name).with_context(|| format!("Failed to create file: {}", path.display()))
+}
+
+/// A song's workbook: a "Summary" sheet followed by the "Records" sheet.
+pub fn write_to_excel(records: &[ProcessedRecord], output_path: &Path, options: &OutputOptions) -> Result<()> {
+    let workbook = create_workbook(output_path)?;
+    let mut summary = workbook.add_worksheet(Some("Summary"))?;
+    write_summary_sheet(&mut summary, records)?;
+    let mut sheet = workbook.add_worksheet(Some("Records"))?;
+    write_sheet(&mut sheet, records, options)?;
+    workbook.close()?;
+    Ok(())
+}
+
+/// One workbook holding a worksheet per song, added in the order songs are
+/// written.
+pub struct XlsxWorkbook {
+    workbook: Workbook,
+    sheet_names: HashSet<String>,
+    /// After the workbook, which may still write the file when dropped.
+    temp: TempFile,
+}
+
+impl XlsxWorkbook {
+    pub fn create(path: &Path) -> Result<XlsxWorkbook> {
+        let temp = TempFile::new(path);
+        let workbook = create_workbook(temp.path())?;
+        Ok(XlsxWorkbook {
+            workbook,
+            sheet_names: HashSet::new(),
+            temp,
+        })
+    }
+
+    pub fn add_song(&mut self, song_name: &str, records: &[ProcessedRecord], options: &OutputOptions) -> Result<()> {
+        let name = self.sheet_name(song_name);
+        let mut sheet = self.workbook.add_worksheet(Some(&name))?;
+        write_sheet(&mut sheet, records, options)
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.workbook.close()?;
+        self.temp.persist()
+    }
+
+    /// A valid sheet name for `song_name` that no earlier sheet uses; Excel
+    /// compares sheet names case-insensitively.
+    fn sheet_name(&mut self, song_name: &str) -> String {
+        let base: String = song_name
+            .chars()
+            .map(|c| match c {
+                '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+        let base = base.trim_matches('\'');
+        let base = if base.is_empty() { "Sheet" } else { base };
+
+        let mut name = truncate(base, MAX_SHEET_NAME);
+        let mut n = 2;
+        while !self.sheet_names.insert(name.to_lowercase()) {
+            let suffix = format!(" ({})", n);
+            name = truncate(base, MAX_SHEET_NAME - suffix.len()) + &suffix;
+            n += 1;
+        }
+        name
+    }
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    s.chars().take(max_chars).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> String {
+        let mut text = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    /// The text between `start` and `end` after each `start` in `xml`.
+    fn between<'a>(xml: &'a str, start: &str, end: &str) -> Vec<&'a str> {
+        xml.split(start).skip(1).map(|part| part.split(end).next().unwrap()).collect()
+    }
+
+    /// The values of each row in a worksheet, with shared strings looked up,
+    /// numbers formatted back from the 16 digits they are written with and
+    /// booleans as `0` / `1`.
+    fn sheet_rows(sheet: &str, shared_strings: &[String]) -> Vec<Vec<String>> {
+        between(sheet, "<row ", "</row>")
+            .into_iter()
+            .map(|row| {
+                between(row, "<c ", "</c>")
+                    .into_iter()
+                    .filter_map(|cell| {
+                        let (attributes, content) = cell.split_once('>')?;
+                        let value = *between(content, "<v>", "</v>").first()?;
+                        Some(if attributes.contains("t=\"s\"") {
+                            shared_strings[value.parse::<usize>().unwrap()].clone()
+                        } else {
+                            value.parse::<f64>().unwrap().to_string()
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn record(song_name: &str, difficulty: &str, score: i32, acc: f64, fc: bool) -> ProcessedRecord {
+        ProcessedRecord {
+            song_name: song_name.to_string(),
+            difficulty: difficulty.to_string(),
+            score,
+            acc,
+            fc,
+            ..ProcessedRecord::default()
+        }
+    }
+
+    #[test]
+    fn workbook_reads_back_with_the_records() {
+        let dir = std::env::temp_dir().join(format!("phi-save-data-xlsx-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Song.xlsx");
+        let mut options = OutputOptions::from_config(&Config::default());
+        options.columns = vec![Column::SongName, Column::Difficulty, Column::Score, Column::Acc, Column::Fc];
+        let records = [record("Song", "IN", 990000, 99.1, true), record("Song", "AT", 812345, 91.25, false)];
+        write_to_excel(&records, &path, &options).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let workbook = read_entry(&mut archive, "xl/workbook.xml");
+        let sheet_names = between(&workbook, "<sheet name=\"", "\"");
+        assert_eq!(sheet_names, ["Summary", "Records"]);
+        let shared_strings: Vec<_> = between(&read_entry(&mut archive, "xl/sharedStrings.xml"), "<si>", "</si>")
+            .into_iter()
+            .map(|item| item.split_once('>').unwrap().1.trim_end_matches("</t>").to_string())
+            .collect();
+        let sheet = read_entry(&mut archive, "xl/worksheets/sheet2.xml");
+        assert_eq!(
+            sheet_rows(&sheet, &shared_strings),
+            [
+                ["song_name", "difficulty", "score", "acc", "fc"],
+                ["Song", "IN", "990000", "99.1", "1"],
+                ["Song", "AT", "812345", "91.25", "0"],
+            ]
+        );
+        assert!(sheet.contains("ySplit=\"1\""), "{}", sheet);
+        assert!(sheet.contains("<autoFilter ref=\"A1:E3\"/>"), "{}", sheet);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
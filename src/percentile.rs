@@ -0,0 +1,85 @@
+//! `--with-percentiles`: where each record's acc stands among the players of
+//! its chart.
+
+use std::collections::HashMap;
+
+use crate::input::player_key;
+use crate::ProcessedRecord;
+
+/// Charts with fewer players leave the percentile empty, as it says little.
+const MIN_PLAYERS: usize = 5;
+
+/// Sets `acc_percentile` on each record: the percentage of players on the
+/// same song and difficulty, by their best acc there across snapshots, whose
+/// acc is below the
+/// record's, counting a tie as half below. Needs every record of the charts
+/// at once, so it runs after they are collected.
+pub fn annotate(mut records: Vec<&mut ProcessedRecord>) {
+    let mut best: HashMap<(String, String), HashMap<String, f64>> = HashMap::new();
+    for record in &records {
+        let players = best.entry((record.song_name.clone(), record.difficulty.clone())).or_default();
+        let acc = players.entry(player_key(&record.player_id).to_string()).or_insert(record.acc);
+        *acc = acc.max(record.acc);
+    }
+    let charts: HashMap<_, Vec<f64>> = best
+        .into_iter()
+        .map(|(chart, players)| {
+            let mut accs: Vec<_> = players.into_values().collect();
+            accs.sort_by(f64::total_cmp);
+            (chart, accs)
+        })
+        .collect();
+    for record in &mut records {
+        let accs = &charts[&(record.song_name.clone(), record.difficulty.clone())];
+        if accs.len() < MIN_PLAYERS {
+            continue;
+        }
+        let below = accs.partition_point(|&acc| acc < record.acc);
+        let tied = accs[below..].partition_point(|&acc| acc <= record.acc);
+        record.acc_percentile = Some((below as f64 + tied as f64 / 2.0) / accs.len() as f64 * 100.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(player_id: &str, acc: f64) -> ProcessedRecord {
+        ProcessedRecord {
+            player_id: player_id.to_string(),
+            song_name: "Song".to_string(),
+            difficulty: "IN".to_string(),
+            acc,
+            ..ProcessedRecord::default()
+        }
+    }
+
+    fn percentiles(records: &mut [ProcessedRecord]) -> Vec<Option<f64>> {
+        annotate(records.iter_mut().collect());
+        records.iter().map(|record| record.acc_percentile).collect()
+    }
+
+    #[test]
+    fn ties_count_as_half_below() {
+        // The older snapshot of p1 doesn't count as a player of its own.
+        let mut records = [
+            record("p1/2024-01-01", 80.0),
+            record("p1/2024-02-01", 90.0),
+            record("p2", 90.0),
+            record("p3", 95.0),
+            record("p4", 97.0),
+            record("p5", 99.0),
+        ];
+        // p1's older 80 is below every player's best.
+        assert_eq!(percentiles(&mut records), [Some(0.0), Some(20.0), Some(20.0), Some(50.0), Some(70.0), Some(90.0)]);
+    }
+
+    #[test]
+    fn charts_with_fewer_than_5_players_are_left_empty() {
+        let mut records = vec![record("p1", 90.0), record("p2", 90.0), record("p3", 95.0), record("p4", 97.0)];
+        assert_eq!(percentiles(&mut records), [None; 4]);
+        // A second snapshot of p4 is still 4 players.
+        records.push(record("p4/2024-01-01", 99.0));
+        assert_eq!(percentiles(&mut records), [None; 5]);
+    }
+}
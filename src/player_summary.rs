@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::best::computed_rks;
+use crate::config::Config;
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::{Difficulty, ProcessedSave, SaveInfo};
+
+pub const PLAYER_SUMMARY_FILE: &str = "player_summary.csv";
+
+/// Shown for summary fields that old saves lack.
+const MISSING: &str = "missing";
+
+const COUNTS: [&str; 3] = ["cleared", "full_combo", "phi"];
+
+/// The difficulties the game reports counts for.
+pub const COUNTED: [Difficulty; 4] = [Difficulty::Ez, Difficulty::Hd, Difficulty::In, Difficulty::At];
+
+/// Splits a challenge mode rank into its color (the hundreds digit) and level.
+fn decode_challenge_rank(rank: u32) -> (&'static str, u32) {
+    let color = match rank / 100 {
+        0 => "white",
+        1 => "green",
+        2 => "blue",
+        3 => "red",
+        4 => "gold",
+        5 => "rainbow",
+        _ => "unknown",
+    };
+    (color, rank % 100)
+}
+
+fn header() -> Vec<String> {
+    let mut header = vec![
+        "player_id".to_string(),
+        "ranking_score".to_string(),
+        "computed_rks".to_string(),
+        "game_version".to_string(),
+        "challenge_color".to_string(),
+        "challenge_level".to_string(),
+        "avatar".to_string(),
+        "save_version".to_string(),
+        "nickname".to_string(),
+    ];
+    for count in COUNTS {
+        for difficulty in COUNTED {
+            header.push(format!("{}_{}", difficulty.as_str(), count));
+        }
+    }
+    header
+}
+
+fn row(player_id: String, save_info: &SaveInfo, computed_rks: Option<f64>) -> Vec<String> {
+    let summary = &save_info.summary;
+    // Marked rather than defaulted, so a missing value isn't mistaken for zero.
+    let mut row = vec![
+        player_id,
+        summary.ranking_score.map_or_else(|| MISSING.to_string(), |score| score.to_string()),
+        computed_rks.map_or_else(String::new, |rks| rks.to_string()),
+        summary.game_version.map_or_else(|| MISSING.to_string(), |version| version.to_string()),
+    ];
+    match summary.challenge_mode_rank.map(decode_challenge_rank) {
+        Some((color, level)) => row.extend([color.to_string(), level.to_string()]),
+        None => row.extend([String::new(), String::new()]),
+    }
+    row.push(summary.avatar.clone().unwrap_or_default());
+    row.push(summary.save_version.map_or_else(String::new, |version| version.to_string()));
+    row.push(save_info.nickname.clone().unwrap_or_default());
+    for counts in [&summary.cleared, &summary.full_combo, &summary.phi] {
+        for i in 0..COUNTED.len() {
+            // Missing counts are left empty rather than reported as zero.
+            row.push(counts.as_ref().and_then(|counts| counts.get(i)).map_or_else(String::new, u32::to_string));
+        }
+    }
+    row
+}
+
+/// Writes one row per save with the totals the game itself reports, to check
+/// the exported records against. With --constants the rks is also computed
+/// from the records, next to the one stored in the save.
+pub fn write_player_summary(
+    saves: &[(&SaveFile, ProcessedSave)],
+    config: &Config,
+    output_path: &Path,
+) -> Result<()> {
+    write_atomically(output_path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        writer.write_record(header())?;
+        for (save_file, save) in saves {
+            let computed = config.constants.as_ref().map(|_| computed_rks(save.records.clone()));
+            writer.write_record(row(save_file.player_id.clone(), &save.save_info, computed))?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_color_and_level() {
+        assert_eq!(decode_challenge_rank(145), ("green", 45));
+        assert_eq!(decode_challenge_rank(300), ("red", 0));
+        assert_eq!(decode_challenge_rank(548), ("rainbow", 48));
+        assert_eq!(decode_challenge_rank(38), ("white", 38));
+    }
+
+    #[test]
+    fn rank_past_rainbow_is_unknown() {
+        assert_eq!(decode_challenge_rank(612), ("unknown", 12));
+    }
+}
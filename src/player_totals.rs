@@ -0,0 +1,164 @@
+//! `--player-summary-out`: totals of each save's exported records, the
+//! numbers otherwise spread over every song's file.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use xlsxwriter::Format;
+
+use crate::config::Config;
+use crate::filter::RecordFilter;
+use crate::input::{player_key, SaveFile};
+use crate::output::{create_workbook, write_atomically};
+use crate::{captured_at, Difficulty, ProcessedSave};
+
+pub const PLAYER_TOTALS_FILE: &str = "player_totals.csv";
+
+/// Workbook holding the totals when xlsx is among the formats.
+pub const SUMMARY_WORKBOOK_FILE: &str = "summary.xlsx";
+
+/// The difficulties whose acc is averaged in `mean_acc_in_at`.
+const HARD: [Difficulty; 2] = [Difficulty::In, Difficulty::At];
+
+struct Totals {
+    player_id: String,
+    snapshot: String,
+    captured_at: String,
+    ranking_score: Option<f64>,
+    game_version: Option<i32>,
+    records: usize,
+    per_difficulty: [usize; Difficulty::ALL.len()],
+    fc_count: usize,
+    ap_count: usize,
+    mean_acc_in_at: Option<f64>,
+}
+
+impl Totals {
+    fn header() -> Vec<String> {
+        let mut header: Vec<_> =
+            ["player_id", "snapshot", "ranking_score", "game_version", "records"].map(str::to_string).into();
+        header.extend(Difficulty::ALL.iter().map(|difficulty| format!("{}_records", difficulty.as_str())));
+        header.extend(["fc_count", "ap_count", "mean_acc_in_at"].map(str::to_string));
+        header
+    }
+
+    /// Cells as text, with missing values left empty.
+    fn row(&self) -> Vec<String> {
+        let mut row = vec![
+            self.player_id.clone(),
+            self.snapshot.clone(),
+            self.ranking_score.map_or_else(String::new, |score| score.to_string()),
+            self.game_version.map_or_else(String::new, |version| version.to_string()),
+            self.records.to_string(),
+        ];
+        row.extend(self.per_difficulty.iter().map(usize::to_string));
+        row.push(self.fc_count.to_string());
+        row.push(self.ap_count.to_string());
+        row.push(self.mean_acc_in_at.map_or_else(String::new, |acc| acc.to_string()));
+        row
+    }
+}
+
+fn totals(save_file: &SaveFile, save: &ProcessedSave, filter: &RecordFilter) -> Totals {
+    let save_info = &save.save_info;
+    let records: Vec<_> = save
+        .records
+        .iter()
+        .filter(|record| filter.matches(record) && filter.matches_song(&record.song_name))
+        .collect();
+    let mut per_difficulty = [0; Difficulty::ALL.len()];
+    for record in &records {
+        if let Some(count) = per_difficulty.get_mut(Difficulty::rank(&record.difficulty)) {
+            *count += 1;
+        }
+    }
+    let hard: Vec<_> = records
+        .iter()
+        .filter(|record| HARD.iter().any(|difficulty| record.difficulty == difficulty.as_str()))
+        .map(|record| record.acc)
+        .collect();
+    Totals {
+        player_id: save_file.player_id.clone(),
+        snapshot: save_file.snapshot.clone(),
+        captured_at: captured_at(save_file, save_info),
+        ranking_score: save_info.summary.ranking_score,
+        game_version: save_info.summary.game_version,
+        records: records.len(),
+        per_difficulty,
+        fc_count: records.iter().filter(|record| record.fc).count(),
+        ap_count: records.iter().filter(|record| record.ap).count(),
+        mean_acc_in_at: (!hard.is_empty()).then(|| hard.iter().sum::<f64>() / hard.len() as f64),
+    }
+}
+
+/// Keeps each player's most recently captured save, or the last one by
+/// file name when the capture times are the same or unknown.
+fn latest_only(all: Vec<Totals>) -> Vec<Totals> {
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    for (index, totals) in all.iter().enumerate() {
+        let entry = latest.entry(player_key(&totals.player_id).to_string()).or_insert(index);
+        if totals.captured_at >= all[*entry].captured_at {
+            *entry = index;
+        }
+    }
+    all.into_iter()
+        .enumerate()
+        .filter(|(index, totals)| latest[player_key(&totals.player_id)] == *index)
+        .map(|(_, totals)| totals)
+        .collect()
+}
+
+fn write_xlsx(all: &[Totals], path: &Path) -> Result<()> {
+    let workbook = create_workbook(path)?;
+    let mut sheet = workbook.add_worksheet(Some("Players"))?;
+    let mut header = Format::new();
+    header.set_bold();
+    for (col, name) in Totals::header().iter().enumerate() {
+        sheet.write_string(0, col as u16, name, Some(&header))?;
+    }
+    for (row, totals) in all.iter().enumerate() {
+        let row = row as u32 + 1;
+        for (col, cell) in totals.row().iter().enumerate() {
+            // The first two columns are text; the rest are numbers or empty.
+            match cell.parse::<f64>() {
+                Ok(number) if col >= 2 => sheet.write_number(row, col as u16, number, None)?,
+                _ => sheet.write_string(row, col as u16, cell, None)?,
+            }
+        }
+    }
+    sheet.set_column(0, 1, 20.0, None)?;
+    sheet.freeze_panes(1, 0);
+    workbook.close()?;
+    Ok(())
+}
+
+/// Writes one row per save with its record totals to `csv_path`, and the same
+/// rows to a "Players" sheet of `xlsx_path` if given. With
+/// --latest-snapshot only each player's latest save is kept.
+pub fn write_player_totals(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    config: &Config,
+    csv_path: &Path,
+    xlsx_path: Option<&Path>,
+) -> Result<()> {
+    let mut all: Vec<_> = saves.iter().map(|(save_file, save)| totals(save_file, save, filter)).collect();
+    if config.latest_snapshot {
+        all = latest_only(all);
+    }
+
+    write_atomically(csv_path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        writer.write_record(Totals::header())?;
+        for totals in &all {
+            writer.write_record(totals.row())?;
+        }
+        writer.flush()?;
+        Ok(())
+    })?;
+    if let Some(path) = xlsx_path {
+        write_atomically(path, |temp| write_xlsx(&all, temp))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! `--plots`: each chart's acc histogram drawn as a png.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::debug;
+use chrono::DateTime;
+use plotters::prelude::{
+    BitMapBackend, ChartBuilder, Circle, Color, Histogram, IntoDrawingArea, IntoSegmentedCoord, LineSeries,
+    SegmentValue, BLUE, WHITE,
+};
+
+use crate::config::Config;
+use crate::histogram::{histogram, AccHistogram};
+use crate::output::{split_by_difficulty, TempFile};
+use crate::run_summary::RunSummary;
+use crate::timeline::Point;
+use crate::{claim_output_file, ProcessedRecord};
+
+pub const PLOT_DIR: &str = "plots";
+
+fn draw(histogram: &AccHistogram, title: &str, path: &Path, size: (u32, u32)) -> Result<()> {
+    let root = BitMapBackend::new(path, size).into_drawing_area();
+    root.fill(&WHITE)?;
+    let max_count = histogram.rows.iter().map(|row| row.count).max().unwrap_or_default();
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(50)
+        .build_cartesian_2d((0..histogram.rows.len()).into_segmented(), 0..max_count + 1)?;
+    // Bins are labelled by their lower edge to keep the axis readable.
+    let label = |value: &SegmentValue<usize>| match value {
+        SegmentValue::Exact(index) | SegmentValue::CenterOf(index) => histogram.rows.get(*index).map_or_else(
+            String::new,
+            |row| if row.lower.is_empty() { row.label.clone() } else { row.lower.clone() },
+        ),
+        SegmentValue::Last => String::new(),
+    };
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(histogram.rows.len())
+        .x_label_formatter(&label)
+        .x_desc("acc")
+        .y_desc("players")
+        .draw()?;
+    chart.draw_series(
+        Histogram::vertical(&chart)
+            .style(BLUE.filled())
+            .margin(2)
+            .data(histogram.rows.iter().enumerate().map(|(index, row)| (index, row.count))),
+    )?;
+    root.present()?;
+    Ok(())
+}
+
+/// Draws rankingScore over time. The x axis is real time, so snapshots taken
+/// at irregular intervals are spaced accordingly.
+pub fn draw_timeline(points: &[Point], title: &str, path: &Path, size: (u32, u32)) -> Result<()> {
+    let root = BitMapBackend::new(path, size).into_drawing_area();
+    root.fill(&WHITE)?;
+    let data: Vec<_> = points.iter().map(|point| (point.timestamp as f64, point.ranking_score)).collect();
+    let (first, last) = (data[0].0, data[data.len() - 1].0);
+    let low = data.iter().map(|&(_, rks)| rks).fold(f64::INFINITY, f64::min);
+    let high = data.iter().map(|&(_, rks)| rks).fold(f64::NEG_INFINITY, f64::max);
+    // Some room around the line, and a visible range when the rks never changed.
+    let padding = ((high - low) * 0.1).max(0.05);
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(first..last, low - padding..high + padding)?;
+    let date = |timestamp: &f64| {
+        DateTime::from_timestamp(*timestamp as i64, 0)
+            .map_or_else(String::new, |time| time.format("%Y-%m-%d").to_string())
+    };
+    let rks = |rks: &f64| format!("{:.2}", rks);
+    chart
+        .configure_mesh()
+        .x_label_formatter(&date)
+        .y_label_formatter(&rks)
+        .x_desc("captured at")
+        .y_desc("rks")
+        .draw()?;
+    chart.draw_series(LineSeries::new(data.iter().copied(), &BLUE))?;
+    chart.draw_series(data.iter().map(|&point| Circle::new(point, 3, BLUE.filled())))?;
+    root.present()?;
+    Ok(())
+}
+
+/// Writes `plots/{stem}_{difficulty}.png` for each difficulty of the song
+/// with file stem `stem` played by at least --plot-min-players players,
+/// returning the files written, or that would be with `dry_run`.
+pub fn write_song_plots(
+    stem: &str,
+    records: &[ProcessedRecord],
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let plot_dir = config.output_dir().join(PLOT_DIR);
+    let mut written = Vec::new();
+    for (stem, records) in split_by_difficulty(stem, records) {
+        let histogram = histogram(&records, config.histogram_bin_width);
+        if histogram.players < config.plot_min_players {
+            debug!("{}: not plotted, only {} players", stem, histogram.players);
+            continue;
+        }
+        let path = plot_dir.join(format!("{}.png", stem));
+        if !claim_output_file(&path, config.existing, summary)? {
+            continue;
+        }
+        if !dry_run {
+            fs::create_dir_all(&plot_dir)?;
+            let (song_name, difficulty) = (&records[0].song_name, &records[0].difficulty);
+            let title = format!("{} {} ({} players)", song_name, difficulty, histogram.players);
+            let temp = TempFile::keeping_extension(&path);
+            draw(&histogram, &title, temp.path(), (config.plot_width, config.plot_height))?;
+            temp.persist()?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
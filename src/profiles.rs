@@ -0,0 +1,131 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::input::SaveFile;
+use crate::lenient::{json_path, Lenient};
+use crate::output::write_atomically;
+use crate::ProcessedSave;
+
+/// The `user` section of a save, shown on the player's profile in game.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct User {
+    show_player_id: Option<bool>,
+    self_intro: Option<String>,
+    avatar: Option<String>,
+    background: Option<String>,
+}
+
+/// The `settings` section of a save.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    chord_support: Option<bool>,
+    #[serde(rename = "fcAPIndicator")]
+    fc_ap_indicator: Option<bool>,
+    enable_hit_sound: Option<bool>,
+    low_resolution_mode: Option<bool>,
+    device_name: Option<String>,
+    bright: Option<f64>,
+    music_volume: Option<f64>,
+    effect_volume: Option<f64>,
+    hit_sound_volume: Option<f64>,
+    sound_offset: Option<f64>,
+    note_scale: Option<f64>,
+}
+
+/// One row of the profile export. Flat so that it also serializes as csv;
+/// fields a save lacks are left empty, or null in json.
+#[derive(Serialize)]
+struct Profile {
+    player_id: String,
+    nickname: Option<String>,
+    ranking_score: Option<f64>,
+    game_version: Option<i32>,
+    challenge_mode_rank: Option<u32>,
+    avatar: Option<String>,
+    show_player_id: Option<bool>,
+    self_intro: Option<String>,
+    background: Option<String>,
+    chord_support: Option<bool>,
+    fc_ap_indicator: Option<bool>,
+    enable_hit_sound: Option<bool>,
+    low_resolution_mode: Option<bool>,
+    device_name: Option<String>,
+    bright: Option<f64>,
+    music_volume: Option<f64>,
+    effect_volume: Option<f64>,
+    hit_sound_volume: Option<f64>,
+    sound_offset: Option<f64>,
+    note_scale: Option<f64>,
+}
+
+/// A section that failed to parse is reported and treated as missing.
+fn section<T: Default + Clone>(save_file: &SaveFile, name: &str, section: &Option<Lenient<T>>) -> T {
+    match section.as_ref().map(|section| &section.0) {
+        Some(Ok(section)) => section.clone(),
+        Some(Err(invalid)) => {
+            let path = json_path(name.to_string(), &invalid.path);
+            warn!("{}: ignoring {}: {}", save_file.location, path, invalid.message);
+            T::default()
+        }
+        None => T::default(),
+    }
+}
+
+fn profile(save_file: &SaveFile, save: &ProcessedSave) -> Profile {
+    let summary = save.save_info.summary.clone();
+    let user: User = section(save_file, "user", &save.user);
+    let settings: Settings = section(save_file, "settings", &save.settings);
+    Profile {
+        player_id: save_file.player_id.clone(),
+        nickname: save.save_info.nickname.clone(),
+        ranking_score: summary.ranking_score,
+        game_version: summary.game_version,
+        challenge_mode_rank: summary.challenge_mode_rank,
+        // The profile's own avatar is the current one; the summary's may be older.
+        avatar: user.avatar.or(summary.avatar),
+        show_player_id: user.show_player_id,
+        self_intro: user.self_intro,
+        background: user.background,
+        chord_support: settings.chord_support,
+        fc_ap_indicator: settings.fc_ap_indicator,
+        enable_hit_sound: settings.enable_hit_sound,
+        low_resolution_mode: settings.low_resolution_mode,
+        device_name: settings.device_name,
+        bright: settings.bright,
+        music_volume: settings.music_volume,
+        effect_volume: settings.effect_volume,
+        hit_sound_volume: settings.hit_sound_volume,
+        sound_offset: settings.sound_offset,
+        note_scale: settings.note_scale,
+    }
+}
+
+/// Writes one profile per save, as a json array if `output_path` ends in
+/// `.json` and as csv otherwise.
+pub fn write_profiles(saves: &[(&SaveFile, ProcessedSave)], output_path: &Path) -> Result<()> {
+    let profiles: Vec<_> = saves.iter().map(|(save_file, save)| profile(save_file, save)).collect();
+    if output_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json")) {
+        write_atomically(output_path, |temp| {
+            let mut writer = BufWriter::new(File::create(temp)?);
+            serde_json::to_writer_pretty(&mut writer, &profiles)?;
+            writer.flush()?;
+            Ok(())
+        })
+    } else {
+        write_atomically(output_path, |temp| {
+            let mut writer = csv::Writer::from_path(temp)?;
+            for profile in &profiles {
+                writer.serialize(profile)?;
+            }
+            writer.flush()?;
+            Ok(())
+        })
+    }
+}
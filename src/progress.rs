@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::input::SaveFile;
+use crate::lenient::json_path;
+use crate::output::write_atomically;
+use crate::ProcessedSave;
+
+pub const PROGRESS_FILE: &str = "progress.csv";
+
+/// The `gameProgress` section of a save. Every field is optional since older
+/// and minimal saves leave most of them out; unknown keys are ignored.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameProgress {
+    is_first_run: Option<bool>,
+    legacy_chapter_finished: Option<bool>,
+    already_show_collection_tip: Option<bool>,
+    #[serde(rename = "alreadyShowAutoUnlockINTip")]
+    already_show_auto_unlock_in_tip: Option<bool>,
+    completed: Option<String>,
+    song_update_info: Option<u32>,
+    challenge_mode_rank: Option<u32>,
+    /// Data in KiB, MiB, GiB, TiB and PiB.
+    money: Option<Vec<u32>>,
+    unlock_flag_of_spasmodic: Option<u32>,
+    unlock_flag_of_igallta: Option<u32>,
+    unlock_flag_of_rrharil: Option<u32>,
+    flag_of_song_record_key: Option<u32>,
+    random_version_unlocked: Option<u32>,
+    chapter8_unlock_begin: Option<bool>,
+    chapter8_unlock_second_phase: Option<bool>,
+    chapter8_passed: Option<bool>,
+    chapter8_song_unlocked: Option<u32>,
+}
+
+const MONEY_UNITS: [&str; 5] = ["kib", "mib", "gib", "tib", "pib"];
+
+fn header() -> Vec<String> {
+    let mut header = vec![
+        "player_id".to_string(),
+        "is_first_run".to_string(),
+        "legacy_chapter_finished".to_string(),
+        "already_show_collection_tip".to_string(),
+        "already_show_auto_unlock_in_tip".to_string(),
+        "completed".to_string(),
+        "song_update_info".to_string(),
+        "challenge_mode_rank".to_string(),
+    ];
+    header.extend(MONEY_UNITS.iter().map(|unit| format!("money_{}", unit)));
+    header.extend(
+        [
+            "unlock_flag_of_spasmodic",
+            "unlock_flag_of_igallta",
+            "unlock_flag_of_rrharil",
+            "flag_of_song_record_key",
+            "random_version_unlocked",
+            "chapter8_unlock_begin",
+            "chapter8_unlock_second_phase",
+            "chapter8_passed",
+            "chapter8_song_unlocked",
+        ]
+        .map(str::to_string),
+    );
+    header
+}
+
+/// Absent fields are left empty.
+fn cell<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or_else(String::new, T::to_string)
+}
+
+fn row(player_id: String, progress: &GameProgress) -> Vec<String> {
+    let mut row = vec![
+        player_id,
+        cell(&progress.is_first_run),
+        cell(&progress.legacy_chapter_finished),
+        cell(&progress.already_show_collection_tip),
+        cell(&progress.already_show_auto_unlock_in_tip),
+        cell(&progress.completed),
+        cell(&progress.song_update_info),
+        cell(&progress.challenge_mode_rank),
+    ];
+    for i in 0..MONEY_UNITS.len() {
+        row.push(cell(&progress.money.as_ref().and_then(|money| money.get(i))));
+    }
+    row.extend([
+        cell(&progress.unlock_flag_of_spasmodic),
+        cell(&progress.unlock_flag_of_igallta),
+        cell(&progress.unlock_flag_of_rrharil),
+        cell(&progress.flag_of_song_record_key),
+        cell(&progress.random_version_unlocked),
+        cell(&progress.chapter8_unlock_begin),
+        cell(&progress.chapter8_unlock_second_phase),
+        cell(&progress.chapter8_passed),
+        cell(&progress.chapter8_song_unlocked),
+    ]);
+    row
+}
+
+/// Writes one row per save with the fields of its gameProgress section.
+/// Saves without the section still get a row, with every field empty.
+pub fn write_progress(saves: &[(&SaveFile, ProcessedSave)], output_path: &Path) -> Result<()> {
+    write_atomically(output_path, |temp| {
+        let mut writer = csv::Writer::from_path(temp)?;
+        writer.write_record(header())?;
+        for (save_file, save) in saves {
+            let progress = match save.game_progress.as_ref().map(|progress| &progress.0) {
+                Some(Ok(progress)) => progress.clone(),
+                Some(Err(invalid)) => {
+                    let path = json_path("gameProgress".to_string(), &invalid.path);
+                    warn!("{}: ignoring {}: {}", save_file.location, path, invalid.message);
+                    GameProgress::default()
+                }
+                None => GameProgress::default(),
+            };
+            writer.write_record(row(save_file.player_id.clone(), &progress))?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
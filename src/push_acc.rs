@@ -0,0 +1,278 @@
+//! `--push-acc`: the acc each chart needs for a player's rks to go up.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::best::{ranked_plays, BEST_SLOTS, PHI_SLOTS};
+use crate::config::Config;
+use crate::constants::play_rks;
+use crate::filter::RecordFilter;
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::run_summary::RunSummary;
+use crate::{
+    claim_output_file, player_file_stem, records_by_player, split_revision, split_song_id, Difficulty, ProcessedRecord,
+    ProcessedSave,
+};
+
+pub const PUSH_ACC_DIR: &str = "push_acc";
+
+/// The smallest rise in rks worth pushing a chart for; the game shows rks
+/// with two decimals.
+const RKS_STEP: f64 = 0.01;
+
+/// What every other chart of the player adds to the rks, so that a chart's
+/// effect can be found without ranking all plays again.
+pub struct Others {
+    /// Sum of the best 27 play rks and the rks of the 27th, when there are 27.
+    best_sum: f64,
+    best_cutoff: Option<f64>,
+    /// Sum of the 3 highest All Perfect constants and the 3rd, likewise.
+    phi_sum: f64,
+    phi_cutoff: Option<f64>,
+}
+
+impl Others {
+    /// From the ranked plays of the player, leaving out the one at `skip`.
+    pub fn new(plays: &[ProcessedRecord], skip: Option<usize>) -> Others {
+        let others =
+            || plays.iter().enumerate().filter(move |&(index, _)| Some(index) != skip).map(|(_, play)| play);
+        let best: Vec<_> = others().filter_map(|play| play.play_rks).take(BEST_SLOTS).collect();
+        let mut phi: Vec<_> =
+            others().filter(|play| play.acc == 100.0).filter_map(|play| play.chart_constant).collect();
+        phi.sort_by(|a, b| b.total_cmp(a));
+        phi.truncate(PHI_SLOTS);
+        Others {
+            best_sum: best.iter().sum(),
+            best_cutoff: (best.len() == BEST_SLOTS).then(|| best[BEST_SLOTS - 1]),
+            phi_sum: phi.iter().sum(),
+            phi_cutoff: (phi.len() == PHI_SLOTS).then(|| phi[PHI_SLOTS - 1]),
+        }
+    }
+
+    /// The player's rks without the left out chart.
+    pub fn total(&self) -> f64 {
+        (self.best_sum + self.phi_sum) / (BEST_SLOTS + PHI_SLOTS) as f64
+    }
+
+    /// The player's rks with the chart of `constant` played at `acc`: the
+    /// play joins the best 27 if it beats the 27th, and at 100% also the phi
+    /// slots if its constant beats the 3rd.
+    pub fn rks(&self, constant: f64, acc: f64) -> f64 {
+        let push = |sum: f64, cutoff: Option<f64>, value: f64| match cutoff {
+            Some(cutoff) => sum + (value - cutoff).max(0.0),
+            None => sum + value,
+        };
+        let best = push(self.best_sum, self.best_cutoff, play_rks(constant, acc));
+        let phi = if acc == 100.0 { push(self.phi_sum, self.phi_cutoff, constant) } else { self.phi_sum };
+        (best + phi) / (BEST_SLOTS + PHI_SLOTS) as f64
+    }
+}
+
+/// The lowest acc above `acc`, rounded up to two decimals, at which the chart
+/// raises the rks from `rks` by [`RKS_STEP`]; None when even 100% doesn't.
+fn required_acc(others: &Others, constant: f64, acc: f64, rks: f64) -> Option<f64> {
+    let target = rks + RKS_STEP;
+    if acc >= 100.0 || others.rks(constant, 100.0) < target {
+        return None;
+    }
+    // The rks only grows with the acc, so bisect between a miss and a hit.
+    let (mut miss, mut hit) = (acc, 100.0);
+    for _ in 0..64 {
+        let middle = (miss + hit) / 2.0;
+        if others.rks(constant, middle) >= target {
+            hit = middle;
+        } else {
+            miss = middle;
+        }
+    }
+    Some(((hit * 100.0).ceil() / 100.0).min(100.0))
+}
+
+#[derive(Serialize)]
+struct Row {
+    song: String,
+    difficulty: String,
+    constant: f64,
+    /// Empty for charts not played yet.
+    acc: Option<f64>,
+    play_rks: Option<f64>,
+    required_acc: Option<f64>,
+    /// `push`, `unplayed`, or `maxed` when no acc gets the rks up.
+    status: &'static str,
+}
+
+impl Row {
+    /// Charts closest to going up first, the ones that can't last.
+    fn order(&self) -> (u8, f64) {
+        match (self.required_acc, self.acc) {
+            (Some(required), Some(acc)) => (0, required - acc),
+            (Some(required), None) => (1, required),
+            (None, _) => (2, -self.constant),
+        }
+    }
+}
+
+/// The rows for one player: every played chart with a constant, and the
+/// unplayed ones of at least `unplayed_constant` when given.
+fn rows(
+    records: Vec<ProcessedRecord>,
+    config: &Config,
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+) -> Vec<Row> {
+    let plays = ranked_plays(records);
+    let rks = Others::new(&plays, None).total();
+    let mut rows = Vec::new();
+    for (index, play) in plays.iter().enumerate() {
+        let (Some(constant), Some(play_rks)) = (play.chart_constant, play.play_rks) else {
+            continue;
+        };
+        let required_acc = required_acc(&Others::new(&plays, Some(index)), constant, play.acc, rks);
+        rows.push(Row {
+            song: play.song_name.clone(),
+            difficulty: play.difficulty.clone(),
+            constant,
+            acc: Some(play.acc),
+            play_rks: Some(play_rks),
+            required_acc,
+            status: if required_acc.is_some() { "push" } else { "maxed" },
+        });
+    }
+    if let Some(min_constant) = config.push_acc_unplayed {
+        let played: HashSet<_> =
+            plays.iter().map(|play| (split_revision(&play.song_id).0, play.difficulty.as_str())).collect();
+        let others = Others::new(&plays, None);
+        for (song_id, difficulty, constant) in config.chart_constants.charts() {
+            if constant < min_constant || played.contains(&(song_id, difficulty)) {
+                continue;
+            }
+            let (name, _, _) = split_song_id(song_id);
+            let song = aliases.get(&name).cloned().unwrap_or(name);
+            if !filter.matches_song(&song) {
+                continue;
+            }
+            let required_acc = required_acc(&others, constant, 0.0, rks);
+            rows.push(Row {
+                song,
+                difficulty: difficulty.to_string(),
+                constant,
+                acc: None,
+                play_rks: None,
+                required_acc,
+                status: if required_acc.is_some() { "unplayed" } else { "maxed" },
+            });
+        }
+    }
+    rows.sort_by(|a, b| {
+        let (a_order, b_order) = (a.order(), b.order());
+        a_order
+            .0
+            .cmp(&b_order.0)
+            .then_with(|| a_order.1.total_cmp(&b_order.1))
+            .then_with(|| a.song.cmp(&b.song))
+            .then_with(|| Difficulty::rank(&a.difficulty).cmp(&Difficulty::rank(&b.difficulty)))
+    });
+    rows
+}
+
+/// Writes `push_acc/{player}.csv` for every player with at least one play of
+/// a chart with a known constant. Returns the files written, or that would be
+/// with `dry_run`.
+pub fn write_push_acc(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let push_acc_dir = config.output_dir().join(PUSH_ACC_DIR);
+    let mut written = Vec::new();
+    for (player, records) in records_by_player(saves, filter, aliases) {
+        if !records.iter().any(|record| record.play_rks.is_some()) {
+            continue;
+        }
+        let path = push_acc_dir.join(format!("{}.csv", player_file_stem(&player)));
+        if !claim_output_file(&path, config.existing, summary)? {
+            continue;
+        }
+        if !dry_run {
+            fs::create_dir_all(&push_acc_dir)?;
+            write_atomically(&path, |temp| {
+                let mut writer = csv::Writer::from_path(temp)?;
+                for row in rows(records, config, filter, aliases) {
+                    writer.serialize(row)?;
+                }
+                writer.flush()?;
+                Ok(())
+            })?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(song_id: &str, acc: f64, constant: f64) -> ProcessedRecord {
+        ProcessedRecord {
+            song_id: song_id.to_string(),
+            song_name: song_id.to_string(),
+            difficulty: "IN".to_string(),
+            acc,
+            chart_constant: Some(constant),
+            play_rks: Some(play_rks(constant, acc)),
+            ..ProcessedRecord::default()
+        }
+    }
+
+    /// The acc `song_id` needs, among the plays `records`.
+    fn required(records: Vec<ProcessedRecord>, song_id: &str) -> Option<f64> {
+        let plays = ranked_plays(records);
+        let rks = Others::new(&plays, None).total();
+        let index = plays.iter().position(|play| play.song_id == song_id).unwrap();
+        let play = &plays[index];
+        required_acc(&Others::new(&plays, Some(index)), play.chart_constant.unwrap(), play.acc, rks)
+    }
+
+    #[test]
+    fn with_free_slots_the_play_rks_only_has_to_rise() {
+        // 15 * (35 / 45)^2 = 9.0741 now, so rks 0.3025; 0.01 more needs a play
+        // rks of 9.3741, at 55 + 45 * sqrt(9.3741 / 15) = 90.574.
+        assert_eq!(required(vec![play("a", 90.0, 15.0)], "a"), Some(90.58));
+    }
+
+    #[test]
+    fn below_the_27th_the_play_has_to_pass_it() {
+        // 27 plays of 12 make rks 324 / 30 = 10.8; 10.81 needs the chart to
+        // beat the 27th by 0.3, at 55 + 45 * sqrt(12.3 / 14) = 97.179.
+        let mut records: Vec<_> = (0..27).map(|index| play(&format!("b{}", index), 99.0, 12.0)).collect();
+        for record in &mut records {
+            record.play_rks = Some(12.0);
+        }
+        records.push(play("a", 80.0, 14.0));
+        assert_eq!(required(records, "a"), Some(97.18));
+    }
+
+    #[test]
+    fn an_ap_can_be_what_it_takes() {
+        // At 100% the chart also takes a phi slot: 15 more in the sum, where
+        // the best 27 alone can't give the 0.3 needed.
+        assert_eq!(required(vec![play("a", 99.9, 15.0)], "a"), Some(100.0));
+    }
+
+    #[test]
+    fn chart_below_every_cutoff_is_maxed() {
+        let mut records: Vec<_> = (0..27).map(|index| play(&format!("b{}", index), 100.0, 16.0)).collect();
+        records.push(play("a", 95.0, 10.0));
+        assert_eq!(required(records, "a"), None);
+        assert_eq!(required(vec![play("a", 100.0, 15.0)], "a"), None);
+    }
+}
@@ -0,0 +1,89 @@
+use log::{error, info, warn};
+
+use crate::failures::{self, SaveFailure};
+use crate::timings::Timings;
+
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub saves_found: usize,
+    /// Directories looked in for saves, below the input directories.
+    pub dirs_scanned: usize,
+    pub failed_saves: Vec<SaveFailure>,
+    pub players_not_allowed: usize,
+    pub players_denied: usize,
+    pub songs_written: usize,
+    pub players_written: usize,
+    pub files_skipped: usize,
+    /// Per-song files left as they were, as their records didn't change.
+    pub files_unchanged: usize,
+    /// Output files that could not be written.
+    pub files_failed: usize,
+    /// Errors the run went on past with `--error-policy continue`.
+    pub continued_errors: usize,
+    pub unknown_difficulty_entries: usize,
+    pub malformed_entries: usize,
+    /// Records with out-of-range values, per save.
+    pub invalid_records: Vec<(String, usize)>,
+    /// Records exported without a chart constant from --constants.
+    pub missing_constant_records: usize,
+    pub missing_constant_charts: usize,
+    pub timings: Timings,
+}
+
+impl RunSummary {
+    pub fn print(&self) {
+        if self.players_written > 0 {
+            info!(
+                "Wrote {} player files from {} saves",
+                self.players_written, self.saves_found
+            );
+        } else {
+            info!(
+                "Wrote {} songs from {} saves",
+                self.songs_written, self.saves_found
+            );
+        }
+        if !self.failed_saves.is_empty() {
+            failures::print(&self.failed_saves);
+        }
+        if self.players_not_allowed > 0 {
+            info!("Skipped {} players not in --players", self.players_not_allowed);
+        }
+        if self.players_denied > 0 {
+            info!("Skipped {} players in --exclude-players", self.players_denied);
+        }
+        if self.files_skipped > 0 {
+            info!("Left {} existing files untouched", self.files_skipped);
+        }
+        if self.files_unchanged > 0 {
+            info!("Left {} files of unchanged songs as they were", self.files_unchanged);
+        }
+        if self.files_failed > 0 {
+            error!("Failed to write {} files", self.files_failed);
+        }
+        if self.malformed_entries > 0 {
+            warn!("Skipped {} malformed score entries", self.malformed_entries);
+        }
+        for (player, count) in &self.invalid_records {
+            warn!("{}: {} records with out-of-range score or acc", player, count);
+        }
+        if self.missing_constant_records > 0 {
+            warn!(
+                "{} records on {} charts have no chart constant (use -v to list the charts)",
+                self.missing_constant_records, self.missing_constant_charts
+            );
+        }
+        if self.unknown_difficulty_entries > 0 {
+            warn!(
+                "{} score entries were beyond the known difficulties (use --strict to fail instead)",
+                self.unknown_difficulty_entries
+            );
+        }
+        if self.continued_errors > 0 {
+            warn!(
+                "Went on past {} errors (use --error-policy fail-fast to stop at the first)",
+                self.continued_errors
+            );
+        }
+    }
+}
@@ -0,0 +1,11 @@
+//! Song-level helpers over records grouped by song name.
+
+use std::collections::HashMap;
+
+/// The names of all songs in `songs`, sorted so that every run writes them in
+/// the same order.
+pub fn get_all_song_names<R>(songs: &HashMap<String, Vec<R>>) -> Vec<String> {
+    let mut names: Vec<_> = songs.keys().cloned().collect();
+    names.sort();
+    names
+}
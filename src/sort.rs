@@ -0,0 +1,129 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Difficulty, ProcessedRecord};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Score,
+    Acc,
+    SongName,
+    Difficulty,
+    RankingScore,
+    ScoreDeficit,
+}
+
+impl SortField {
+    const ALL: [SortField; 6] = [
+        SortField::Score,
+        SortField::Acc,
+        SortField::SongName,
+        SortField::Difficulty,
+        SortField::RankingScore,
+        SortField::ScoreDeficit,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            SortField::Score => "score",
+            SortField::Acc => "acc",
+            SortField::SongName => "song_name",
+            SortField::Difficulty => "difficulty",
+            SortField::RankingScore => "ranking_score",
+            SortField::ScoreDeficit => "score_deficit",
+        }
+    }
+
+    fn compare(self, a: &ProcessedRecord, b: &ProcessedRecord) -> Ordering {
+        match self {
+            SortField::Score => a.score.cmp(&b.score),
+            SortField::Acc => a.acc.total_cmp(&b.acc),
+            SortField::SongName => a.song_name.cmp(&b.song_name),
+            SortField::Difficulty => {
+                Difficulty::rank(&a.difficulty).cmp(&Difficulty::rank(&b.difficulty))
+            }
+            SortField::RankingScore => a.ranking_score.total_cmp(&b.ranking_score),
+            SortField::ScoreDeficit => a.score_deficit.cmp(&b.score_deficit),
+        }
+    }
+}
+
+/// One `--sort-by` entry such as `acc:desc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SortKey {
+    field: SortField,
+    descending: bool,
+}
+
+impl SortKey {
+    fn compare(self, a: &ProcessedRecord, b: &ProcessedRecord) -> Ordering {
+        let ordering = self.field.compare(a, b);
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, direction) = s.split_once(':').unwrap_or((s, "asc"));
+        let field = SortField::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+            .ok_or_else(|| {
+                let valid: Vec<_> = SortField::ALL.iter().map(|f| f.name()).collect();
+                format!("unknown sort key `{}`, expected one of: {}", name, valid.join(", "))
+            })?;
+        let descending = match direction {
+            "asc" => false,
+            "desc" => true,
+            other => return Err(format!("unknown sort direction `{}`, expected asc or desc", other)),
+        };
+        Ok(SortKey { field, descending })
+    }
+}
+
+impl TryFrom<String> for SortKey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.field.name(), if self.descending { "desc" } else { "asc" })
+    }
+}
+
+impl From<SortKey> for String {
+    fn from(key: SortKey) -> String {
+        key.to_string()
+    }
+}
+
+const DEFAULT_ORDER: [SortKey; 3] = [
+    SortKey { field: SortField::SongName, descending: false },
+    SortKey { field: SortField::Difficulty, descending: false },
+    SortKey { field: SortField::Score, descending: true },
+];
+
+/// Sorts by `keys`, breaking remaining ties with the default song/difficulty/score order.
+pub fn sort_records(records: &mut [ProcessedRecord], keys: &[SortKey]) {
+    records.sort_by(|a, b| {
+        keys.iter()
+            .chain(DEFAULT_ORDER.iter())
+            .map(|key| key.compare(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
@@ -0,0 +1,70 @@
+//! `--stats`: per-chart aggregates of the exported records.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::input::player_key;
+use crate::output::write_atomically;
+use crate::{Difficulty, ProcessedRecord};
+
+pub const STATS_FILE: &str = "stats.csv";
+
+#[derive(Serialize)]
+struct ChartStats {
+    song_name: String,
+    difficulty: String,
+    records: usize,
+    /// Distinct player directories, so several snapshots of one player count once.
+    players: usize,
+    mean_acc: f64,
+    max_score: i32,
+    min_score: i32,
+    fc_count: usize,
+    ap_count: usize,
+}
+
+/// Rows for every song added, in the order the songs were added.
+#[derive(Default)]
+pub struct SongStats {
+    rows: Vec<ChartStats>,
+}
+
+impl SongStats {
+    /// Adds a row per difficulty of `song_name` that has records, in
+    /// difficulty order.
+    pub fn add_song(&mut self, song_name: &str, records: &[ProcessedRecord]) {
+        let mut by_difficulty: BTreeMap<(usize, &str), Vec<&ProcessedRecord>> = BTreeMap::new();
+        for record in records {
+            let difficulty = record.difficulty.as_str();
+            by_difficulty.entry((Difficulty::rank(difficulty), difficulty)).or_default().push(record);
+        }
+        for ((_, difficulty), records) in by_difficulty {
+            let players: HashSet<_> = records.iter().map(|record| player_key(&record.player_id)).collect();
+            self.rows.push(ChartStats {
+                song_name: song_name.to_string(),
+                difficulty: difficulty.to_string(),
+                records: records.len(),
+                players: players.len(),
+                mean_acc: records.iter().map(|record| record.acc).sum::<f64>() / records.len() as f64,
+                max_score: records.iter().map(|record| record.score).max().unwrap_or_default(),
+                min_score: records.iter().map(|record| record.score).min().unwrap_or_default(),
+                fc_count: records.iter().filter(|record| record.fc).count(),
+                ap_count: records.iter().filter(|record| record.ap).count(),
+            });
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        write_atomically(path, |temp| {
+            let mut writer = csv::Writer::from_path(temp)?;
+            for row in &self.rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,148 @@
+//! `--suggestions`: the charts where a little more acc would raise a
+//! player's rks the most.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::best::ranked_plays;
+use crate::config::Config;
+use crate::filter::RecordFilter;
+use crate::input::SaveFile;
+use crate::output::{escape_markdown, write_atomically};
+use crate::push_acc::Others;
+use crate::run_summary::RunSummary;
+use crate::{claim_output_file, player_file_stem, records_by_player, ProcessedRecord, ProcessedSave};
+
+pub const SUGGESTIONS_DIR: &str = "suggestions";
+
+/// Charts listed per player.
+const SUGGESTIONS: usize = 20;
+
+struct Suggestion {
+    song: String,
+    difficulty: String,
+    constant: f64,
+    acc: f64,
+    target_acc: f64,
+    rks_gain: f64,
+}
+
+/// The charts below 100% whose rks would go up the most with `step` more
+/// acc, capped at 100%, from the largest gain down.
+fn suggestions(records: Vec<ProcessedRecord>, step: f64) -> (f64, Vec<Suggestion>) {
+    let plays = ranked_plays(records);
+    let rks = Others::new(&plays, None).total();
+    let mut suggestions = Vec::new();
+    for (index, play) in plays.iter().enumerate() {
+        let Some(constant) = play.chart_constant.filter(|_| play.acc < 100.0) else {
+            continue;
+        };
+        let target_acc = (play.acc + step).min(100.0);
+        let rks_gain = Others::new(&plays, Some(index)).rks(constant, target_acc) - rks;
+        if rks_gain > 0.0 {
+            suggestions.push(Suggestion {
+                song: play.song_name.clone(),
+                difficulty: play.difficulty.clone(),
+                constant,
+                acc: play.acc,
+                target_acc,
+                rks_gain,
+            });
+        }
+    }
+    suggestions.sort_by(|a, b| b.rks_gain.total_cmp(&a.rks_gain).then_with(|| a.song.cmp(&b.song)));
+    suggestions.truncate(SUGGESTIONS);
+    (rks, suggestions)
+}
+
+/// Writes `suggestions/{player}.md` for every player with at least one play
+/// of a chart with a known constant. Returns the files written, or that
+/// would be with `dry_run`.
+pub fn write_suggestions(
+    saves: &[(&SaveFile, ProcessedSave)],
+    filter: &RecordFilter,
+    aliases: &HashMap<String, String>,
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let suggestions_dir = config.output_dir().join(SUGGESTIONS_DIR);
+    let mut written = Vec::new();
+    for (player, records) in records_by_player(saves, filter, aliases) {
+        if !records.iter().any(|record| record.play_rks.is_some()) {
+            continue;
+        }
+        let path = suggestions_dir.join(format!("{}.md", player_file_stem(&player)));
+        if !claim_output_file(&path, config.existing, summary)? {
+            continue;
+        }
+        if !dry_run {
+            fs::create_dir_all(&suggestions_dir)?;
+            let (rks, suggestions) = suggestions(records, config.suggestion_step);
+            write_atomically(&path, |temp| {
+                let mut writer = BufWriter::new(File::create(temp)?);
+                writeln!(writer, "# {}\n", escape_markdown(&player))?;
+                writeln!(
+                    writer,
+                    "rks {:.4}; each chart is projected at {:+}% acc over the current, capped at 100%.\n",
+                    rks, config.suggestion_step
+                )?;
+                writeln!(writer, "| song | difficulty | constant | acc | target_acc | rks_gain |")?;
+                writeln!(writer, "| --- | --- | --- | --- | --- | --- |")?;
+                for suggestion in suggestions {
+                    writeln!(
+                        writer,
+                        "| {} | {} | {} | {:.2} | {:.2} | {:+.4} |",
+                        escape_markdown(&suggestion.song),
+                        suggestion.difficulty,
+                        suggestion.constant,
+                        suggestion.acc,
+                        suggestion.target_acc,
+                        suggestion.rks_gain
+                    )?;
+                }
+                writer.flush()?;
+                Ok(())
+            })?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::play_rks;
+
+    fn play(song_name: &str, constant: f64, acc: f64) -> ProcessedRecord {
+        ProcessedRecord {
+            song_id: song_name.to_string(),
+            song_name: song_name.to_string(),
+            difficulty: "IN".to_string(),
+            acc,
+            chart_constant: Some(constant),
+            play_rks: Some(play_rks(constant, acc)),
+            ..ProcessedRecord::default()
+        }
+    }
+
+    #[test]
+    fn largest_gain_first_capped_at_100() {
+        let records = vec![play("B", 16.0, 90.0), play("A", 15.0, 99.5), play("C", 12.0, 100.0), play("D", 14.0, 60.0)];
+        let (rks, suggestions) = suggestions(records, 1.0);
+        // C is already at 100%, and D at 61% would still be worth nothing.
+        let listed: Vec<_> = suggestions.iter().map(|s| (s.song.as_str(), s.target_acc)).collect();
+        assert_eq!(listed, [("A", 100.0), ("B", 91.0)]);
+        // 15 * (44.5 / 45)^2 + 16 * (35 / 45)^2 + 12 in the best slots and 12 in a phi slot.
+        assert!((rks - (14.668_518_5 + 9.679_012_3 + 24.0) / 30.0).abs() < 1e-6);
+        // As an AP, A fills a phi slot as well: 15 + 15 instead of 14.6685.
+        assert!((suggestions[0].rks_gain - (30.0 - 14.668_518_5) / 30.0).abs() < 1e-6);
+        // 16 * ((36 / 45)^2 - (35 / 45)^2) = 0.5610.
+        assert!((suggestions[1].rks_gain - 0.560_987_7 / 30.0).abs() < 1e-6);
+    }
+}
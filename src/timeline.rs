@@ -0,0 +1,106 @@
+//! `--rks-timeline`: each player's rankingScore over their snapshots.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chrono::DateTime;
+use log::debug;
+
+use crate::config::Config;
+use crate::input::SaveFile;
+use crate::output::write_atomically;
+use crate::run_summary::RunSummary;
+use crate::{captured_at, claim_output_file, player_file_stem, ProcessedSave};
+
+pub const TIMELINE_DIR: &str = "timeline";
+
+/// One snapshot of a player.
+pub struct Point {
+    pub captured_at: String,
+    /// Seconds since the Unix epoch, placing snapshots by their real time.
+    pub timestamp: i64,
+    pub snapshot: String,
+    pub ranking_score: f64,
+}
+
+/// Each player's snapshots in time order, one per capture time; snapshots
+/// without a capture time or a rankingScore are left out.
+fn timelines(saves: &[(&SaveFile, ProcessedSave)]) -> BTreeMap<String, Vec<Point>> {
+    let mut timelines: BTreeMap<String, BTreeMap<i64, Point>> = BTreeMap::new();
+    for (save_file, save) in saves {
+        let save_info = &save.save_info;
+        let captured_at = captured_at(save_file, save_info);
+        let (Some(ranking_score), Ok(time)) =
+            (save_info.summary.ranking_score, DateTime::parse_from_rfc3339(&captured_at))
+        else {
+            debug!("{}: no capture time or rankingScore, left out of the timeline", save_file.location);
+            continue;
+        };
+        let point = Point {
+            captured_at,
+            timestamp: time.timestamp(),
+            snapshot: format!("{}/{}", save_file.player_id, save_file.snapshot),
+            ranking_score,
+        };
+        // Copies of one snapshot have the same time; the first one is kept.
+        timelines.entry(save_file.player().to_string()).or_default().entry(point.timestamp).or_insert(point);
+    }
+    timelines.into_iter().map(|(player, points)| (player, points.into_values().collect())).collect()
+}
+
+/// Writes `timeline/{player}.csv`, and with the plots feature a line chart
+/// `timeline/{player}.png`, for each player with at least two snapshots.
+/// Returns the files written, or that would be with `dry_run`.
+pub fn write_timelines(
+    saves: &[(&SaveFile, ProcessedSave)],
+    config: &Config,
+    dry_run: bool,
+    summary: &mut RunSummary,
+) -> Result<Vec<PathBuf>> {
+    let timeline_dir = config.output_dir().join(TIMELINE_DIR);
+    let mut written = Vec::new();
+    for (player, points) in timelines(saves) {
+        if points.len() < 2 {
+            continue;
+        }
+        let stem = player_file_stem(&player);
+        let csv_path = timeline_dir.join(format!("{}.csv", stem));
+        if claim_output_file(&csv_path, config.existing, summary)? {
+            if !dry_run {
+                fs::create_dir_all(&timeline_dir)?;
+                write_atomically(&csv_path, |temp| {
+                    let mut writer = csv::Writer::from_path(temp)?;
+                    writer.write_record(["captured_at", "snapshot", "ranking_score"])?;
+                    for point in &points {
+                        writer.write_record([
+                            point.captured_at.clone(),
+                            point.snapshot.clone(),
+                            point.ranking_score.to_string(),
+                        ])?;
+                    }
+                    writer.flush()?;
+                    Ok(())
+                })?;
+            }
+            written.push(csv_path);
+        }
+        #[cfg(feature = "plots")]
+        {
+            let png_path = timeline_dir.join(format!("{}.png", stem));
+            if claim_output_file(&png_path, config.existing, summary)? {
+                if !dry_run {
+                    fs::create_dir_all(&timeline_dir)?;
+                    let title = format!("{} ({} snapshots)", player, points.len());
+                    let temp = crate::output::TempFile::keeping_extension(&png_path);
+                    let size = (config.plot_width, config.plot_height);
+                    crate::plots::draw_timeline(&points, &title, temp.path(), size)?;
+                    temp.persist()?;
+                }
+                written.push(png_path);
+            }
+        }
+    }
+    Ok(written)
+}
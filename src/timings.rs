@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::info;
+use serde::Serialize;
+
+use crate::output::write_atomically;
+
+pub const TIMINGS_FILE: &str = "timings.json";
+
+/// Where a run spent its time, for --timings.
+#[derive(Debug)]
+pub struct Timings {
+    started: Instant,
+    pub scan: Duration,
+    /// Wall time of reading the saves, on however many threads.
+    pub parse: Duration,
+    /// How long each read of a save took, cached or not.
+    saves: Vec<Duration>,
+    pub group: Duration,
+    /// Time spent writing per-song and combined files, by extension, summed
+    /// over the writer threads.
+    write: BTreeMap<&'static str, Duration>,
+    /// Records read from the saves, before any filter.
+    pub records: usize,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings {
+            started: Instant::now(),
+            scan: Duration::ZERO,
+            parse: Duration::ZERO,
+            saves: Vec::new(),
+            group: Duration::ZERO,
+            write: BTreeMap::new(),
+            records: 0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    version: &'static str,
+    scan_seconds: f64,
+    parse_seconds: f64,
+    save_reads: usize,
+    save_max_seconds: f64,
+    save_mean_seconds: f64,
+    group_seconds: f64,
+    write_seconds: BTreeMap<&'a str, f64>,
+    total_seconds: f64,
+    records: usize,
+    records_per_second: f64,
+}
+
+impl Timings {
+    pub fn add_save(&mut self, elapsed: Duration) {
+        self.saves.push(elapsed);
+    }
+
+    pub fn add_write(&mut self, extension: &'static str, elapsed: Duration) {
+        *self.write.entry(extension).or_default() += elapsed;
+    }
+
+    fn report(&self) -> Report<'_> {
+        let total = self.started.elapsed().as_secs_f64();
+        let max = self.saves.iter().max().copied().unwrap_or_default();
+        let mean = if self.saves.is_empty() {
+            0.0
+        } else {
+            self.saves.iter().sum::<Duration>().as_secs_f64() / self.saves.len() as f64
+        };
+        Report {
+            version: env!("CARGO_PKG_VERSION"),
+            scan_seconds: self.scan.as_secs_f64(),
+            parse_seconds: self.parse.as_secs_f64(),
+            save_reads: self.saves.len(),
+            save_max_seconds: max.as_secs_f64(),
+            save_mean_seconds: mean,
+            group_seconds: self.group.as_secs_f64(),
+            write_seconds: self.write.iter().map(|(&extension, elapsed)| (extension, elapsed.as_secs_f64())).collect(),
+            total_seconds: total,
+            records: self.records,
+            records_per_second: if total > 0.0 { self.records as f64 / total } else { 0.0 },
+        }
+    }
+
+    pub fn print(&self) {
+        let report = self.report();
+        info!("Timings:");
+        info!("  {:<14} {:>9.3}s", "scanning", report.scan_seconds);
+        info!(
+            "  {:<14} {:>9.3}s  ({} reads, max {:.3}s, mean {:.3}s)",
+            "parsing", report.parse_seconds, report.save_reads, report.save_max_seconds, report.save_mean_seconds
+        );
+        info!("  {:<14} {:>9.3}s", "grouping", report.group_seconds);
+        for (extension, seconds) in &report.write_seconds {
+            info!("  {:<14} {:>9.3}s", format!("{} files", extension), seconds);
+        }
+        info!(
+            "  {:<14} {:>9.3}s  ({} records, {:.0} records/s)",
+            "total", report.total_seconds, report.records, report.records_per_second
+        );
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        write_atomically(path, |temp| {
+            let mut writer = BufWriter::new(File::create(temp)?);
+            serde_json::to_writer_pretty(&mut writer, &self.report())?;
+            writer.flush()?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,102 @@
+""" 生成测试用的存档文件，在本目录下运行：python3 make_fixtures.py
+
+cloud.save 不是游戏导出的真实存档，而是按公开的云存档格式另行构造的：与 src/decode.rs 的代码
+无关，并带有真实存档才有的特征（deflate 压缩、gameRecord 以外的条目、歌曲数超过 127）。
+里面没有玩家数据，user 等条目只是占位。
+
+save_*.json 是同一份 save.json 的 UTF-8（带 BOM）、UTF-16LE 和 UTF-16BE 编码。
+"""
+import base64
+import struct
+import zipfile
+
+from cryptography.hazmat.primitives import padding
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+KEY = base64.b64decode("6Jaa0qVAJZuXkZCLiOa/Ax5tIZVu+taKUN1V1nqwkks=")
+IV = base64.b64decode("Kk/wisgNYwcAV8WVGMgyUw==")
+
+
+def encrypt(version, plain):
+    """ 版本号加 PKCS7 填充后加密的数据 """
+    padder = padding.PKCS7(128).padder()
+    padded = padder.update(plain) + padder.finalize()
+    encryptor = Cipher(algorithms.AES(KEY), modes.CBC(IV)).encryptor()
+    return bytes([version]) + encryptor.update(padded) + encryptor.finalize()
+
+
+def varint(value):
+    """ 小端 base-128 整数 """
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def string(text):
+    data = text.encode("utf-8")
+    return varint(len(data)) + data
+
+
+def song(song_id, levels):
+    """ levels: {难度序号: (score, acc, fc)} """
+    played = sum(1 << level for level in levels)
+    full_combo = sum(1 << level for level, (_, _, fc) in levels.items() if fc)
+    body = bytes([played, full_combo])
+    for level in sorted(levels):
+        score, acc, _ = levels[level]
+        body += struct.pack("<if", score, acc)
+    return string(song_id) + varint(len(body)) + body
+
+
+SONGS = [
+    song("Glaciaxion.SunsetRay.0", {0: (1000000, 100.0, True), 1: (995112, 99.63, True), 2: (962340, 97.41, False)}),
+    song("Rrhar'il.TeamGrimoire.0", {2: (883201, 94.12, False), 3: (712004, 86.5, False)}),
+    song("SpasModic(Haocore Mix).SULLIMaxi.0", {3: (1000000, 100.0, True)}),
+    song("Stasis.Maozon.0", {1: (1000000, 100.0, True), 4: (654321, 81.25, False)}),
+]
+# 真实存档有两百多首歌，歌曲数的 varint 占两个字节。
+SONGS += [song("Song{:03}.Fixture.0".format(n), {0: (900000 + n, 90.0, False)}) for n in range(126)]
+
+
+def write_cloud_save():
+    record = varint(len(SONGS)) + b"".join(SONGS)
+    entries = [
+        ("gameKey", encrypt(3, bytes(16))),
+        ("gameProgress", encrypt(4, bytes(32))),
+        ("gameRecord", encrypt(1, record)),
+        ("settings", encrypt(1, bytes(24))),
+        ("user", encrypt(1, string("anonymous") + bytes(8))),
+    ]
+    with zipfile.ZipFile("cloud.save", "w", zipfile.ZIP_DEFLATED) as archive:
+        for name, data in entries:
+            # 固定的时间，重新生成时文件不变。
+            info = zipfile.ZipInfo(name, date_time=(2024, 1, 1, 0, 0, 0))
+            archive.writestr(info, data, zipfile.ZIP_DEFLATED)
+
+
+SAVE = """{"gameRecord":{"Rrhar'il.Team Grimoire.0":[null,null,{"score":990000,"acc":99.1,"fc":true}],
+"Glaciaxion.SunsetRay.0":[{"score":1000000,"acc":100.0,"fc":true}]},
+"saveInfo":{"summary":{"rankingScore":14.5,"gameVersion":90}}}
+"""
+
+
+def write_encodings():
+    """ 同一份 save.json 的三种编码，都带字节顺序标记 """
+    for name, bom, encoding in [
+        ("save_utf8_bom.json", b"\xEF\xBB\xBF", "utf-8"),
+        ("save_utf16le.json", b"\xFF\xFE", "utf-16-le"),
+        ("save_utf16be.json", b"\xFE\xFF", "utf-16-be"),
+    ]:
+        with open(name, "wb") as f:
+            f.write(bom + SAVE.encode(encoding))
+
+
+if __name__ == "__main__":
+    write_cloud_save()
+    write_encodings()